@@ -0,0 +1,117 @@
+#![allow(non_upper_case_globals)]
+#![allow(non_camel_case_types)]
+#![allow(non_snake_case)]
+#![warn(clippy::pedantic)]
+#![warn(clippy::all)]
+#![allow(clippy::wildcard_imports)]
+#![allow(clippy::missing_panics_doc)]
+#![allow(clippy::missing_errors_doc)]
+use crate::core;
+use crate::core::{APIError, APIError::RP_OK, APIResult};
+use enum_primitive::*;
+
+// The slow analog I/O lives on the extension connector E2: four 12-bit sigma-delta DACs (0 -- 1.8V)
+// and four inputs on the Zynq's XADC (0 -- 3.5V).
+enum_from_primitive! {
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[repr(C)]
+pub enum Pin {
+        AOUT0 = 0,
+        AOUT1,
+        AOUT2,
+        AOUT3,
+        AIN0,
+        AIN1,
+        AIN2,
+        AIN3,
+}
+}
+impl std::str::FromStr for Pin {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "AOUT0" => Ok(Pin::AOUT0),
+            "AOUT1" => Ok(Pin::AOUT1),
+            "AOUT2" => Ok(Pin::AOUT2),
+            "AOUT3" => Ok(Pin::AOUT3),
+            "AIN0" => Ok(Pin::AIN0),
+            "AIN1" => Ok(Pin::AIN1),
+            "AIN2" => Ok(Pin::AIN2),
+            "AIN3" => Ok(Pin::AIN3),
+            _ => Err(()),
+        }
+    }
+}
+
+impl Pin {
+    #[inline]
+    #[must_use]
+    pub fn is_output(self) -> bool {
+        (self as u32) < (Pin::AIN0 as u32)
+    }
+}
+
+#[derive(Debug)]
+pub struct AnalogPin {
+    _intern: (),
+}
+
+impl AnalogPin {
+    pub(crate) fn init() -> Self {
+        AnalogPin { _intern: () }
+    }
+
+    /// Sets all four analog outputs back to 0V.
+    pub fn reset(&mut self) -> APIResult<()> {
+        wrap_call!(rp_ApinReset)
+    }
+
+    /// Reads the voltage on an analog pin. For output pins, this is the value last written.
+    pub fn get_value(&mut self, pin: Pin) -> APIResult<f32> {
+        let mut value: f32 = 0.0;
+        wrap_call!(
+            rp_ApinGetValue,
+            pin as core::rp_apin_t,
+            std::ptr::addr_of_mut!(value),
+        )?;
+        Ok(value)
+    }
+
+    pub fn get_value_raw(&mut self, pin: Pin) -> APIResult<u32> {
+        let mut value: u32 = 0;
+        wrap_call!(
+            rp_ApinGetValueRaw,
+            pin as core::rp_apin_t,
+            std::ptr::addr_of_mut!(value),
+        )?;
+        Ok(value)
+    }
+
+    /// Sets the voltage on an analog output pin. Returns `RP_EPN` if `pin` is an input.
+    pub fn set_value(&mut self, pin: Pin, volts: f32) -> APIResult<()> {
+        if !pin.is_output() {
+            return Err(APIError::RP_EPN);
+        }
+        wrap_call!(rp_ApinSetValue, pin as core::rp_apin_t, volts)
+    }
+
+    pub fn set_value_raw(&mut self, pin: Pin, value: u32) -> APIResult<()> {
+        if !pin.is_output() {
+            return Err(APIError::RP_EPN);
+        }
+        wrap_call!(rp_ApinSetValueRaw, pin as core::rp_apin_t, value)
+    }
+
+    /// Returns the `(min, max)` voltage range of the given pin.
+    pub fn get_range(&mut self, pin: Pin) -> APIResult<(f32, f32)> {
+        let mut min_val: f32 = 0.0;
+        let mut max_val: f32 = 0.0;
+        wrap_call!(
+            rp_ApinGetRange,
+            pin as core::rp_apin_t,
+            std::ptr::addr_of_mut!(min_val),
+            std::ptr::addr_of_mut!(max_val),
+        )?;
+        Ok((min_val, max_val))
+    }
+}
@@ -0,0 +1,113 @@
+#![allow(non_upper_case_globals)]
+#![allow(non_camel_case_types)]
+#![allow(non_snake_case)]
+#![warn(clippy::pedantic)]
+#![warn(clippy::all)]
+#![allow(clippy::cast_precision_loss)]
+#![allow(clippy::cast_possible_truncation)]
+use crate::core;
+
+/// The EEPROM stores full-scale voltages as fixed-point numbers with this many volts per 2^32.
+const FULL_SCALE_UNITS_V: f64 = 100.0;
+
+#[must_use]
+pub fn full_scale_to_volts(full_scale: u32) -> f32 {
+    (f64::from(full_scale) * FULL_SCALE_UNITS_V / (1u64 << 32) as f64) as f32
+}
+
+/// Calibration of one fast input channel. Full scale values are the voltage corresponding to a
+/// full-scale ADC reading with the jumpers in the LV / HV position; offsets are in ADC counts.
+#[derive(Debug, Clone, Copy)]
+pub struct FrontEndCalibration {
+    pub full_scale_lv_v: f32,
+    pub full_scale_hv_v: f32,
+    pub offset_lv: i32,
+    pub offset_hv: i32,
+}
+
+/// Calibration of one fast output channel. The offset is in DAC counts.
+#[derive(Debug, Clone, Copy)]
+pub struct BackEndCalibration {
+    pub full_scale_v: f32,
+    pub offset: i32,
+}
+
+/// Safe copy of the Red Pitaya's factory/user calibration parameters, as cached by the API
+/// from the EEPROM at initialization.
+#[derive(Debug, Clone, Copy)]
+pub struct Calibration {
+    pub front_end: [FrontEndCalibration; 2],
+    pub back_end: [BackEndCalibration; 2],
+}
+
+impl Calibration {
+    #[must_use]
+    pub fn front_end(&self, ch: core::Channel) -> &FrontEndCalibration {
+        &self.front_end[ch as usize]
+    }
+
+    #[must_use]
+    pub fn back_end(&self, ch: core::Channel) -> &BackEndCalibration {
+        &self.back_end[ch as usize]
+    }
+}
+
+impl From<core::rp_calib_params_t> for Calibration {
+    fn from(raw: core::rp_calib_params_t) -> Self {
+        Calibration {
+            front_end: [
+                FrontEndCalibration {
+                    full_scale_lv_v: full_scale_to_volts(raw.fe_ch1_fs_g_lo),
+                    full_scale_hv_v: full_scale_to_volts(raw.fe_ch1_fs_g_hi),
+                    offset_lv: raw.fe_ch1_lo_offs,
+                    offset_hv: raw.fe_ch1_hi_offs,
+                },
+                FrontEndCalibration {
+                    full_scale_lv_v: full_scale_to_volts(raw.fe_ch2_fs_g_lo),
+                    full_scale_hv_v: full_scale_to_volts(raw.fe_ch2_fs_g_hi),
+                    offset_lv: raw.fe_ch2_lo_offs,
+                    offset_hv: raw.fe_ch2_hi_offs,
+                },
+            ],
+            back_end: [
+                BackEndCalibration {
+                    full_scale_v: full_scale_to_volts(raw.be_ch1_fs),
+                    offset: raw.be_ch1_dc_offs,
+                },
+                BackEndCalibration {
+                    full_scale_v: full_scale_to_volts(raw.be_ch2_fs),
+                    offset: raw.be_ch2_dc_offs,
+                },
+            ],
+        }
+    }
+}
+
+impl std::fmt::Display for Calibration {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (i, fe) in self.front_end.iter().enumerate() {
+            write!(
+                f,
+                "IN{}: LV fs {:.4} V offs {}, HV fs {:.4} V offs {}; ",
+                i + 1,
+                fe.full_scale_lv_v,
+                fe.offset_lv,
+                fe.full_scale_hv_v,
+                fe.offset_hv
+            )?;
+        }
+        for (i, be) in self.back_end.iter().enumerate() {
+            write!(
+                f,
+                "OUT{}: fs {:.4} V offs {}",
+                i + 1,
+                be.full_scale_v,
+                be.offset
+            )?;
+            if i == 0 {
+                write!(f, "; ")?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -85,7 +85,7 @@ pub enum APIError {
 pub type APIResult<T> = Result<T, APIError>;
 
 enum_from_primitive! {
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 #[repr(C)]
 pub enum Channel {
     CH_1 = 0,
@@ -13,7 +13,7 @@ use enum_primitive::*;
 // use std::mem::MaybeUninit;
 
 enum_from_primitive! {
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 #[repr(C)]
 pub enum Pin {
         LED_0 = 0,
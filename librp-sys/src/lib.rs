@@ -3,9 +3,12 @@
 
 #[macro_use]
 pub mod core;
+pub mod analog;
+pub mod calibration;
 pub mod dpin;
 pub mod generator;
 pub mod oscilloscope;
 pub mod pitaya;
+pub mod traces;
 
 pub use pitaya::Pitaya;
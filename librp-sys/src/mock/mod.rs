@@ -21,6 +21,8 @@ macro_rules! fn_ok {
 }
 
 use std::f32::consts::PI;
+use std::ptr::addr_of_mut;
+use std::sync::Mutex;
 use std::time::Instant;
 
 use lazy_static::lazy_static;
@@ -40,6 +42,51 @@ pub fn core_mock_init() {
 fn_ok!(rp_InitReset, (reset: bool));
 fn_ok!(rp_Release);
 
+pub unsafe fn rp_GetVersion() -> *const ::std::os::raw::c_char {
+    b"mock\0".as_ptr().cast()
+}
+
+pub unsafe fn rp_IdGetID(id: *mut u32) -> ::std::os::raw::c_int {
+    *id = 0;
+    APIError::RP_OK as ::std::os::raw::c_int
+}
+pub unsafe fn rp_IdGetDNA(dna: *mut u64) -> ::std::os::raw::c_int {
+    *dna = 0x0012_3456_789a_bcde;
+    APIError::RP_OK as ::std::os::raw::c_int
+}
+
+#[derive(Debug, Copy, Clone, Default)]
+#[repr(C)]
+pub struct rp_calib_params_t {
+    pub fe_ch1_fs_g_hi: u32,
+    pub fe_ch2_fs_g_hi: u32,
+    pub fe_ch1_fs_g_lo: u32,
+    pub fe_ch2_fs_g_lo: u32,
+    pub fe_ch1_lo_offs: i32,
+    pub fe_ch2_lo_offs: i32,
+    pub be_ch1_fs: u32,
+    pub be_ch2_fs: u32,
+    pub be_ch1_dc_offs: i32,
+    pub be_ch2_dc_offs: i32,
+    pub magic: u32,
+    pub fe_ch1_hi_offs: i32,
+    pub fe_ch2_hi_offs: i32,
+}
+
+// nominal values: 1V full scale on LV and the outputs, 20V full scale on HV, no offsets. Full
+// scale values are stored in units of 100V / 2^32.
+pub unsafe fn rp_GetCalibrationSettings() -> rp_calib_params_t {
+    rp_calib_params_t {
+        fe_ch1_fs_g_hi: 858_993_459,
+        fe_ch2_fs_g_hi: 858_993_459,
+        fe_ch1_fs_g_lo: 42_949_673,
+        fe_ch2_fs_g_lo: 42_949_673,
+        be_ch1_fs: 42_949_673,
+        be_ch2_fs: 42_949_673,
+        ..Default::default()
+    }
+}
+
 pub type rp_channel_t = ::std::os::raw::c_uint;
 pub type rp_waveform_t = ::std::os::raw::c_uint;
 pub type rp_gen_mode_t = ::std::os::raw::c_uint;
@@ -106,6 +153,70 @@ pub unsafe fn rp_DpinGetState(pin: rp_dpin_t, state: *mut rp_pinState_t) -> ::st
     APIError::RP_OK as ::std::os::raw::c_int
 }
 
+pub type rp_apin_t = ::std::os::raw::c_uint;
+
+// analog outputs hold whatever was last written; analog inputs read back as 0V
+static mut APIN_VALUES: [f32; 8] = [0.0; 8];
+
+pub unsafe fn rp_ApinReset() -> ::std::os::raw::c_int {
+    APIN_VALUES[..4].fill(0.0);
+    if cfg!(feature = "no_api_loud") {
+        println!("[{}] rp_ApinReset", API_START_TIME.elapsed().as_secs_f32());
+    }
+    APIError::RP_OK as ::std::os::raw::c_int
+}
+pub unsafe fn rp_ApinGetValue(pin: rp_apin_t, value: *mut f32) -> ::std::os::raw::c_int {
+    *value = APIN_VALUES[pin as usize];
+    if cfg!(feature = "no_api_loud") {
+        println!(
+            "[{}] rp_ApinGetValue (pin = {:?})",
+            API_START_TIME.elapsed().as_secs_f32(),
+            pin
+        );
+    }
+    APIError::RP_OK as ::std::os::raw::c_int
+}
+pub unsafe fn rp_ApinGetValueRaw(pin: rp_apin_t, value: *mut u32) -> ::std::os::raw::c_int {
+    // 12-bit converters on both the inputs and outputs
+    let full_scale = if pin < 4 { 1.8 } else { 3.5 };
+    *value = (APIN_VALUES[pin as usize] / full_scale * 4095.0) as u32;
+    if cfg!(feature = "no_api_loud") {
+        println!(
+            "[{}] rp_ApinGetValueRaw (pin = {:?})",
+            API_START_TIME.elapsed().as_secs_f32(),
+            pin
+        );
+    }
+    APIError::RP_OK as ::std::os::raw::c_int
+}
+pub unsafe fn rp_ApinSetValue(pin: rp_apin_t, value: f32) -> ::std::os::raw::c_int {
+    if pin >= 4 {
+        return APIError::RP_EPN as ::std::os::raw::c_int;
+    }
+    APIN_VALUES[pin as usize] = value.clamp(0.0, 1.8);
+    if cfg!(feature = "no_api_loud") {
+        println!(
+            "[{}] rp_ApinSetValue (pin = {:?})(value = {:?})",
+            API_START_TIME.elapsed().as_secs_f32(),
+            pin,
+            value
+        );
+    }
+    APIError::RP_OK as ::std::os::raw::c_int
+}
+pub unsafe fn rp_ApinSetValueRaw(pin: rp_apin_t, value: u32) -> ::std::os::raw::c_int {
+    rp_ApinSetValue(pin, value as f32 * 1.8 / 4095.0)
+}
+pub unsafe fn rp_ApinGetRange(
+    pin: rp_apin_t,
+    min_val: *mut f32,
+    max_val: *mut f32,
+) -> ::std::os::raw::c_int {
+    *min_val = 0.0;
+    *max_val = if pin < 4 { 1.8 } else { 3.5 };
+    APIError::RP_OK as ::std::os::raw::c_int
+}
+
 pub type rp_acq_trig_src_t = ::std::os::raw::c_uint;
 pub type rp_acq_trig_state_t = ::std::os::raw::c_uint;
 
@@ -116,7 +227,32 @@ pub const ADC_SAMPLE_RATE: f64 = 125000000.0;
 fn_ok!(rp_AcqSetTriggerSrc, (src: rp_acq_trig_src_t));
 fn_ok!(rp_AcqSetDecimationFactor, (decimation: u32));
 fn_ok!(rp_AcqSetTriggerDelay, (decimated_data_num: i32));
-fn_ok!(rp_AcqStart);
+
+// recorded acquisitions to play back, and the next one to play
+static REPLAY: Mutex<(Vec<crate::traces::Record>, usize)> = Mutex::new((Vec::new(), 0));
+
+/// Play back `records` in turn, one per acquisition, in place of the synthetic fringes.
+pub fn mock_set_replay(records: Vec<crate::traces::Record>) {
+    *REPLAY.lock().unwrap() = (records, 0);
+}
+
+pub unsafe fn rp_AcqStart() -> ::std::os::raw::c_int {
+    if cfg!(feature = "no_api_loud") {
+        println!("[{}] rp_AcqStart", API_START_TIME.elapsed().as_secs_f32());
+    }
+    let mut replay = REPLAY.lock().unwrap();
+    let (records, next) = &mut *replay;
+    if let Some([a, b]) = records.get(*next) {
+        // samples are read from just after the write pointer, which is always 0 here
+        let (buff_a, buff_b) = (&mut *addr_of_mut!(BUFF_A), &mut *addr_of_mut!(BUFF_B));
+        for i in 0..16384 {
+            buff_a[(i + 1) % 16384] = a[i];
+            buff_b[(i + 1) % 16384] = b[i];
+        }
+        *next = (*next + 1) % records.len();
+    }
+    APIError::RP_OK as ::std::os::raw::c_int
+}
 fn_ok!(rp_AcqStop);
 
 pub unsafe fn rp_AcqGetTriggerState(state: *mut rp_acq_trig_state_t) -> ::std::os::raw::c_int {
@@ -6,7 +6,9 @@
 #![allow(clippy::unused_self)]
 #![allow(clippy::missing_panics_doc)]
 #![allow(clippy::missing_errors_doc)]
+#![allow(clippy::cast_precision_loss)]
 
+use crate::calibration::FrontEndCalibration;
 use crate::core;
 use crate::core::{APIError, APIError::RP_OK, APIResult, Channel};
 use enum_primitive::*;
@@ -49,7 +51,209 @@ pub enum TrigSrc {
 }
 }
 
+/// Position of the input jumpers: LV is +-1V full scale, HV is +-20V full scale.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum InputGain {
+    LV,
+    HV,
+}
+
+impl std::str::FromStr for InputGain {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "LV" => Ok(InputGain::LV),
+            "HV" => Ok(InputGain::HV),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Units of the floating-point data the scope hands back: either the sign-extended ADC counts,
+/// or volts at the input connector (after calibration, jumper gain and external attenuation).
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ScopeUnits {
+    Raw,
+    Volts,
+}
+
+impl std::str::FromStr for ScopeUnits {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "raw" => Ok(ScopeUnits::Raw),
+            "volts" => Ok(ScopeUnits::Volts),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Linear map from (sign-extended) ADC counts to volts for a single channel.
+#[derive(Debug, Copy, Clone)]
+pub struct ChannelScaling {
+    pub volts_per_count: f32,
+    pub offset_counts: i32,
+}
+
+impl ChannelScaling {
+    /// Nominal scaling, ignoring calibration: full scale of 1V (LV) or 20V (HV) over 2^13 counts.
+    #[must_use]
+    pub fn nominal(gain: InputGain) -> Self {
+        let full_scale = match gain {
+            InputGain::LV => 1.0,
+            InputGain::HV => 20.0,
+        };
+        ChannelScaling {
+            volts_per_count: full_scale / ADC_HALF_SCALE,
+            offset_counts: 0,
+        }
+    }
+
+    /// `attenuation` is any external attenuation in front of the input, e.g. 10.0 for a 10x probe.
+    #[must_use]
+    pub fn calibrated(calib: &FrontEndCalibration, gain: InputGain, attenuation: f32) -> Self {
+        let (full_scale, offset_counts) = match gain {
+            InputGain::LV => (calib.full_scale_lv_v, calib.offset_lv),
+            InputGain::HV => (calib.full_scale_hv_v, calib.offset_hv),
+        };
+        ChannelScaling {
+            volts_per_count: full_scale * attenuation / ADC_HALF_SCALE,
+            offset_counts,
+        }
+    }
+
+    #[inline]
+    #[must_use]
+    pub fn to_volts(&self, raw: u32) -> f32 {
+        (sign_extend(raw) + self.offset_counts) as f32 * self.volts_per_count
+    }
+}
+
+// The ADC is 14 bits, two's complement, so full scale corresponds to 2^13 counts
+const ADC_BITS: u32 = 14;
+const ADC_HALF_SCALE: f32 = (1 << (ADC_BITS - 1)) as f32;
+
+/// The acquisition buffers hold 14-bit two's complement samples in the low bits of each word.
+#[inline]
+#[must_use]
+pub fn sign_extend(raw: u32) -> i32 {
+    ((raw << (32 - ADC_BITS)) as i32) >> (32 - ADC_BITS)
+}
+
+/// How successive traces of a channel are combined before being handed back in the float buffers.
+/// Traces are aligned on the trigger write pointer, so averaging is coherent with the ramp.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Averaging {
+    None,
+    /// Accumulate `n` consecutive traces and hand back their mean; data is only ready once every
+    /// `n` acquisitions.
+    Block(usize),
+    /// Hand back the running mean of the last `n` traces, updated on every acquisition.
+    Sliding(usize),
+}
+
+impl Averaging {
+    /// Number of acquisitions between successive ready traces.
+    #[must_use]
+    pub fn traces_per_update(self) -> usize {
+        match self {
+            Averaging::Block(n) => n.max(1),
+            Averaging::None | Averaging::Sliding(_) => 1,
+        }
+    }
+}
+
 #[derive(Debug)]
+struct TraceAverager {
+    mode: Averaging,
+    sum: Vec<f32>,
+    history: Vec<Vec<f32>>,
+    next: usize,
+    count: usize,
+}
+
+impl TraceAverager {
+    fn new(mode: Averaging) -> Self {
+        TraceAverager {
+            mode,
+            sum: Vec::new(),
+            history: Vec::new(),
+            next: 0,
+            count: 0,
+        }
+    }
+
+    fn reset(&mut self) {
+        self.sum.clear();
+        self.history.clear();
+        self.next = 0;
+        self.count = 0;
+    }
+
+    /// Fold the newest trace in. Returns true if `trace` now holds an averaged trace ready to be
+    /// used; otherwise its contents are partial and should be ignored.
+    fn push(&mut self, trace: &mut [f32]) -> bool {
+        if self.sum.len() != trace.len() {
+            self.reset();
+            self.sum.resize(trace.len(), 0.0);
+        }
+        match self.mode {
+            Averaging::None => true,
+            Averaging::Block(n) => {
+                for (s, x) in self.sum.iter_mut().zip(trace.iter()) {
+                    *s += x;
+                }
+                self.count += 1;
+                if self.count < n {
+                    return false;
+                }
+                let norm = 1.0 / self.count as f32;
+                for (s, x) in self.sum.iter_mut().zip(trace.iter_mut()) {
+                    *x = *s * norm;
+                    *s = 0.0;
+                }
+                self.count = 0;
+                true
+            }
+            Averaging::Sliding(n) => {
+                let n = n.max(1);
+                if self.history.len() < n {
+                    self.history.push(trace.to_vec());
+                } else {
+                    for (s, x) in self.sum.iter_mut().zip(self.history[self.next].iter()) {
+                        *s -= x;
+                    }
+                    self.history[self.next].copy_from_slice(trace);
+                }
+                for (s, x) in self.sum.iter_mut().zip(trace.iter()) {
+                    *s += x;
+                }
+                self.next = (self.next + 1) % n;
+                if self.next == 0 {
+                    // recompute the sum from scratch once per pass so rounding errors from the
+                    // running subtraction can't accumulate
+                    self.sum.iter_mut().for_each(|s| *s = 0.0);
+                    for past in &self.history {
+                        for (s, x) in self.sum.iter_mut().zip(past.iter()) {
+                            *s += x;
+                        }
+                    }
+                }
+                let norm = 1.0 / self.history.len() as f32;
+                for (s, x) in self.sum.iter().zip(trace.iter_mut()) {
+                    *x = s * norm;
+                }
+                true
+            }
+        }
+    }
+}
+
+/// Region of interest within the acquisition buffer:
+/// - Not the first ``skip_start`` points
+/// - Not the last ``skip_end`` points
+/// - Within that region, only every ``skip_rate``-th point
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub struct ScopeRegion {
     skip_start: usize,
     skip_end: usize,
@@ -57,6 +261,41 @@ pub struct ScopeRegion {
     num_points: usize,
 }
 
+impl ScopeRegion {
+    /// Builds a region, clamping the parameters so the region is never empty.
+    #[must_use]
+    pub fn new(skip_start: usize, skip_end: usize, skip_rate: usize) -> Self {
+        let start_clamped = skip_start.clamp(0, 16383);
+        let end_clamped = skip_end.clamp(0, 16383 - start_clamped);
+        let rate_clamped = skip_rate.clamp(1, (16383 - start_clamped - end_clamped).max(1));
+        let num_points =
+            ((BUFF_SIZE - start_clamped - end_clamped) + rate_clamped - 1) / rate_clamped;
+        ScopeRegion {
+            skip_start: start_clamped,
+            skip_end: end_clamped,
+            skip_rate: rate_clamped,
+            num_points,
+        }
+    }
+
+    #[inline]
+    #[must_use]
+    pub fn num_points(&self) -> usize {
+        self.num_points
+    }
+
+    #[inline]
+    #[must_use]
+    pub fn skip_rate(&self) -> usize {
+        self.skip_rate
+    }
+
+    /// Offsets (from the trigger) of the samples in this region.
+    fn indices(&self) -> std::iter::StepBy<std::ops::Range<usize>> {
+        (self.skip_start..(BUFF_SIZE - self.skip_end)).step_by(self.skip_rate)
+    }
+}
+
 #[derive(Debug)]
 pub struct Oscilloscope {
     chA_buff_raw: *const u32,
@@ -67,7 +306,16 @@ pub struct Oscilloscope {
     // arrays of a FULL waveform, as the raw u32, for caching a waveform to send over a socket
     pub chA_last_waveform: Vec<u32>,
     pub chB_last_waveform: Vec<u32>,
-    region: ScopeRegion,
+    // the falling segment of the ramp, acquired separately, culled to ``falling_region`` and
+    // reversed so that it runs in the same direction as the rising segment
+    pub chA_buff_falling: Vec<f32>,
+    pub chB_buff_falling: Vec<f32>,
+    region: [ScopeRegion; 2],
+    falling_region: [ScopeRegion; 2],
+    units: ScopeUnits,
+    scaling: [ChannelScaling; 2],
+    averaging: [TraceAverager; 2],
+    ready: [bool; 2],
 }
 
 /// # Errors
@@ -84,36 +332,139 @@ impl Oscilloscope {
             chB_buff_float: Vec::with_capacity(BUFF_SIZE),
             chA_last_waveform: Vec::with_capacity(BUFF_SIZE),
             chB_last_waveform: Vec::with_capacity(BUFF_SIZE),
-            region: ScopeRegion {
-                skip_start: 0,
-                skip_end: 0,
-                skip_rate: 1,
-                num_points: 16834,
-            },
+            chA_buff_falling: Vec::new(),
+            chB_buff_falling: Vec::new(),
+            region: [ScopeRegion::new(0, 0, 1); 2],
+            falling_region: [ScopeRegion::new(0, 0, 1); 2],
+            units: ScopeUnits::Raw,
+            scaling: [ChannelScaling::nominal(InputGain::LV); 2],
+            averaging: [
+                TraceAverager::new(Averaging::None),
+                TraceAverager::new(Averaging::None),
+            ],
+            ready: [false; 2],
+        }
+    }
+
+    /// Set how successive traces on channel `ch` are combined; see `Averaging`. Discards any
+    /// partially accumulated traces.
+    pub fn set_averaging(&mut self, ch: Channel, averaging: Averaging) {
+        self.averaging[ch as usize] = TraceAverager::new(averaging);
+        self.ready[ch as usize] = false;
+    }
+
+    #[must_use]
+    pub fn averaging(&self, ch: Channel) -> Averaging {
+        self.averaging[ch as usize].mode
+    }
+
+    /// True if the last call to `update_scope_data_both` left a complete (averaged) trace for
+    /// channel `ch` in its float buffer.
+    #[inline]
+    #[must_use]
+    pub fn is_ready(&self, ch: Channel) -> bool {
+        self.ready[ch as usize]
+    }
+
+    /// Discard any partly averaged traces, e.g. after a break in acquisition.
+    pub fn reset_averaging(&mut self) {
+        for avg in &mut self.averaging {
+            avg.reset();
         }
+        self.ready = [false; 2];
+    }
+
+    /// Choose whether `chA_buff_float`/`chB_buff_float` (and so the fits) and `write_waveform`
+    /// hold raw ADC counts or calibrated volts. `write_raw_waveform` always gives raw ADC words.
+    pub fn set_units(&mut self, units: ScopeUnits) {
+        self.units = units;
+        self.reset_averaging();
+    }
+
+    #[must_use]
+    pub fn units(&self) -> ScopeUnits {
+        self.units
     }
 
-    /// Set the region-of-interest for this scope. When grabbing data from the scope,
-    /// it will return a vector of the data in the acquisition buffer, but
+    pub fn set_scaling(&mut self, ch: Channel, scaling: ChannelScaling) {
+        self.scaling[ch as usize] = scaling;
+        self.reset_averaging();
+    }
+
+    #[must_use]
+    pub fn scaling(&self, ch: Channel) -> ChannelScaling {
+        self.scaling[ch as usize]
+    }
+
+    /// Convert a raw word from the acquisition buffer of channel `ch` into the configured units.
+    #[inline]
+    #[must_use]
+    pub fn convert(&self, ch: Channel, raw: u32) -> f32 {
+        match self.units {
+            ScopeUnits::Raw => sign_extend(raw) as f32,
+            ScopeUnits::Volts => self.scaling[ch as usize].to_volts(raw),
+        }
+    }
+
+    /// Set the region-of-interest for both channels of this scope. When grabbing data from the
+    /// scope, it will return a vector of the data in the acquisition buffer, but
     /// - Not the first ``skip_start`` points
     /// - Not the last ``skip_end`` points
     /// - Within that region, only every ``skip_rate``-th point
     pub fn set_roi(&mut self, skip_start: usize, skip_end: usize, skip_rate: usize) {
-        let start_clamped = skip_start.clamp(0, 16383);
-        let end_clamped = skip_end.clamp(0, 16383 - skip_start);
-        let rate_clamped = skip_rate.clamp(1, 16383 - start_clamped - end_clamped);
-        let num_points =
-            ((BUFF_SIZE - start_clamped - end_clamped) + rate_clamped - 1) / rate_clamped;
-        self.chA_buff_float = Vec::new();
-        self.chA_buff_float.reserve_exact(num_points);
-        self.chB_buff_float = Vec::new();
-        self.chB_buff_float.reserve_exact(num_points);
-        self.region = ScopeRegion {
-            skip_start: start_clamped,
-            skip_end: end_clamped,
-            skip_rate: rate_clamped,
-            num_points,
-        }
+        self.set_roi_channel(Channel::CH_1, skip_start, skip_end, skip_rate);
+        self.set_roi_channel(Channel::CH_2, skip_start, skip_end, skip_rate);
+    }
+
+    /// Set the region-of-interest of a single channel, e.g. so that lasers with very different
+    /// fringe counts can each be fit over their own region. See `set_roi`.
+    pub fn set_roi_channel(
+        &mut self,
+        ch: Channel,
+        skip_start: usize,
+        skip_end: usize,
+        skip_rate: usize,
+    ) {
+        let region = ScopeRegion::new(skip_start, skip_end, skip_rate);
+        let buff = match ch {
+            Channel::CH_1 => &mut self.chA_buff_float,
+            Channel::CH_2 => &mut self.chB_buff_float,
+        };
+        *buff = Vec::new();
+        buff.reserve_exact(region.num_points);
+        self.region[ch as usize] = region;
+        self.averaging[ch as usize].reset();
+        self.ready[ch as usize] = false;
+    }
+
+    #[must_use]
+    pub fn roi(&self, ch: Channel) -> ScopeRegion {
+        self.region[ch as usize]
+    }
+
+    /// Set the region-of-interest used by `update_scope_data_falling` for channel `ch`. Offsets
+    /// are counted from the end of the rising segment, i.e. `BUFF_SIZE` samples after the
+    /// trigger, before reversal.
+    pub fn set_roi_falling_channel(
+        &mut self,
+        ch: Channel,
+        skip_start: usize,
+        skip_end: usize,
+        skip_rate: usize,
+    ) {
+        let region = ScopeRegion::new(skip_start, skip_end, skip_rate);
+        let buff = match ch {
+            Channel::CH_1 => &mut self.chA_buff_falling,
+            Channel::CH_2 => &mut self.chB_buff_falling,
+        };
+        *buff = Vec::new();
+        buff.reserve_exact(region.num_points);
+        self.falling_region[ch as usize] = region;
+    }
+
+    #[must_use]
+    pub fn roi_falling(&self, ch: Channel) -> ScopeRegion {
+        self.falling_region[ch as usize]
     }
 
     pub fn set_decimation(&mut self, dec: u32) -> APIResult<()> {
@@ -173,7 +524,7 @@ impl Oscilloscope {
     /// `self`'s configured ROI. NOTE: allocates a pair of vectors
     #[allow(clippy::unnecessary_cast)]
     pub fn get_scope_data_both(&mut self) -> APIResult<(Vec<u32>, Vec<u32>)> {
-        // returns owned vectors of the data in the region of interest described by self.region.
+        // returns owned vectors of the data in the regions of interest described by self.region.
         // The API has functions for this, but only for copying the whole acq buffer, which is
         // slow, presumably because of memory bandwidth limitations. If we only use part of the
         // buffer, though, it makes more sense to only copy those parts of it.
@@ -182,17 +533,17 @@ impl Oscilloscope {
         // do a single read from the FPGA registers of the data we need, and then we can cache
         // those vectors while we do math on them.
         let index = self.get_write_index_at_trigger()? as isize;
-        let mut ret_a = Vec::with_capacity(self.region.num_points);
-        let mut ret_b = Vec::with_capacity(self.region.num_points);
-        for i in (self.region.skip_start..(BUFF_SIZE - self.region.skip_end))
-            .step_by(self.region.skip_rate)
-        {
+        let mut ret_a = Vec::with_capacity(self.region[0].num_points);
+        let mut ret_b = Vec::with_capacity(self.region[1].num_points);
+        for i in self.region[0].indices() {
             ret_a.push(unsafe {
                 read_volatile(
                     self.chA_buff_raw
                         .offset((index.wrapping_add(i as isize + 1)) as isize & BUFF_MASK as isize),
                 )
             });
+        }
+        for i in self.region[1].indices() {
             ret_b.push(unsafe {
                 read_volatile(
                     self.chB_buff_raw
@@ -205,34 +556,83 @@ impl Oscilloscope {
 
     /// updates the `Oscilloscope`'s internal buffers with most recent scope data.
     /// Provided as an alternative to `get_scope_data_both` that avoids heap allocation.
+    /// If averaging is enabled on a channel, its buffer holds the averaged trace, and `is_ready`
+    /// reports whether that trace is complete.
     #[allow(clippy::cast_precision_loss)]
     #[allow(clippy::unnecessary_cast)]
     pub fn update_scope_data_both(&mut self) -> APIResult<()> {
         let index = self.get_write_index_at_trigger()? as isize;
 
-        self.chA_buff_float.clear();
-        self.chB_buff_float.clear();
-        self.chA_buff_float.reserve_exact(self.region.num_points);
-        self.chB_buff_float.reserve_exact(self.region.num_points);
+        // taken out of `self` while they're filled, so that `convert` can borrow it
+        let mut buff_a = std::mem::take(&mut self.chA_buff_float);
+        let mut buff_b = std::mem::take(&mut self.chB_buff_float);
+        buff_a.clear();
+        buff_b.clear();
+        buff_a.reserve_exact(self.region[0].num_points);
+        buff_b.reserve_exact(self.region[1].num_points);
 
-        let region_iter = (self.region.skip_start..(BUFF_SIZE - self.region.skip_end))
-            .step_by(self.region.skip_rate);
-        self.chA_buff_float.extend(region_iter.map(|i| unsafe {
-            read_volatile(
-                self.chA_buff_raw
-                    .offset((index.wrapping_add(i as isize + 1)) as isize & BUFF_MASK as isize),
-            ) as f32
+        buff_a.extend(self.region[0].indices().map(|i| {
+            self.convert(Channel::CH_1, unsafe {
+                read_volatile(
+                    self.chA_buff_raw
+                        .offset((index.wrapping_add(i as isize + 1)) as isize & BUFF_MASK as isize),
+                )
+            })
+        }));
+        buff_b.extend(self.region[1].indices().map(|i| {
+            self.convert(Channel::CH_2, unsafe {
+                read_volatile(
+                    self.chB_buff_raw
+                        .offset((index.wrapping_add(i as isize + 1)) as isize & BUFF_MASK as isize),
+                )
+            })
         }));
 
-        let region_iter = (self.region.skip_start..(BUFF_SIZE - self.region.skip_end))
-            .step_by(self.region.skip_rate as usize);
-        self.chB_buff_float.extend(region_iter.map(|i| unsafe {
-            read_volatile(
-                self.chB_buff_raw
-                    .offset((index.wrapping_add(i as isize + 1)) as isize & BUFF_MASK as isize),
-            ) as f32
+        self.ready = [
+            self.averaging[0].push(&mut buff_a),
+            self.averaging[1].push(&mut buff_b),
+        ];
+        self.chA_buff_float = buff_a;
+        self.chB_buff_float = buff_b;
+        Ok(())
+    }
+
+    /// Like `update_scope_data_both`, but for the falling segment of the ramp, from the same
+    /// acquisition once it has carried on past the rising segment (see `set_trigger_delay`): the
+    /// falling segment wraps around the buffer, overwriting the start of the rise. Fills
+    /// `chA_buff_falling`/`chB_buff_falling` from the falling regions of interest, in reverse
+    /// order, so that the data runs from low to high ramp voltage just like the rising segment.
+    /// Averaging is not applied to the falling segment.
+    #[allow(clippy::unnecessary_cast)]
+    pub fn update_scope_data_falling(&mut self) -> APIResult<()> {
+        let index = self.get_write_index_at_trigger()? as isize;
+
+        let mut buff_a = std::mem::take(&mut self.chA_buff_falling);
+        let mut buff_b = std::mem::take(&mut self.chB_buff_falling);
+        buff_a.clear();
+        buff_b.clear();
+        buff_a.reserve_exact(self.falling_region[0].num_points);
+        buff_b.reserve_exact(self.falling_region[1].num_points);
+
+        buff_a.extend(self.falling_region[0].indices().rev().map(|i| {
+            self.convert(Channel::CH_1, unsafe {
+                read_volatile(
+                    self.chA_buff_raw
+                        .offset((index.wrapping_add(i as isize + 1)) as isize & BUFF_MASK as isize),
+                )
+            })
+        }));
+        buff_b.extend(self.falling_region[1].indices().rev().map(|i| {
+            self.convert(Channel::CH_2, unsafe {
+                read_volatile(
+                    self.chB_buff_raw
+                        .offset((index.wrapping_add(i as isize + 1)) as isize & BUFF_MASK as isize),
+                )
+            })
         }));
 
+        self.chA_buff_falling = buff_a;
+        self.chB_buff_falling = buff_b;
         Ok(())
     }
 
@@ -264,6 +664,36 @@ impl Oscilloscope {
         Ok(())
     }
 
+    /// Like `write_raw_waveform`, but converts the full waveform into the configured units
+    /// (see `set_units`) as it copies.
+    #[allow(clippy::unnecessary_cast)]
+    pub fn write_waveform(&mut self, chA: &mut Vec<f32>, chB: &mut Vec<f32>) -> APIResult<()> {
+        let index = self.get_write_index_at_trigger()? as isize;
+        chA.clear();
+        chB.clear();
+        chA.reserve_exact(BUFF_SIZE);
+        chB.reserve_exact(BUFF_SIZE);
+
+        chA.extend((0..BUFF_SIZE).map(|i| {
+            self.convert(Channel::CH_1, unsafe {
+                read_volatile(
+                    self.chA_buff_raw
+                        .offset((index.wrapping_add(i as isize + 1)) as isize & BUFF_MASK as isize),
+                )
+            })
+        }));
+        chB.extend((0..BUFF_SIZE).map(|i| {
+            self.convert(Channel::CH_2, unsafe {
+                read_volatile(
+                    self.chB_buff_raw
+                        .offset((index.wrapping_add(i as isize + 1)) as isize & BUFF_MASK as isize),
+                )
+            })
+        }));
+
+        Ok(())
+    }
+
     /// # Errors
     /// If an RP API call returns a failure code, this returns Err containing the failure.
     /// # Panics
@@ -271,10 +701,9 @@ impl Oscilloscope {
     #[allow(clippy::unnecessary_cast)]
     pub fn get_scope_data_channel(&mut self, ch: Channel) -> APIResult<Vec<u32>> {
         let index = self.get_write_index_at_trigger()? as isize;
-        let mut ret = Vec::with_capacity(self.region.num_points);
-        for i in (self.region.skip_start..(BUFF_SIZE - self.region.skip_end))
-            .step_by(self.region.skip_rate as usize)
-        {
+        let region = self.region[ch as usize];
+        let mut ret = Vec::with_capacity(region.num_points);
+        for i in region.indices() {
             ret.push(unsafe {
                 read_volatile(
                     match ch {
@@ -299,3 +728,49 @@ impl Oscilloscope {
         Ok(posn)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn block_averaging() {
+        let mut avg = TraceAverager::new(Averaging::Block(2));
+        let mut trace = [1.0, 2.0];
+        assert!(!avg.push(&mut trace));
+        let mut trace = [3.0, 4.0];
+        assert!(avg.push(&mut trace));
+        assert_eq!(trace, [2.0, 3.0]);
+        // the next block starts from scratch
+        assert!(!avg.push(&mut [5.0, 5.0]));
+        let mut trace = [7.0, 9.0];
+        assert!(avg.push(&mut trace));
+        assert_eq!(trace, [6.0, 7.0]);
+
+        // a trace of another length throws away the partial block
+        assert!(!avg.push(&mut [100.0, 100.0]));
+        assert!(!avg.push(&mut [1.0, 1.0, 1.0]));
+        let mut trace = [3.0, 3.0, 3.0];
+        assert!(avg.push(&mut trace));
+        assert_eq!(trace, [2.0, 2.0, 2.0]);
+    }
+
+    #[test]
+    fn sliding_averaging() {
+        let mut avg = TraceAverager::new(Averaging::Sliding(3));
+        let mut means = Vec::new();
+        for x in [3.0, 6.0, 9.0, 12.0, 0.0, 3.0, 30.0] {
+            let mut trace = [x, -x];
+            assert!(avg.push(&mut trace));
+            assert_eq!(trace[1], -trace[0]);
+            means.push(trace[0]);
+        }
+        // the mean of what's there until the window fills, then of the last 3, across the
+        // recomputation of the sum at each wraparound
+        assert_eq!(means, [3.0, 4.5, 6.0, 9.0, 7.0, 5.0, 11.0]);
+
+        let mut trace = [1.0];
+        assert!(avg.push(&mut trace));
+        assert_eq!(trace, [1.0]);
+    }
+}
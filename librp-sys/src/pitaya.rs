@@ -3,10 +3,16 @@
 #![allow(non_snake_case)]
 #![warn(clippy::pedantic)]
 #![warn(clippy::all)]
+use crate::analog::AnalogPin;
+use crate::calibration::Calibration;
+use crate::core;
 use crate::core as rp;
+use crate::core::{APIError, APIError::RP_OK, APIResult};
+use enum_primitive::FromPrimitive;
 use crate::dpin::DigitalPin;
 use crate::generator::Generator;
 use crate::oscilloscope::Oscilloscope;
+use crate::traces::Record;
 
 #[cfg(not(any(feature = "no_api", feature = "no_api_loud")))]
 use std::process::Command;
@@ -21,9 +27,13 @@ pub struct Pitaya {
     pub scope: Oscilloscope,
     pub gen: Generator,
     pub dpin: DigitalPin,
+    pub analog: AnalogPin,
 }
 
 impl Pitaya {
+    /// Whether this build simulates the board (the ``no_api`` features) rather than driving it.
+    pub const SIMULATED: bool = cfg!(any(feature = "no_api", feature = "no_api_loud"));
+
     /// # Errors
     /// Returns ``FAILED_TO_LOAD_FPGA_IMAGE`` if the program fails to load the Red Pitaya bitmap onto
     /// the FPGA.
@@ -55,6 +65,7 @@ impl Pitaya {
                 scope: Oscilloscope::init(),
                 gen: Generator::init(),
                 dpin: DigitalPin::init(),
+                analog: AnalogPin::init(),
             }),
             err => Err(InitializationError::API_FAILED(err)),
         }
@@ -71,8 +82,92 @@ impl Pitaya {
             scope: Oscilloscope::init(),
             gen: Generator::init(),
             dpin: DigitalPin::init(),
+            analog: AnalogPin::init(),
         })
     }
+
+    /// Acquire `records` in turn, looping at the end, in place of the simulated fringes.
+    /// # Errors
+    /// Returns a message if this is a hardware build, or there are no records
+    #[cfg(not(any(feature = "no_api", feature = "no_api_loud")))]
+    #[allow(clippy::unused_self, clippy::needless_pass_by_value)]
+    pub fn replay(&mut self, _records: Vec<Record>) -> Result<(), String> {
+        Err("replaying traces needs a simulated (no_api) build".to_string())
+    }
+
+    /// Acquire `records` in turn, looping at the end, in place of the simulated fringes.
+    /// # Errors
+    /// Returns a message if this is a hardware build, or there are no records
+    #[cfg(any(feature = "no_api", feature = "no_api_loud"))]
+    #[allow(clippy::unused_self)]
+    pub fn replay(&mut self, records: Vec<Record>) -> Result<(), String> {
+        if records.is_empty() {
+            return Err("no traces to replay".to_string());
+        }
+        rp::mock_set_replay(records);
+        Ok(())
+    }
+}
+
+/// Identity of the board we're running on: model, FPGA unique DNA, and API version.
+#[derive(Debug, Clone)]
+pub struct BoardIdentity {
+    pub model_id: u32,
+    pub dna: u64,
+    pub api_version: String,
+}
+
+impl std::fmt::Display for BoardIdentity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "model {}, DNA {:#018x}, API version {}",
+            self.model_id, self.dna, self.api_version
+        )
+    }
+}
+
+impl Pitaya {
+    /// # Errors
+    /// Propagates any Red Pitaya API errors
+    pub fn model_id(&self) -> APIResult<u32> {
+        let mut id: u32 = 0;
+        wrap_call!(rp_IdGetID, std::ptr::addr_of_mut!(id))?;
+        Ok(id)
+    }
+
+    /// Unique identifier burned into the Zynq's FPGA; unlike the hostname, this can't be changed,
+    /// so it's a reliable way to tell boards apart.
+    /// # Errors
+    /// Propagates any Red Pitaya API errors
+    pub fn dna(&self) -> APIResult<u64> {
+        let mut dna: u64 = 0;
+        wrap_call!(rp_IdGetDNA, std::ptr::addr_of_mut!(dna))?;
+        Ok(dna)
+    }
+
+    #[must_use]
+    pub fn api_version(&self) -> String {
+        unsafe { std::ffi::CStr::from_ptr(rp::rp_GetVersion()) }
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    /// # Errors
+    /// Propagates any Red Pitaya API errors
+    pub fn identity(&self) -> APIResult<BoardIdentity> {
+        Ok(BoardIdentity {
+            model_id: self.model_id()?,
+            dna: self.dna()?,
+            api_version: self.api_version(),
+        })
+    }
+
+    /// The calibration parameters the API read from the EEPROM at initialization.
+    #[must_use]
+    pub fn calibration(&self) -> Calibration {
+        unsafe { rp::rp_GetCalibrationSettings() }.into()
+    }
 }
 
 impl Drop for Pitaya {
@@ -0,0 +1,104 @@
+//! Recorded acquisitions, to replay through the simulated API (see `Pitaya::replay`). A trace file
+//! is a sequence of records, one per acquisition, each holding the raw buffers of `CH_1` and then
+//! `CH_2` as given by `Oscilloscope::write_raw_waveform`: `BUFF_SIZE` little-endian `u32` words
+//! per channel, starting just after the trigger.
+
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use crate::oscilloscope::{Oscilloscope, BUFF_SIZE};
+
+/// The raw buffers of one acquisition, indexed by channel.
+pub type Record = [Vec<u32>; 2];
+
+const RECORD_BYTES: usize = 2 * BUFF_SIZE * 4;
+
+/// Split the contents of a trace file into its records.
+/// # Errors
+/// Returns a message if there are no records, or the length isn't a whole number of them
+pub fn parse(bytes: &[u8]) -> Result<Vec<Record>, String> {
+    if bytes.is_empty() || bytes.len() % RECORD_BYTES != 0 {
+        return Err(format!(
+            "{} bytes isn't a whole number of {RECORD_BYTES}-byte records",
+            bytes.len()
+        ));
+    }
+    let words = |x: &[u8]| {
+        x.chunks_exact(4)
+            .map(|w| u32::from_le_bytes([w[0], w[1], w[2], w[3]]))
+            .collect::<Vec<u32>>()
+    };
+    Ok(bytes
+        .chunks_exact(RECORD_BYTES)
+        .map(|record| {
+            let (ch1, ch2) = record.split_at(RECORD_BYTES / 2);
+            [words(ch1), words(ch2)]
+        })
+        .collect())
+}
+
+/// # Errors
+/// Returns a message if the file can't be read or isn't a trace file
+pub fn read(path: &Path) -> Result<Vec<Record>, String> {
+    let bytes = fs::read(path).map_err(|e| format!("failed to read {}: {e}", path.display()))?;
+    parse(&bytes).map_err(|e| format!("in {}: {e}", path.display()))
+}
+
+fn write_record(out: &mut impl Write, record: &Record) -> io::Result<()> {
+    for ch in record {
+        for word in ch {
+            out.write_all(&word.to_le_bytes())?;
+        }
+    }
+    Ok(())
+}
+
+/// Appends each acquisition of a scope to a trace file.
+#[derive(Debug)]
+pub struct TraceRecorder {
+    out: BufWriter<File>,
+    record: Record,
+}
+
+impl TraceRecorder {
+    /// # Errors
+    /// Returns any error in creating the file
+    pub fn create(path: &Path) -> io::Result<Self> {
+        Ok(TraceRecorder {
+            out: BufWriter::new(File::create(path)?),
+            record: [Vec::with_capacity(BUFF_SIZE), Vec::with_capacity(BUFF_SIZE)],
+        })
+    }
+
+    /// Append the scope's latest acquisition.
+    /// # Errors
+    /// Returns any error in reading the scope or writing the file
+    pub fn record(&mut self, scope: &mut Oscilloscope) -> io::Result<()> {
+        let [ch1, ch2] = &mut self.record;
+        scope
+            .write_raw_waveform(ch1, ch2)
+            .map_err(|e| io::Error::other(format!("{e:?}")))?;
+        write_record(&mut self.out, &self.record)?;
+        self.out.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn records_round_trip() {
+        let records: Vec<Record> = (0..2u32)
+            .map(|n| [vec![n; BUFF_SIZE], (0..BUFF_SIZE as u32).collect()])
+            .collect();
+        let mut bytes = Vec::new();
+        for record in &records {
+            write_record(&mut bytes, record).unwrap();
+        }
+        assert_eq!(parse(&bytes).unwrap(), records);
+        assert!(parse(&bytes[1..]).is_err());
+        assert!(parse(&[]).is_err());
+    }
+}
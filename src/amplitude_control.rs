@@ -0,0 +1,156 @@
+#![warn(clippy::pedantic)]
+#![allow(clippy::cast_precision_loss, clippy::module_name_repetitions)]
+
+use std::f32::consts::PI;
+use std::str::Split;
+
+use super::command::{arg, arg_in, CommandError, CommandResult};
+
+/// Slow outer loop on the ramp amplitude. Piezo sensitivity drifts (e.g. with temperature), which
+/// changes how many fringes of the reference laser fit in a ramp; this averages the reference
+/// laser's fitted fringe frequency over `interval_cycles` cycles, then moves the amplitude a
+/// (bounded) step towards the value that would bring it back to `target_freq`.
+#[derive(Debug)]
+pub struct AmplitudeControl {
+    pub target_freq: f32, // radians per sample, like `Laser::fringe_freq`
+    pub gain: f32,        // fraction of the estimated correction applied per update
+    pub max_step_v: f32,
+    pub min_v: f32,
+    pub max_v: f32,
+    pub interval_cycles: u32,
+    pub enabled: bool,
+    sum: f32,
+    count: u32,
+    last_measured: f32,
+}
+
+/// A change of ramp amplitude requested by `AmplitudeControl::push`.
+#[derive(Debug)]
+pub struct AmplitudeStep {
+    pub amplitude_v: f32,
+    pub measured_freq: f32,
+}
+
+impl AmplitudeControl {
+    #[must_use]
+    pub fn new(target_fringes: f32, max_step_v: f32, min_v: f32, max_v: f32) -> Self {
+        AmplitudeControl {
+            target_freq: fringes_to_freq(target_fringes),
+            gain: 0.5,
+            max_step_v,
+            min_v,
+            max_v,
+            interval_cycles: 64,
+            enabled: true,
+            sum: 0.0,
+            count: 0,
+            last_measured: f32::NAN,
+        }
+    }
+
+    /// Add one fitted fringe frequency of the reference laser, given the current amplitude. Every
+    /// `interval_cycles` measurements, returns the new amplitude to apply.
+    pub fn push(&mut self, fringe_freq: f32, amplitude_v: f32) -> Option<AmplitudeStep> {
+        if !self.enabled || !fringe_freq.is_finite() {
+            return None;
+        }
+        self.sum += fringe_freq.abs();
+        self.count += 1;
+        if self.count < self.interval_cycles.max(1) {
+            return None;
+        }
+        let measured = self.sum / self.count as f32;
+        self.sum = 0.0;
+        self.count = 0;
+        self.last_measured = measured;
+
+        // the fringe frequency is proportional to the amplitude
+        let ideal = amplitude_v * self.target_freq / measured;
+        let step = (self.gain * (ideal - amplitude_v)).clamp(-self.max_step_v, self.max_step_v);
+        if step.is_nan() {
+            return None;
+        }
+        Some(AmplitudeStep {
+            amplitude_v: (amplitude_v + step).clamp(self.min_v, self.max_v),
+            measured_freq: measured,
+        })
+    }
+
+    /// Takes a split over a string command, parses the command, executes the command, and returns
+    /// a string
+    /// # Errors
+    /// In case of an invalid command (or inability to parse a command), returns a `CommandError`
+    pub fn process_command(&mut self, cmd: Split<'_, char>) -> CommandResult {
+        let resp = match cmd.collect::<Vec<&str>>()[..] {
+            ["MODE", "SET", "ENABLE"] => {
+                self.enabled = true;
+                String::new()
+            }
+            ["MODE", "SET", "DISABLE"] => {
+                self.enabled = false;
+                self.sum = 0.0;
+                self.count = 0;
+                String::new()
+            }
+            ["MODE", "GET"] => if self.enabled { "ENABLED" } else { "DISABLED" }.to_string(),
+            ["TARGET", "SET", x] => {
+                self.target_freq = fringes_to_freq(arg::<f32>(x)?);
+                String::new()
+            }
+            ["TARGET", "GET"] => freq_to_fringes(self.target_freq).to_string(),
+            ["MEASURED", "GET"] => freq_to_fringes(self.last_measured).to_string(),
+            ["GAIN", "SET", x] => {
+                self.gain = arg_in(x, 0.0..=1.0)?;
+                String::new()
+            }
+            ["GAIN", "GET"] => self.gain.to_string(),
+            ["MAX_STEP", "SET", x] => {
+                self.max_step_v = arg::<f32>(x)?.abs();
+                String::new()
+            }
+            ["MAX_STEP", "GET"] => self.max_step_v.to_string(),
+            ["INTERVAL", "SET", x] => {
+                self.interval_cycles = arg::<u32>(x)?.max(1);
+                String::new()
+            }
+            ["INTERVAL", "GET"] => self.interval_cycles.to_string(),
+            _ => Err(CommandError::Unknown)?,
+        };
+        Ok(resp)
+    }
+}
+
+/// Number of fringes over the 16384-sample rising ramp for a given fringe frequency
+#[must_use]
+pub fn freq_to_fringes(freq: f32) -> f32 {
+    freq * 16384.0 / (2.0 * PI)
+}
+
+#[must_use]
+pub fn fringes_to_freq(fringes: f32) -> f32 {
+    fringes * 2.0 * PI / 16384.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bounded_steps() {
+        let mut ctrl = AmplitudeControl::new(10.0, 0.05, 0.5, 1.5);
+        ctrl.interval_cycles = 2;
+        ctrl.gain = 1.0;
+        // too many fringes: the amplitude should drop, but only by max_step_v
+        assert!(ctrl.push(fringes_to_freq(12.0), 1.0).is_none());
+        let step = ctrl.push(fringes_to_freq(12.0), 1.0).unwrap();
+        assert!((step.amplitude_v - 0.95).abs() < 1e-6);
+        // small correction, within the step bound
+        ctrl.push(fringes_to_freq(10.1), 1.0);
+        let step = ctrl.push(fringes_to_freq(10.1), 1.0).unwrap();
+        assert!((step.amplitude_v - 1.0 / 1.01).abs() < 1e-4);
+        // and clamped to the allowed range
+        ctrl.push(fringes_to_freq(5.0), 1.49);
+        let step = ctrl.push(fringes_to_freq(5.0), 1.49).unwrap();
+        assert!((step.amplitude_v - 1.5).abs() < 1e-6);
+    }
+}
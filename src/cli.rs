@@ -0,0 +1,168 @@
+#![warn(clippy::pedantic)]
+
+use std::path::{Path, PathBuf};
+
+use clap::{Parser, Subcommand, ValueEnum};
+
+use super::config_model::Config;
+
+#[derive(Debug, Parser)]
+#[command(about = "Transfer cavity lock for Red Pitaya boards")]
+pub struct Cli {
+    /// Config file to read [default: config.toml next to the executable]
+    #[arg(short, long, global = true)]
+    pub config: Option<PathBuf>,
+    /// Config section to use, instead of the one matching the board's DNA or hostname
+    #[arg(long, global = true)]
+    pub host: Option<String>,
+    #[arg(long, value_enum, default_value_t = LogLevel::Info, global = true)]
+    pub log_level: LogLevel,
+    /// Overrides `[general] logs_port`
+    #[arg(long)]
+    pub logs_port: Option<u16>,
+    /// Overrides `[general] command_port`
+    #[arg(long)]
+    pub command_port: Option<u16>,
+    /// Fetch the cluster config from the master board's command socket (host:port) at startup,
+    /// check it, and use it in place of the config file (which is kept as a .bak)
+    #[arg(long, value_name = "HOST:PORT")]
+    pub cluster_master: Option<String>,
+    /// Only reload the config on `CONFIG:RELOAD`, rather than whenever the file changes
+    #[arg(long)]
+    pub no_watch_config: bool,
+    /// Where the scope data comes from [default: the board, or simulated fringes in a `no_api`
+    /// build]. Whether the board is driven at all is fixed when building
+    #[arg(long, value_enum)]
+    pub backend: Option<Backend>,
+    /// Trace file to replay, as written by --record-traces
+    #[arg(long, value_name = "FILE", required_if_eq("backend", "replay"))]
+    pub traces: Option<PathBuf>,
+    /// Append every acquisition to a trace file, to replay later
+    #[arg(long, value_name = "FILE")]
+    pub record_traces: Option<PathBuf>,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Run the lock (the default)
+    Run,
+    /// Set up everything from the config without touching the hardware, and describe the result
+    CheckConfig {
+        /// Config file to check, if not given by --config
+        file: Option<PathBuf>,
+    },
+    /// Print the board's identity, calibration and config section, then exit
+    Info,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+pub enum LogLevel {
+    Error,
+    Warn,
+    Info,
+    /// Also list the lock's state periodically, even without `debug_list_freq_cycles`
+    Debug,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Backend {
+    Hardware,
+    /// Synthetic fringes from the `no_api` build of librp-sys
+    Simulation,
+    /// Recorded traces (see --traces) played through the `no_api` build of librp-sys
+    Replay,
+}
+
+impl Cli {
+    /// The config file to read: given on the command line, or `config.toml` next to the
+    /// executable at `exe`.
+    #[must_use]
+    pub fn config_path(&self, exe: &Path) -> PathBuf {
+        match (&self.command, &self.config) {
+            (Some(Command::CheckConfig { file: Some(file) }), _) | (_, Some(file)) => file.clone(),
+            _ => exe.with_file_name("config.toml"),
+        }
+    }
+
+    /// The backend asked for, or the default for this build.
+    /// # Errors
+    /// Returns a message if the build can't provide it, or --traces is given without replaying
+    pub fn backend(&self, simulated_build: bool) -> Result<Backend, String> {
+        let default = if simulated_build {
+            Backend::Simulation
+        } else {
+            Backend::Hardware
+        };
+        let backend = self.backend.unwrap_or(default);
+        match backend {
+            Backend::Hardware if simulated_build => {
+                return Err("this build simulates the board; rebuild without no_api".to_string())
+            }
+            Backend::Simulation | Backend::Replay if !simulated_build => {
+                return Err(format!(
+                    "the {backend:?} backend needs a build with librp-sys's `no_api` feature"
+                ))
+            }
+            _ => {}
+        }
+        if self.traces.is_some() && backend != Backend::Replay {
+            return Err("--traces is only used with --backend replay".to_string());
+        }
+        Ok(backend)
+    }
+
+    /// Apply the command-line overrides to the config.
+    /// # Errors
+    /// Returns a message if the overridden ports clash
+    pub fn apply_overrides(&self, cfg: &mut Config) -> Result<(), String> {
+        if let Some(port) = self.logs_port {
+            cfg.general.logs_port = port;
+        }
+        if let Some(port) = self.command_port {
+            cfg.general.command_port = port;
+        }
+        if cfg.general.logs_port == cfg.general.command_port {
+            return Err(format!(
+                "logs and command ports are both {}",
+                cfg.general.logs_port
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config_model::minimal_config;
+
+    #[test]
+    fn options_and_overrides() {
+        let exe = Path::new("/opt/rusterf/rusterf");
+        let cli = Cli::try_parse_from(["rusterf"]).unwrap();
+        assert!(cli.command.is_none());
+        assert_eq!(cli.log_level, LogLevel::Info);
+        assert_eq!(cli.config_path(exe), Path::new("/opt/rusterf/config.toml"));
+
+        let cli =
+            Cli::try_parse_from(["rusterf", "check-config", "lab.toml", "--host", "rp-2"]).unwrap();
+        assert_eq!(cli.config_path(exe), Path::new("lab.toml"));
+        assert_eq!(cli.host.as_deref(), Some("rp-2"));
+
+        assert_eq!(cli.backend(false), Ok(Backend::Hardware));
+        assert_eq!(cli.backend(true), Ok(Backend::Simulation));
+        assert!(Cli::try_parse_from(["rusterf", "--backend", "replay"]).is_err());
+        let cli =
+            Cli::try_parse_from(["rusterf", "--backend", "replay", "--traces", "a.bin"]).unwrap();
+        assert_eq!(cli.backend(true), Ok(Backend::Replay));
+        assert!(cli.backend(false).is_err());
+        let cli = Cli::try_parse_from(["rusterf", "--traces", "a.bin"]).unwrap();
+        assert!(cli.backend(true).is_err());
+
+        let cli = Cli::try_parse_from(["rusterf", "--command-port", "8080"]).unwrap();
+        let mut cfg: Config = minimal_config().parse().unwrap();
+        assert!(cli.apply_overrides(&mut cfg).is_err());
+    }
+}
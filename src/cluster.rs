@@ -0,0 +1,219 @@
+#![warn(clippy::pedantic)]
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use std::str;
+use std::time::Duration;
+
+use zeromq::prelude::*;
+
+use super::config_model::Config;
+use super::configs;
+use super::state;
+
+/// How long to wait for the master board to answer `CONFIG:GET`.
+pub const FETCH_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Problems with `cfg` as the config of a whole cluster: anything other than exactly one master
+/// board, a `number_of_pitayas` that doesn't match the board sections, and slave lasers or DNAs
+/// claimed by more than one board.
+#[must_use]
+pub fn check(cfg: &Config) -> Vec<String> {
+    let mut out = Vec::new();
+    let masters = cfg
+        .boards
+        .iter()
+        .filter(|(_, board)| board.is_master)
+        .map(|(name, _)| name.as_str())
+        .collect::<Vec<&str>>();
+    if masters.len() != 1 {
+        out.push(format!(
+            "{} boards set is_master ({}), rather than exactly one",
+            masters.len(),
+            masters.join(", ")
+        ));
+    }
+    if let Some(n) = cfg.general.number_of_pitayas {
+        if n as usize != cfg.boards.len() {
+            out.push(format!(
+                "number_of_pitayas is {n}, but there are {} board sections",
+                cfg.boards.len()
+            ));
+        }
+    }
+    let mut slave_lasers: BTreeMap<&str, Vec<&str>> = BTreeMap::new();
+    let mut dnas: BTreeMap<u64, Vec<&str>> = BTreeMap::new();
+    for (name, board) in &cfg.boards {
+        slave_lasers
+            .entry(board.slave_laser.as_str())
+            .or_default()
+            .push(name);
+        if let Some(dna) = board.dna() {
+            dnas.entry(dna).or_default().push(name);
+        }
+    }
+    for (laser, boards) in slave_lasers.iter().filter(|(_, x)| x.len() > 1) {
+        out.push(format!(
+            "{laser} is the slave laser of more than one board ({})",
+            boards.join(", ")
+        ));
+    }
+    for (dna, boards) in dnas.iter().filter(|(_, x)| x.len() > 1) {
+        out.push(format!(
+            "DNA {dna:x} is given for more than one board ({})",
+            boards.join(", ")
+        ));
+    }
+    out
+}
+
+/// Sections of `local` that differ from `master`'s. Only sections present in both are compared,
+/// since a board's own copy may leave out the others' sections.
+#[must_use]
+pub fn differences(local: &Config, master: &Config) -> Vec<String> {
+    let mut out = Vec::new();
+    for (name, differs) in [
+        ("general", local.general != master.general),
+        ("ramp", local.ramp != master.ramp),
+        ("ref_laser", local.ref_laser != master.ref_laser),
+        ("leds", local.leds != master.leds),
+    ] {
+        if differs {
+            out.push(format!("[{name}]"));
+        }
+    }
+    for (name, board) in &local.boards {
+        if master.boards.get(name).is_some_and(|x| x != board) {
+            out.push(format!("[{name}]"));
+        }
+    }
+    for (name, laser) in &local.lasers {
+        if master.lasers.get(name).is_some_and(|x| x != laser) {
+            out.push(format!("[{name}]"));
+        }
+    }
+    out
+}
+
+/// Ask the board at `master` (`host:port` of its command socket) for its config file.
+/// # Errors
+/// Returns a message if the master can't be reached, doesn't answer within `FETCH_TIMEOUT`, or
+/// sends back an error or no config
+pub async fn fetch(master: &str) -> Result<String, String> {
+    let request = async {
+        let mut sock = zeromq::ReqSocket::new();
+        sock.connect(&format!("tcp://{master}")).await?;
+        sock.send("CONFIG:GET".into()).await?;
+        sock.recv().await
+    };
+    let reply = async_std::future::timeout(FETCH_TIMEOUT, request)
+        .await
+        .map_err(|_| format!("no answer from {master} within {FETCH_TIMEOUT:?}"))?
+        .map_err(|e| format!("failed to ask {master} for its config: {e}"))?;
+    let text = reply
+        .get(0)
+        .and_then(|x| str::from_utf8(x).ok())
+        .unwrap_or_default();
+    if text.is_empty() {
+        return Err(format!("{master} sent back no config"));
+    }
+    if let Some(e) = text.strip_prefix("ERR:") {
+        return Err(format!("{master} refused to send its config: {e}"));
+    }
+    Ok(text.to_string())
+}
+
+/// Fetch the cluster config from `master`, check it, report how the config file at `path`
+/// differs from it, and replace the file with it, keeping the old one as a backup (see
+/// `state::write_atomic`). The config has to have a section for this board: `host` if given, or
+/// else the one matching `dna` or the hostname, as in `configs::board_section`. Returns what was
+/// found and done.
+/// # Errors
+/// Returns a message if the config can't be fetched, isn't valid, isn't consistent as the config
+/// of a whole cluster, or has no section for this board; the file at `path` is then left as it was
+pub async fn sync_from_master(
+    master: &str,
+    path: &Path,
+    host: Option<&str>,
+    dna: Option<u64>,
+) -> Result<Vec<String>, String> {
+    let text = fetch(master).await?;
+    let (cfg, mut out) =
+        Config::parse(&text).map_err(|e| format!("config from {master} is invalid: {e}"))?;
+    let problems = check(&cfg);
+    if !problems.is_empty() {
+        return Err(format!(
+            "config from {master} is inconsistent: {}",
+            problems.join("; ")
+        ));
+    }
+    let board = match host {
+        Some(x) => x.to_string(),
+        None => configs::board_section(&cfg, dna)?,
+    };
+    cfg.board(&board)
+        .map_err(|e| format!("config from {master} doesn't fit this board: {e}"))?;
+
+    let local = fs::read_to_string(path).ok();
+    if local.as_deref() == Some(text.as_str()) {
+        out.push(format!("{} matches {master}", path.display()));
+        return Ok(out);
+    }
+    match local.as_deref().map(Config::parse) {
+        Some(Ok((local_cfg, _))) => {
+            let differences = differences(&local_cfg, &cfg);
+            if !differences.is_empty() {
+                out.push(format!(
+                    "{} differs from {master} in {}",
+                    path.display(),
+                    differences.join(", ")
+                ));
+            }
+        }
+        Some(Err(e)) => out.push(format!("{} was invalid: {e}", path.display())),
+        None => {}
+    }
+    state::write_atomic(path, &text, true)?;
+    out.push(format!(
+        "wrote config from {master} to {}, for board [{board}]",
+        path.display()
+    ));
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config_model::{minimal_config, test_board};
+
+    #[test]
+    fn cluster_consistency() {
+        let text = minimal_config().replace("[general]\n", "[general]\nnumber_of_pitayas = 2\n");
+        let cfg: Config = text.parse().unwrap();
+        // a single board isn't a whole cluster of `number_of_pitayas = 2`
+        assert_eq!(check(&cfg).len(), 1);
+
+        // a second master, locking the same slave laser
+        let second = test_board("rp-2");
+        let cluster: Config = format!("{text}{second}").parse().unwrap();
+        let problems = check(&cluster);
+        assert_eq!(problems.len(), 2, "{problems:?}");
+        assert!(problems[0].starts_with("2 boards set is_master"));
+        assert!(problems[1].starts_with("las_1114 is the slave laser"));
+
+        let second = second
+            .replace("is_master = true", "is_master = false")
+            .replace("las_1114", "las_1550");
+        let laser = "[las_1550]\nwavelength_nm = 1550.0\ngain_p = 0.001\ngain_i = 0.003\n\
+                     gain_d = 0.0\nintegral_decay_rate = 0.85\nfeedback_max_step_size_v = 0.01\n";
+        let fixed: Config = format!("{text}{second}{laser}").parse().unwrap();
+        assert!(check(&fixed).is_empty(), "{:?}", check(&fixed));
+
+        let local: Config = text
+            .replace("amplitude_volts = 1.0", "amplitude_volts = 1.5")
+            .parse()
+            .unwrap();
+        assert_eq!(differences(&local, &fixed), ["[ramp]"]);
+    }
+}
@@ -0,0 +1,200 @@
+#![warn(clippy::pedantic)]
+#![allow(clippy::module_name_repetitions)]
+
+use std::fmt;
+use std::ops::RangeInclusive;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+/// Why a command over the command socket wasn't carried out.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CommandError {
+    /// No such command, or the wrong number of arguments for it
+    Unknown,
+    /// An argument that doesn't parse
+    BadArgument(String),
+    /// An argument that parses, but is outside the allowed range
+    OutOfRange(String),
+    /// A command that doesn't apply in the current state, e.g. for a feature that isn't configured
+    NotPermitted(String),
+    /// A valid command that failed, e.g. writing a file
+    Failed(String),
+}
+
+impl CommandError {
+    /// Status code sent back to the client; 0 is success.
+    #[must_use]
+    pub fn code(&self) -> u16 {
+        match self {
+            CommandError::Unknown => 1,
+            CommandError::BadArgument(_) => 2,
+            CommandError::OutOfRange(_) => 3,
+            CommandError::NotPermitted(_) => 4,
+            CommandError::Failed(_) => 5,
+        }
+    }
+
+    #[must_use]
+    pub fn kind(&self) -> &'static str {
+        match self {
+            CommandError::Unknown => "unknown_command",
+            CommandError::BadArgument(_) => "bad_argument",
+            CommandError::OutOfRange(_) => "out_of_range",
+            CommandError::NotPermitted(_) => "not_permitted",
+            CommandError::Failed(_) => "failed",
+        }
+    }
+}
+
+impl fmt::Display for CommandError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CommandError::Unknown => write!(f, "unknown command"),
+            CommandError::BadArgument(x) => write!(f, "bad argument {x}"),
+            CommandError::OutOfRange(x) => write!(f, "out of range: {x}"),
+            CommandError::NotPermitted(x) => write!(f, "not permitted: {x}"),
+            CommandError::Failed(x) => write!(f, "failed: {x}"),
+        }
+    }
+}
+
+impl std::error::Error for CommandError {}
+
+pub type CommandResult = Result<String, CommandError>;
+
+/// Parse a command argument.
+/// # Errors
+/// Returns `BadArgument` if `x` doesn't parse as a `T`
+pub fn arg<T: FromStr>(x: &str) -> Result<T, CommandError> {
+    x.parse()
+        .map_err(|_| CommandError::BadArgument(format!("'{x}'")))
+}
+
+/// Parse a command argument that has to lie in `range`.
+/// # Errors
+/// Returns `BadArgument` if `x` doesn't parse as a `T`, or `OutOfRange` if it isn't in `range`
+pub fn arg_in<T: FromStr + PartialOrd + fmt::Display>(
+    x: &str,
+    range: RangeInclusive<T>,
+) -> Result<T, CommandError> {
+    let value = arg::<T>(x)?;
+    if range.contains(&value) {
+        Ok(value)
+    } else {
+        Err(CommandError::OutOfRange(format!(
+            "{value} not in [{}, {}]",
+            range.start(),
+            range.end()
+        )))
+    }
+}
+
+/// Reply to a plain-text command: the response itself on success, as ever (empty for most `SET`s),
+/// or `ERR:<code>:<message>`.
+#[must_use]
+pub fn text_reply(result: CommandResult) -> String {
+    match result {
+        Ok(resp) => resp,
+        Err(e) => format!("ERR:{}:{e}", e.code()),
+    }
+}
+
+/// A command framed as JSON, e.g. `{"cmd": "LOCK:REF:GAIN_P:GET", "id": 7}`. The optional `id` is
+/// passed back in the reply, to match them up.
+#[derive(Debug, Deserialize)]
+struct JsonRequest {
+    cmd: String,
+    #[serde(default)]
+    id: Option<serde_json::Value>,
+}
+
+/// e.g. `{"id": 7, "code": 0, "reply": "0.001"}` or
+/// `{"id": 7, "code": 2, "error": "bad_argument", "message": "bad argument 'x'"}`
+#[derive(Debug, Serialize)]
+struct JsonReply {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    id: Option<serde_json::Value>,
+    code: u16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reply: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    message: Option<String>,
+}
+
+/// Whether a request is framed as JSON rather than plain text.
+#[must_use]
+pub fn is_json(request: &str) -> bool {
+    request.trim_start().starts_with('{')
+}
+
+/// Handle a JSON-framed request by passing its command on to `process`. Returns the command
+/// (if the request could be parsed) and the JSON reply.
+pub fn json_reply(
+    request: &str,
+    process: impl FnOnce(&str) -> CommandResult,
+) -> (Option<String>, String) {
+    let (cmd, id, result) = match serde_json::from_str::<JsonRequest>(request) {
+        Ok(request) => {
+            let result = process(&request.cmd);
+            (Some(request.cmd), request.id, result)
+        }
+        Err(e) => (
+            None,
+            None,
+            Err(CommandError::BadArgument(format!(
+                "(invalid JSON request: {e})"
+            ))),
+        ),
+    };
+    let reply = match result {
+        Ok(reply) => JsonReply {
+            id,
+            code: 0,
+            reply: Some(reply),
+            error: None,
+            message: None,
+        },
+        Err(e) => JsonReply {
+            id,
+            code: e.code(),
+            reply: None,
+            error: Some(e.kind()),
+            message: Some(e.to_string()),
+        },
+    };
+    let reply = serde_json::to_string(&reply).unwrap_or_else(|e| {
+        format!("{{\"code\": 5, \"error\": \"failed\", \"message\": \"{e}\"}}")
+    });
+    (cmd, reply)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replies() {
+        assert_eq!(arg::<f32>("0.5"), Ok(0.5));
+        assert_eq!(arg_in("2", 0.0..=1.0).unwrap_err().code(), 3);
+        assert_eq!(text_reply(Ok(String::new())), "");
+        assert_eq!(
+            text_reply(arg::<u32>("x").map(|x| x.to_string())),
+            "ERR:2:bad argument 'x'"
+        );
+
+        let (cmd, reply) = json_reply(r#"{"cmd": "A:GET", "id": 7}"#, |_| Ok("1".to_string()));
+        assert_eq!(cmd.as_deref(), Some("A:GET"));
+        assert_eq!(reply, r#"{"id":7,"code":0,"reply":"1"}"#);
+        let (_, reply) = json_reply(r#"{"cmd": "B"}"#, |_| Err(CommandError::Unknown));
+        assert_eq!(
+            reply,
+            r#"{"code":1,"error":"unknown_command","message":"unknown command"}"#
+        );
+        let (cmd, reply) = json_reply("{cmd", |_| Ok(String::new()));
+        assert!(cmd.is_none());
+        assert!(reply.starts_with(r#"{"code":2,"error":"bad_argument""#));
+    }
+}
@@ -7,9 +7,12 @@ use futures::future::FutureExt;
 use gethostname::gethostname;
 use zeromq::prelude::*;
 
+use super::command::{self, CommandError, CommandResult};
 use super::configs::floor_exp;
 use super::interferometer::Interferometer;
 
+use std::fs;
+use std::path::PathBuf;
 use std::str;
 
 fn iterf32_to_bytes<C>(collection: C) -> Bytes
@@ -37,6 +40,8 @@ pub struct InterfComms {
     command_sock: zeromq::RepSocket,
     command_port: u16,
     logs_publish_frequency_exponent: u8,
+    identity: String,
+    config_file: Option<PathBuf>,
 }
 
 // fn vf32_to_u8(v: &[f32]) -> &[u8] {
@@ -60,6 +65,8 @@ impl InterfComms {
             command_sock,
             command_port: 8081,
             logs_publish_frequency_exponent: 8,
+            identity: String::new(),
+            config_file: None,
         })
     }
 
@@ -68,22 +75,78 @@ impl InterfComms {
         self.logs_publish_frequency_exponent = floor_exp(num_cycles as u64);
     }
 
+    /// Description of the board (config section, model, DNA, calibration) reported in response to
+    /// `IDENTITY:GET` and in `IDENTITY` status messages.
+    pub fn set_identity(&mut self, identity: String) {
+        self.identity = identity;
+    }
+
+    /// Config file sent in response to `CONFIG:GET`, e.g. for the other boards of the cluster to
+    /// start from.
+    pub fn set_config_file(&mut self, path: PathBuf) {
+        self.config_file = Some(path);
+    }
+
+    fn config_text(&self) -> CommandResult {
+        let path = self
+            .config_file
+            .as_ref()
+            .ok_or_else(|| CommandError::NotPermitted("no config file".to_string()))?;
+        fs::read_to_string(path)
+            .map_err(|e| CommandError::Failed(format!("failed to read {}: {e}", path.display())))
+    }
+
+    fn process(&self, interf: &mut Interferometer, cmd: &str) -> CommandResult {
+        let result = match cmd {
+            "IDENTITY:GET" => Ok(self.identity.clone()),
+            "CONFIG:GET" => self.config_text(),
+            "CONFIG:SAVE" if self.config_file.is_none() => Err(CommandError::NotPermitted(
+                "no config file to save to".to_string(),
+            )),
+            _ => interf.process_command(cmd.split(':')),
+        };
+        if let Err(e) = &result {
+            eprintln!(
+                "[{}] failed to process command [{}]: {}",
+                Local::now(),
+                cmd,
+                e
+            );
+        }
+        result
+    }
+
+    /// Publish the board's identity as an `IDENTITY` status event. This is done at startup and
+    /// again with each `publish_logs`, for subscribers that connect later.
+    /// # Errors
+    /// Propagates any zeromq error in the socket send operation.
+    pub async fn publish_identity(&mut self) -> zeromq::ZmqResult<()> {
+        let identity = self.identity.clone();
+        self.publish_status("IDENTITY", &identity).await
+    }
+
     #[inline]
     #[must_use]
     pub fn should_publish_logs(&self, num_cycles: u64) -> bool {
         (num_cycles & ((1 << self.logs_publish_frequency_exponent) - 1)) == 0
     }
 
+    /// Handle one queued command, if there is one, and send back the reply: for plain-text
+    /// commands the response (empty for most `SET`s) or `ERR:<code>:<message>`, and for commands
+    /// framed as JSON (`{"cmd": ..., "id": ...}`) a JSON reply carrying the code (0 for success).
+    /// Returns the command.
     pub async fn handle_socket_request(&mut self, interf: &mut Interferometer) -> Option<String> {
         let cmd_msg = self.command_sock.recv().now_or_never()?.ok()?;
-        let cmd = str::from_utf8(cmd_msg.get(0)?).ok()?;
-        let _ = if let Ok(s) = interf.process_command(cmd.split(':')) {
-            self.command_sock.send(s.into()).await
+        let request = str::from_utf8(cmd_msg.get(0)?).ok()?;
+        let (cmd, reply) = if command::is_json(request) {
+            let (cmd, reply) = command::json_reply(request, |cmd| self.process(interf, cmd));
+            (cmd.unwrap_or_else(|| request.to_string()), reply)
         } else {
-            eprintln!("[{}] failed to process command [{}]", Local::now(), cmd);
-            self.command_sock.send("".into()).await
+            let reply = command::text_reply(self.process(interf, request));
+            (request.to_string(), reply)
         };
-        Some(cmd.to_string())
+        let _ = self.command_sock.send(reply.into()).await;
+        Some(cmd)
     }
 
     /// Poll the command socket, and handle a command if one is queued up. Returns Some()
@@ -130,6 +193,8 @@ impl InterfComms {
         msg.push_back(iterf32_to_bytes(&interf.ref_laser.feedback_log));
         msg.push_back(iterf32_to_bytes(&interf.slave_laser.feedback_log));
 
+        // waveforms stay raw ADC words whatever `scope_units` says, so existing subscribers can
+        // still read them
         msg.push_back(vecu32_to_bytes(&interf.last_waveform_ref));
         msg.push_back(vecu32_to_bytes(&interf.last_waveform_slave));
 
@@ -138,6 +203,43 @@ impl InterfComms {
         msg.push_back(iterf32_to_bytes(interf.ref_laser.fit_coefficients));
         msg.push_back(iterf32_to_bytes(interf.slave_laser.fit_coefficients));
 
+        // one extra frame per configured slow analog monitor input, in config order
+        for monitor in &interf.monitor_inputs {
+            msg.push_back(iterf32_to_bytes(&monitor.log));
+        }
+
+        self.logs_sock.send(msg).await?;
+
+        // in volts as well, if that's what the scope reads, under a topic of their own so that
+        // the regular log stream is unchanged: `[WAVEFORMS, hostname, cycle, ref, slave]`
+        if !interf.last_waveform_volts_ref.is_empty() {
+            let mut msg: zeromq::ZmqMessage = "WAVEFORMS".into();
+            msg.push_back(self.hostname.clone().into());
+            msg.push_back(interf.cycle_counter.to_le_bytes().to_vec().into());
+            msg.push_back(iterf32_to_bytes(
+                interf.last_waveform_volts_ref.iter().copied(),
+            ));
+            msg.push_back(iterf32_to_bytes(
+                interf.last_waveform_volts_slave.iter().copied(),
+            ));
+            self.logs_sock.send(msg).await?;
+        }
+
+        // a PUB socket doesn't keep messages for subscribers that join later, so the identity
+        // goes out again with every batch of logs
+        self.publish_identity().await
+    }
+
+    /// Publish a one-off status event on the logs socket. These go out under the topic `STATUS`
+    /// rather than our hostname, so that subscribers to the regular log stream never see them:
+    /// `[STATUS, hostname, kind, message]`.
+    /// # Errors
+    /// Propagates any zeromq error in the socket send operation.
+    pub async fn publish_status(&mut self, kind: &str, message: &str) -> zeromq::ZmqResult<()> {
+        let mut msg: zeromq::ZmqMessage = "STATUS".into();
+        msg.push_back(self.hostname.clone().into());
+        msg.push_back(kind.to_string().into());
+        msg.push_back(message.to_string().into());
         self.logs_sock.send(msg).await
     }
 
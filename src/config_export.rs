@@ -0,0 +1,306 @@
+#![warn(clippy::pedantic)]
+
+use std::fs;
+use std::path::Path;
+
+use toml_edit::{Array, DocumentMut, Item, Table, Value};
+
+use super::config_model::{Config, LaserSection};
+use super::interferometer::Interferometer;
+use super::lock::Servo;
+use super::multifit::FitSetup;
+use super::ramp::DaqSetup;
+use super::ramp_shape::RampShape;
+use super::state;
+
+/// A config file with the live parameters written into it.
+#[derive(Debug)]
+pub struct Exported {
+    pub text: String,
+    // `section:key` for every key written
+    pub changed: Vec<String>,
+    // live settings that have no place in the config file
+    pub not_saved: Vec<String>,
+}
+
+/// The shortest decimal that reads back as `x`, rather than the nearest f64, so that e.g. a gain
+/// of 0.001 is written as such.
+fn float(x: f32) -> Value {
+    Value::from(x.to_string().parse::<f64>().unwrap_or(f64::from(x)))
+}
+
+/// Set `key` in `table`, keeping any comment after the old value.
+fn set(table: &mut Table, key: &str, value: Value) {
+    match table.get_mut(key).and_then(Item::as_value_mut) {
+        Some(old) => {
+            let decor = old.decor().clone();
+            *old = value;
+            *old.decor_mut() = decor;
+        }
+        None => {
+            table.insert(key, Item::Value(value));
+        }
+    }
+}
+
+fn section<'a>(doc: &'a mut DocumentMut, name: &str) -> Result<&'a mut Table, String> {
+    doc.get_mut(name)
+        .and_then(Item::as_table_mut)
+        .ok_or_else(|| format!("no [{name}] table in the config file"))
+}
+
+/// Keys are only written where the live value differs from what the file gives, so that
+/// everything else (including values inherited from `[multifit]`) is left as it was.
+struct Writer<'a> {
+    table: &'a mut Table,
+    name: &'a str,
+    changed: &'a mut Vec<String>,
+}
+
+impl Writer<'_> {
+    #[allow(clippy::float_cmp)]
+    fn float(&mut self, key: &str, file: f32, live: f32) {
+        if file != live {
+            set(self.table, key, float(live));
+            self.changed.push(format!("{}:{key}", self.name));
+        }
+    }
+
+    fn int(&mut self, key: &str, file: u32, live: u32) {
+        if file != live {
+            set(self.table, key, Value::from(i64::from(live)));
+            self.changed.push(format!("{}:{key}", self.name));
+        }
+    }
+}
+
+fn export_laser(
+    w: &mut Writer,
+    file: &LaserSection,
+    wavelength_nm: f32,
+    servo: &Servo,
+    fit: &FitSetup,
+) {
+    w.float("wavelength_nm", file.wavelength_nm, wavelength_nm);
+    w.float("gain_p", file.gain_p, servo.gain_P);
+    w.float("gain_i", file.gain_i, servo.gain_I);
+    w.float("gain_d", file.gain_d, servo.gain_D);
+    w.float(
+        "derivative_filter_n",
+        file.derivative_filter_n
+            .unwrap_or_else(|| Servo::new().deriv_filter_N),
+        servo.deriv_filter_N,
+    );
+    w.float(
+        "integral_decay_rate",
+        file.integral_decay_rate,
+        servo.alpha_I(),
+    );
+    w.float(
+        "feedback_max_step_size_v",
+        file.feedback_max_step_size_v,
+        servo.max_feedback_step_size,
+    );
+    w.float("setpoint", file.setpoint.unwrap_or(0.0), servo.setpoint());
+    w.int(
+        "max_iterations",
+        file.fit.max_iterations,
+        fit.max_iterations,
+    );
+    w.float("xtol", file.fit.xtol, fit.xtol);
+    w.float("gtol", file.fit.gtol, fit.gtol);
+    w.float("ftol", file.fit.ftol, fit.ftol);
+    w.float("max_av_ratio", file.fit.max_av_ratio, fit.max_av_ratio);
+    w.float(
+        "low_contrast_threshold",
+        file.fit.low_contrast_threshold,
+        fit.low_contrast_threshold,
+    );
+}
+
+fn export_ramp(w: &mut Writer, file: &Config, ramp: &DaqSetup, not_saved: &mut Vec<String>) {
+    let file = &file.ramp;
+    w.float(
+        "amplitude_volts",
+        file.amplitude_volts,
+        ramp.amplitude_volts,
+    );
+    w.float(
+        "piezo_scale_factor",
+        file.piezo_scale_factor,
+        ramp.piezo_scale_factor,
+    );
+    w.float(
+        "piezo_settle_time_ms",
+        file.piezo_settle_time_ms,
+        ramp.piezo_settle_time_ms,
+    );
+
+    let (shape, edge_fraction) = match ramp.shape() {
+        RampShape::LinearCosine => ("linear_cosine", None),
+        RampShape::Triangle => ("triangle", None),
+        RampShape::Sine => ("sine", None),
+        RampShape::RaisedCosineLinear { edge_fraction } => {
+            ("raised_cosine_linear", Some(*edge_fraction))
+        }
+        RampShape::Custom(_) => ("custom", None),
+    };
+    if file.shape.as_deref().unwrap_or("linear_cosine") != shape {
+        if shape == "custom" {
+            // the points would need writing to a file of their own
+            not_saved.push("ramp:shape (custom waveform)".to_string());
+        } else {
+            set(w.table, "shape", Value::from(shape));
+            w.changed.push("ramp:shape".to_string());
+        }
+    }
+    if let Some(edge_fraction) = edge_fraction {
+        w.float(
+            "edge_fraction",
+            file.edge_fraction.unwrap_or(0.05),
+            edge_fraction,
+        );
+    }
+
+    if file.predistortion.as_deref().unwrap_or_default() != ramp.predistortion() {
+        if ramp.predistortion().is_empty() {
+            w.table.remove("predistortion");
+        } else {
+            let coeffs = ramp.predistortion().iter().map(|x| float(*x));
+            set(
+                w.table,
+                "predistortion",
+                Value::Array(coeffs.collect::<Array>()),
+            );
+        }
+        w.changed.push("ramp:predistortion".to_string());
+    }
+}
+
+/// Write the parameters that can be tuned while running (laser wavelengths, servo settings, fit
+/// tolerances, and the ramp amplitude, scale factor, settle time, shape and predistortion) into
+/// the config file `text`, for the section of `board`. Only keys whose values have changed are
+/// touched, so comments, formatting and the other boards' sections are kept.
+/// # Errors
+/// Returns a message if `text` isn't a valid config, or has no sections for this board's lasers
+pub fn export(text: &str, interf: &Interferometer, board: &str) -> Result<Exported, String> {
+    let (file, _) = Config::parse(text).map_err(|e| e.to_string())?;
+    let mut doc = text
+        .parse::<DocumentMut>()
+        .map_err(|e| format!("failed to parse config file: {e}"))?;
+    let (slave_name, slave_file) = file.slave_laser(board)?;
+    let mut changed = Vec::new();
+    let mut not_saved = Vec::new();
+
+    export_laser(
+        &mut Writer {
+            table: section(&mut doc, "ref_laser")?,
+            name: "ref_laser",
+            changed: &mut changed,
+        },
+        &file.ref_laser,
+        interf.ref_laser.wavelength_nm(),
+        &interf.ref_lock,
+        &interf.fit_setup_ref,
+    );
+    export_laser(
+        &mut Writer {
+            table: section(&mut doc, slave_name)?,
+            name: slave_name,
+            changed: &mut changed,
+        },
+        slave_file,
+        interf.slave_laser.wavelength_nm(),
+        &interf.slave_lock,
+        &interf.fit_setup_slave,
+    );
+    export_ramp(
+        &mut Writer {
+            table: section(&mut doc, "ramp")?,
+            name: "ramp",
+            changed: &mut changed,
+        },
+        &file,
+        &interf.ramp_setup,
+        &mut not_saved,
+    );
+
+    Ok(Exported {
+        text: doc.to_string(),
+        changed,
+        not_saved,
+    })
+}
+
+/// Write the live parameters into the config file at `path` (see `export`), keeping the old file
+/// as a backup (see `state::write_atomic`). If nothing has changed, the file is left alone.
+/// # Errors
+/// Returns a message if the file can't be read, exported to, backed up or written
+pub fn save(path: &Path, interf: &Interferometer, board: &str) -> Result<Exported, String> {
+    let text =
+        fs::read_to_string(path).map_err(|e| format!("failed to read {}: {e}", path.display()))?;
+    let out = export(&text, interf, board)?;
+    if out.changed.is_empty() {
+        return Ok(out);
+    }
+    state::write_atomic(path, &out.text, true)?;
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config_model::minimal_config;
+    use crate::configs;
+
+    #[test]
+    fn live_parameters_round_trip() {
+        let board = "rp-1";
+        let text = minimal_config().replace(
+            "symmetry_factor = 0.8\n",
+            "symmetry_factor = 0.8\npredistortion = [0.0, 1.0]\n",
+        );
+        let cfg: Config = text.parse().unwrap();
+        let mut interf = configs::interferometer_from_config(&cfg, board).unwrap();
+        assert!(export(&text, &interf, board).unwrap().changed.is_empty());
+
+        interf.slave_lock.gain_P = 0.002;
+        interf.slave_lock.set_setpoint(0.25);
+        interf.fit_setup_slave.max_iterations = 64;
+        interf.ramp_setup.amplitude(1.25);
+        interf.ramp_setup.set_predistortion(vec![0.0, 1.0, 0.1]);
+        let out = export(&text, &interf, board).unwrap();
+        assert_eq!(
+            out.changed,
+            [
+                "las_1114:gain_p",
+                "las_1114:setpoint",
+                "las_1114:max_iterations",
+                "ramp:amplitude_volts",
+                "ramp:predistortion",
+            ]
+        );
+        // comments and the shared sections survive, and the shortest form of each value is used
+        assert!(out.text.contains("gain_p = 0.002\n"));
+        assert!(out
+            .text
+            .contains("amplitude_volts = 1.25 # either side of the offset\n"));
+        assert!(out.text.contains("predistortion = [0.0, 1.0, 0.1]\n"));
+        assert!(out.text.contains("[multifit]\nsamples_skip_start = 6500"));
+
+        let saved: Config = out.text.parse().unwrap();
+        let (_, slave) = saved.slave_laser(board).unwrap();
+        assert_eq!(slave.setpoint, Some(0.25));
+        assert_eq!(slave.fit.max_iterations, 64);
+        assert!((saved.ref_laser.gain_p - 0.001).abs() < 1e-9);
+        assert_eq!(saved.ramp.predistortion, Some(vec![0.0, 1.0, 0.1]));
+
+        // no predistortion at all clears the key
+        interf.ramp_setup.set_predistortion(Vec::new());
+        let out = export(&text, &interf, board).unwrap();
+        assert!(out.changed.contains(&"ramp:predistortion".to_string()));
+        assert!(!out.text.contains("predistortion"));
+        let saved: Config = out.text.parse().unwrap();
+        assert!(saved.ramp.predistortion.is_none());
+    }
+}
@@ -0,0 +1,1216 @@
+#![warn(clippy::pedantic)]
+#![allow(
+    clippy::module_name_repetitions,
+    clippy::cast_precision_loss,
+    clippy::struct_field_names,
+    clippy::too_many_lines
+)]
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::str::FromStr;
+
+use serde::de::{DeserializeOwned, Error as _};
+use serde::{Deserialize, Deserializer};
+
+use librp_sys::core::Channel;
+use librp_sys::oscilloscope::{Averaging, InputGain, ScopeRegion, ScopeUnits};
+use librp_sys::{analog, dpin};
+
+use super::ramp::falling_points;
+use super::ramp_shape::{RampShape, RAMP_POINTS};
+use super::seed::{SeedOutput, SeedSource};
+use super::status_leds::{Indicator, Pattern, StatusLeds};
+
+/// The contents of a config file. Besides the fixed sections, each board has a section (named
+/// after its hostname, or matched by `dna`) and each slave laser has one, named by the boards'
+/// `slave_laser` keys; a section is taken to be a board's if it has a `slave_laser` or `is_master`
+/// key, and a laser's if it has a `wavelength_nm` key.
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub general: General,
+    pub ramp: Ramp,
+    pub ref_laser: LaserSection,
+    pub leds: BTreeMap<String, Led>,
+    pub server: Option<Server>,
+    pub boards: BTreeMap<String, BoardSection>,
+    pub lasers: BTreeMap<String, LaserSection>,
+}
+
+/// Every problem found in a config file, so that they can all be fixed in one go.
+#[derive(Debug, Clone)]
+pub struct ConfigErrors(pub Vec<String>);
+
+impl fmt::Display for ConfigErrors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} problem(s) in config file:", self.0.len())?;
+        for error in &self.0 {
+            write!(f, "\n  - {error}")?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct General {
+    // not used by the boards themselves, but read by the GUI
+    #[serde(default)]
+    pub number_of_pitayas: Option<u32>,
+    #[serde(default, rename = "interferometer_FSR_MHz")]
+    pub interferometer_fsr_mhz: Option<f32>,
+
+    #[serde(default)]
+    pub pitaya_log_length: Option<u64>,
+    #[serde(default)]
+    pub pitaya_log_length_exponent: Option<u32>,
+    #[serde(default)]
+    pub logs_publish_freq_cycles: Option<u32>,
+    #[serde(default)]
+    pub debug_list_freq_cycles: Option<u64>,
+    #[serde(deserialize_with = "parsed")]
+    pub master_external_trigger_output_pin: dpin::Pin,
+    #[serde(deserialize_with = "parsed")]
+    pub ready_to_acquire_pin: dpin::Pin,
+    #[serde(default, deserialize_with = "parsed_opt")]
+    pub scope_units: Option<ScopeUnits>,
+
+    #[serde(default)]
+    pub handshake_timeout_ms: Option<u64>,
+    #[serde(default)]
+    pub trigger_timeout_ms: Option<u64>,
+    #[serde(default)]
+    pub poll_interval_us: Option<u64>,
+    #[serde(default)]
+    pub deadline_spin_us: Option<u64>,
+    #[serde(default)]
+    pub timeouts_to_fault: Option<u32>,
+    #[serde(default)]
+    pub fault_retry_interval_ms: Option<u64>,
+
+    pub logs_port: u16,
+    pub command_port: u16,
+
+    #[serde(default)]
+    pub park_slew_rate_v_per_s: Option<f32>,
+    #[serde(default)]
+    pub state_file: Option<String>,
+    #[serde(default)]
+    pub state_save_interval_s: Option<f64>,
+    #[serde(default)]
+    pub restore_state: Option<bool>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct Ramp {
+    pub piezo_scale_factor: f32,
+    pub piezo_settle_time_ms: f32,
+    pub amplitude_volts: f32,
+    pub decimation_factor: u32,
+    pub symmetry_factor: f32,
+    #[serde(default)]
+    pub shape: Option<String>,
+    #[serde(default)]
+    pub edge_fraction: Option<f32>,
+    #[serde(default)]
+    pub custom_waveform: Option<String>,
+    #[serde(default)]
+    pub predistortion: Option<Vec<f32>>,
+    #[serde(default)]
+    pub amplitude_control: Option<AmplitudeControlConfig>,
+    #[serde(default)]
+    pub fit_falling: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct AmplitudeControlConfig {
+    pub target_fringes: f32,
+    pub max_step_v: f32,
+    pub min_v: f32,
+    pub max_v: f32,
+    #[serde(default)]
+    pub gain: Option<f32>,
+    #[serde(default)]
+    pub interval_cycles: Option<u32>,
+}
+
+/// The `[multifit]` keys, any of which a laser's section may override. All of them are needed
+/// for every laser, except the `falling_` ones, which are only needed when fitting the falling
+/// segment of the ramp.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct FitParams {
+    pub samples_skip_start: Option<usize>,
+    pub samples_skip_end: Option<usize>,
+    pub skip_rate: Option<usize>,
+    pub max_iterations: Option<u32>,
+    pub xtol: Option<f32>,
+    pub gtol: Option<f32>,
+    pub ftol: Option<f32>,
+    pub max_av_ratio: Option<f32>,
+    pub low_contrast_threshold: Option<f32>,
+    pub falling_samples_skip_start: Option<usize>,
+    pub falling_samples_skip_end: Option<usize>,
+    pub falling_skip_rate: Option<usize>,
+}
+
+/// Region of interest of an acquisition, i.e. which of its `RAMP_POINTS` samples get fit.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Roi {
+    pub skip_start: usize,
+    pub skip_end: usize,
+    pub skip_rate: usize,
+}
+
+/// A laser's fit settings, with any overrides from its own section applied.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Fit {
+    pub roi: Roi,
+    pub falling_roi: Option<Roi>,
+    pub max_iterations: u32,
+    pub xtol: f32,
+    pub gtol: f32,
+    pub ftol: f32,
+    pub max_av_ratio: f32,
+    pub low_contrast_threshold: f32,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct LaserSection {
+    pub wavelength_nm: f32,
+    pub gain_p: f32,
+    pub gain_i: f32,
+    pub gain_d: f32,
+    #[serde(default)]
+    pub derivative_filter_n: Option<f32>,
+    pub integral_decay_rate: f32,
+    pub feedback_max_step_size_v: f32,
+    // in radians; 0 if not given
+    #[serde(default)]
+    pub setpoint: Option<f32>,
+    // trace color in the GUI
+    #[serde(default)]
+    pub plot_color: Option<String>,
+    #[serde(default)]
+    pub wavelength_meter: Option<WavelengthMeterConfig>,
+    #[serde(default)]
+    pub seed_control: Option<SeedControlConfig>,
+    // filled in from the `[multifit]` keys in this section and in `[multifit]`
+    #[serde(skip)]
+    pub fit: Fit,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+pub struct WavelengthMeterConfig {
+    #[serde(default)]
+    pub cycles: Option<usize>,
+    #[serde(default)]
+    pub feedback: Option<bool>,
+    #[serde(default)]
+    pub mode_hop_threshold_nm: Option<f32>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct SeedControlConfig {
+    pub timeout_sec: f32,
+    pub loop_cycle_sec: f32,
+    pub threshold_volts: f32,
+    pub adjustment_size_volts: f32,
+    #[serde(default, deserialize_with = "parsed_opt")]
+    pub source: Option<SeedSource>,
+    #[serde(default, deserialize_with = "parsed_opt")]
+    pub output: Option<SeedOutput>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct BoardSection {
+    // FPGA DNA as a hex string, to pick this section regardless of hostname
+    #[serde(default)]
+    pub dna: Option<String>,
+    pub is_master: bool,
+    pub slave_laser: String,
+    #[serde(deserialize_with = "channel")]
+    pub ref_input_channel: Channel,
+    #[serde(default, deserialize_with = "channel_opt")]
+    pub ref_output_channel: Option<Channel>,
+    #[serde(deserialize_with = "channel")]
+    pub slave_input_channel: Channel,
+    #[serde(deserialize_with = "channel")]
+    pub slave_output_channel: Channel,
+
+    pub ch_1_out_hardware_offset_volts: f32,
+    pub ch_1_min_output_v: f32,
+    pub ch_1_max_output_v: f32,
+    pub ch_1_preamp_gain: f32,
+    pub ch_2_out_hardware_offset_volts: f32,
+    pub ch_2_min_output_v: f32,
+    pub ch_2_max_output_v: f32,
+    pub ch_2_preamp_gain: f32,
+
+    #[serde(default, deserialize_with = "parsed_opt")]
+    pub ch_1_input_gain: Option<InputGain>,
+    #[serde(default)]
+    pub ch_1_input_attenuation: Option<f32>,
+    #[serde(default)]
+    pub ch_1_averaging: Option<AveragingConfig>,
+    #[serde(default, deserialize_with = "parsed_opt")]
+    pub ch_2_input_gain: Option<InputGain>,
+    #[serde(default)]
+    pub ch_2_input_attenuation: Option<f32>,
+    #[serde(default)]
+    pub ch_2_averaging: Option<AveragingConfig>,
+
+    #[serde(default)]
+    pub ref_slow_output: Option<SlowOutputConfig>,
+    #[serde(default)]
+    pub slave_slow_output: Option<SlowOutputConfig>,
+    #[serde(default, deserialize_with = "parsed_vec")]
+    pub monitor_inputs: Vec<analog::Pin>,
+
+    #[serde(default)]
+    pub ref_park_v: Option<f32>,
+    #[serde(default)]
+    pub slave_park_v: Option<f32>,
+}
+
+/// Output range of one generator channel, as seen after the preamp.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OutputRange {
+    pub hw_offset_v: f32,
+    pub min_v: f32,
+    pub max_v: f32,
+    pub preamp_gain: f32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(tag = "mode", rename_all = "lowercase")]
+pub enum AveragingConfig {
+    None,
+    Block { traces: usize },
+    Sliding { traces: usize },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+pub struct SlowOutputConfig {
+    #[serde(deserialize_with = "parsed")]
+    pub pin: analog::Pin,
+    pub gain: f32,
+    pub max_step_v: f32,
+    #[serde(default)]
+    pub center_v: Option<f32>,
+}
+
+/// An LED assignment, either `LED_0 = "ref_lock"` (lit solid while active) or
+/// `LED_5 = {indicator = "saturation", pattern = "blink_fast"}`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "LedSetting")]
+pub struct Led {
+    pub indicator: Indicator,
+    pub pattern: Pattern,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum LedSetting {
+    Solid(String),
+    Table {
+        indicator: String,
+        #[serde(default)]
+        pattern: Option<String>,
+    },
+}
+
+impl TryFrom<LedSetting> for Led {
+    type Error = String;
+    fn try_from(setting: LedSetting) -> Result<Self, Self::Error> {
+        let (indicator, pattern) = match setting {
+            LedSetting::Solid(indicator) => (indicator, None),
+            LedSetting::Table { indicator, pattern } => (indicator, pattern),
+        };
+        let pattern = pattern.unwrap_or_else(|| "solid".to_string());
+        Ok(Led {
+            indicator: indicator
+                .parse()
+                .map_err(|()| format!("invalid LED indicator \"{indicator}\""))?,
+            pattern: pattern
+                .parse()
+                .map_err(|()| format!("invalid LED pattern \"{pattern}\""))?,
+        })
+    }
+}
+
+/// Read by the GUI.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Server {
+    pub listen_port: u16,
+}
+
+fn parsed<'de, D: Deserializer<'de>, T: FromStr>(d: D) -> Result<T, D::Error> {
+    let s = String::deserialize(d)?;
+    s.parse()
+        .map_err(|_| D::Error::custom(format!("invalid value \"{s}\"")))
+}
+
+fn parsed_opt<'de, D: Deserializer<'de>, T: FromStr>(d: D) -> Result<Option<T>, D::Error> {
+    parsed(d).map(Some)
+}
+
+fn parsed_vec<'de, D: Deserializer<'de>, T: FromStr>(d: D) -> Result<Vec<T>, D::Error> {
+    Vec::<String>::deserialize(d)?
+        .iter()
+        .map(|s| {
+            s.parse()
+                .map_err(|_| D::Error::custom(format!("invalid value \"{s}\"")))
+        })
+        .collect()
+}
+
+fn channel<'de, D: Deserializer<'de>>(d: D) -> Result<Channel, D::Error> {
+    match String::deserialize(d)?.as_str() {
+        "CH_1" | "CH_A" => Ok(Channel::CH_1),
+        "CH_2" | "CH_B" => Ok(Channel::CH_2),
+        x => Err(D::Error::custom(format!(
+            "invalid channel \"{x}\", expected one of CH_1, CH_2"
+        ))),
+    }
+}
+
+fn channel_opt<'de, D: Deserializer<'de>>(d: D) -> Result<Option<Channel>, D::Error> {
+    channel(d).map(Some)
+}
+
+/// Deserialize one section, recording the keys it doesn't know about as `section:key`.
+fn deserialize_section<T: DeserializeOwned>(
+    name: &str,
+    value: toml::Value,
+    unknown: &mut BTreeSet<String>,
+) -> Result<T, String> {
+    serde_ignored::deserialize(value, |path| {
+        unknown.insert(format!(
+            "{name}:{}",
+            path.to_string().replace("?.", "").replace('.', ":")
+        ));
+    })
+    .map_err(|e: toml::de::Error| format!("[{name}] {}", e.to_string().trim().replace('\n', " ")))
+}
+
+fn parse_section<T: DeserializeOwned>(
+    name: &str,
+    value: Option<toml::Value>,
+    unknown: &mut BTreeSet<String>,
+    errors: &mut Vec<String>,
+) -> Option<T> {
+    deserialize_section(name, value?, unknown)
+        .map_err(|e| errors.push(e))
+        .ok()
+}
+
+/// A laser section holds the laser's own settings, and maybe some fit overrides; a key is only
+/// unknown if it's neither.
+fn laser_section(
+    name: &str,
+    value: toml::Value,
+    unknown: &mut BTreeSet<String>,
+    errors: &mut Vec<String>,
+) -> Option<(LaserSection, FitParams)> {
+    let mut unknown_laser = BTreeSet::new();
+    let mut unknown_fit = BTreeSet::new();
+    let laser = deserialize_section::<LaserSection>(name, value.clone(), &mut unknown_laser);
+    let fit = deserialize_section::<FitParams>(name, value, &mut unknown_fit);
+    unknown.extend(unknown_laser.intersection(&unknown_fit).cloned());
+    match (laser, fit) {
+        (Ok(laser), Ok(fit)) => Some((laser, fit)),
+        (laser, fit) => {
+            errors.extend(laser.err().into_iter().chain(fit.err()));
+            None
+        }
+    }
+}
+
+impl FitParams {
+    /// These settings, falling back on `default` for any that aren't set.
+    #[must_use]
+    pub fn or(&self, default: &FitParams) -> FitParams {
+        FitParams {
+            samples_skip_start: self.samples_skip_start.or(default.samples_skip_start),
+            samples_skip_end: self.samples_skip_end.or(default.samples_skip_end),
+            skip_rate: self.skip_rate.or(default.skip_rate),
+            max_iterations: self.max_iterations.or(default.max_iterations),
+            xtol: self.xtol.or(default.xtol),
+            gtol: self.gtol.or(default.gtol),
+            ftol: self.ftol.or(default.ftol),
+            max_av_ratio: self.max_av_ratio.or(default.max_av_ratio),
+            low_contrast_threshold: self
+                .low_contrast_threshold
+                .or(default.low_contrast_threshold),
+            falling_samples_skip_start: self
+                .falling_samples_skip_start
+                .or(default.falling_samples_skip_start),
+            falling_samples_skip_end: self
+                .falling_samples_skip_end
+                .or(default.falling_samples_skip_end),
+            falling_skip_rate: self.falling_skip_rate.or(default.falling_skip_rate),
+        }
+    }
+
+    /// The complete fit settings for the laser in section `laser`, or the name of every missing
+    /// key.
+    fn resolve(&self, laser: &str, fit_falling: bool) -> Result<Fit, Vec<String>> {
+        let mut missing = Vec::new();
+        let mut need = |x: Option<f32>, key: &str| {
+            if x.is_none() {
+                missing.push(format!("missing key multifit:{key} (or {laser}:{key})"));
+            }
+            x.unwrap_or_default()
+        };
+        let xtol = need(self.xtol, "xtol");
+        let gtol = need(self.gtol, "gtol");
+        let ftol = need(self.ftol, "ftol");
+        let max_av_ratio = need(self.max_av_ratio, "max_av_ratio");
+        let low_contrast_threshold = need(self.low_contrast_threshold, "low_contrast_threshold");
+        let mut need = |x: Option<usize>, key: &str| {
+            if x.is_none() {
+                missing.push(format!("missing key multifit:{key} (or {laser}:{key})"));
+            }
+            x.unwrap_or_default()
+        };
+        let roi = Roi {
+            skip_start: need(self.samples_skip_start, "samples_skip_start"),
+            skip_end: need(self.samples_skip_end, "samples_skip_end"),
+            skip_rate: need(self.skip_rate, "skip_rate"),
+        };
+        let falling_roi = fit_falling.then(|| Roi {
+            skip_start: need(
+                self.falling_samples_skip_start,
+                "falling_samples_skip_start",
+            ),
+            skip_end: need(self.falling_samples_skip_end, "falling_samples_skip_end"),
+            skip_rate: need(self.falling_skip_rate, "falling_skip_rate"),
+        });
+        let max_iterations = self.max_iterations.unwrap_or_else(|| {
+            missing.push(format!(
+                "missing key multifit:max_iterations (or {laser}:max_iterations)"
+            ));
+            0
+        });
+        if !missing.is_empty() {
+            return Err(missing);
+        }
+        Ok(Fit {
+            roi,
+            falling_roi,
+            max_iterations,
+            xtol,
+            gtol,
+            ftol,
+            max_av_ratio,
+            low_contrast_threshold,
+        })
+    }
+}
+
+impl Roi {
+    #[must_use]
+    pub fn region(&self) -> ScopeRegion {
+        ScopeRegion::new(self.skip_start, self.skip_end, self.skip_rate)
+    }
+
+    fn check(&self, what: &str, errors: &mut Vec<String>) {
+        if self.skip_rate == 0 {
+            errors.push(format!("{what}: skip_rate must be at least 1"));
+        } else if self.skip_start + self.skip_end > RAMP_POINTS - 4
+            || (RAMP_POINTS - self.skip_start - self.skip_end).div_ceil(self.skip_rate) < 4
+        {
+            errors.push(format!(
+                "{what}: skipping {} + {} of {RAMP_POINTS} samples leaves too few to fit",
+                self.skip_start, self.skip_end
+            ));
+        }
+    }
+}
+
+impl From<AveragingConfig> for Averaging {
+    fn from(cfg: AveragingConfig) -> Self {
+        match cfg {
+            AveragingConfig::None => Averaging::None,
+            AveragingConfig::Block { traces } => Averaging::Block(traces),
+            AveragingConfig::Sliding { traces } => Averaging::Sliding(traces),
+        }
+    }
+}
+
+impl BoardSection {
+    #[must_use]
+    pub fn dna(&self) -> Option<u64> {
+        self.dna
+            .as_ref()
+            .and_then(|x| u64::from_str_radix(x.trim_start_matches("0x"), 16).ok())
+    }
+
+    #[must_use]
+    pub fn output(&self, ch: Channel) -> OutputRange {
+        match ch {
+            Channel::CH_1 => OutputRange {
+                hw_offset_v: self.ch_1_out_hardware_offset_volts,
+                min_v: self.ch_1_min_output_v,
+                max_v: self.ch_1_max_output_v,
+                preamp_gain: self.ch_1_preamp_gain,
+            },
+            Channel::CH_2 => OutputRange {
+                hw_offset_v: self.ch_2_out_hardware_offset_volts,
+                min_v: self.ch_2_min_output_v,
+                max_v: self.ch_2_max_output_v,
+                preamp_gain: self.ch_2_preamp_gain,
+            },
+        }
+    }
+
+    /// Jumper setting (default LV) and external attenuation (default 1) of an input channel.
+    #[must_use]
+    pub fn input(&self, ch: Channel) -> (InputGain, f32) {
+        let (gain, attenuation) = match ch {
+            Channel::CH_1 => (self.ch_1_input_gain, self.ch_1_input_attenuation),
+            Channel::CH_2 => (self.ch_2_input_gain, self.ch_2_input_attenuation),
+        };
+        (gain.unwrap_or(InputGain::LV), attenuation.unwrap_or(1.0))
+    }
+
+    #[must_use]
+    pub fn averaging(&self, ch: Channel) -> Averaging {
+        match ch {
+            Channel::CH_1 => self.ch_1_averaging,
+            Channel::CH_2 => self.ch_2_averaging,
+        }
+        .map_or(Averaging::None, Averaging::from)
+    }
+}
+
+fn check(errors: &mut Vec<String>, ok: bool, msg: String) {
+    if !ok {
+        errors.push(msg);
+    }
+}
+
+fn channel_name(ch: Channel) -> &'static str {
+    match ch {
+        Channel::CH_1 => "ch_1",
+        Channel::CH_2 => "ch_2",
+    }
+}
+
+impl Config {
+    /// Parse and check a config file. Returns the config along with warnings about keys that
+    /// aren't used (most likely misspelled), or every problem found.
+    /// # Errors
+    /// Returns all the parse errors and out-of-range values found, not just the first
+    pub fn parse(text: &str) -> Result<(Config, Vec<String>), ConfigErrors> {
+        let mut table: toml::Table =
+            toml::from_str(text).map_err(|e| ConfigErrors(vec![e.to_string()]))?;
+        let mut errors = Vec::new();
+        let mut unknown = BTreeSet::new();
+
+        let mut section = |name: &str, errors: &mut Vec<String>| {
+            table.remove(name).or_else(|| {
+                errors.push(format!("missing section [{name}]"));
+                None
+            })
+        };
+        let general = section("general", &mut errors);
+        let ramp = section("ramp", &mut errors);
+        let multifit = section("multifit", &mut errors);
+        let ref_laser = section("ref_laser", &mut errors);
+        let leds = table.remove("leds");
+        let server = table.remove("server");
+
+        let general: Option<General> = parse_section("general", general, &mut unknown, &mut errors);
+        let ramp: Option<Ramp> = parse_section("ramp", ramp, &mut unknown, &mut errors);
+        let multifit: Option<FitParams> =
+            parse_section("multifit", multifit, &mut unknown, &mut errors);
+        let leds: BTreeMap<String, Led> =
+            parse_section("leds", leds, &mut unknown, &mut errors).unwrap_or_default();
+        let server: Option<Server> = parse_section("server", server, &mut unknown, &mut errors);
+
+        let ref_laser =
+            ref_laser.and_then(|x| laser_section("ref_laser", x, &mut unknown, &mut errors));
+        let mut boards = BTreeMap::new();
+        let mut lasers = BTreeMap::new();
+        for (name, value) in table {
+            if value.get("slave_laser").is_some() || value.get("is_master").is_some() {
+                let mut unknown_board = BTreeSet::new();
+                match deserialize_section::<BoardSection>(&name, value, &mut unknown_board) {
+                    Ok(board) => {
+                        boards.insert(name, board);
+                    }
+                    Err(e) => errors.push(e),
+                }
+                unknown.extend(unknown_board);
+            } else if value.get("wavelength_nm").is_some() {
+                if let Some(x) = laser_section(&name, value, &mut unknown, &mut errors) {
+                    lasers.insert(name, x);
+                }
+            } else if value.is_table() {
+                unknown.insert(format!("[{name}] (neither a board nor a laser section)"));
+            } else {
+                unknown.insert(name);
+            }
+        }
+
+        // Carry on past errors in other sections, so that as many problems as possible get
+        // reported at once; lasers that failed to parse show up again as missing below.
+        let (Some(general), Some(ramp), Some(multifit), Some((mut ref_laser, ref_fit))) =
+            (general, ramp, multifit, ref_laser)
+        else {
+            return Err(ConfigErrors(errors));
+        };
+        let fit_falling = ramp.fit_falling;
+        let mut resolve = |name: &str, laser: &mut LaserSection, fit: &FitParams| match fit
+            .or(&multifit)
+            .resolve(name, fit_falling)
+        {
+            Ok(fit) => laser.fit = fit,
+            Err(missing) => errors.extend(missing),
+        };
+        resolve("ref_laser", &mut ref_laser, &ref_fit);
+        let lasers = lasers
+            .into_iter()
+            .map(|(name, (mut laser, fit))| {
+                resolve(&name, &mut laser, &fit);
+                (name, laser)
+            })
+            .collect();
+
+        let config = Config {
+            general,
+            ramp,
+            ref_laser,
+            leds,
+            server,
+            boards,
+            lasers,
+        };
+        errors.extend(config.validate());
+        if !errors.is_empty() {
+            return Err(ConfigErrors(errors));
+        }
+        let mut warnings: Vec<String> = unknown
+            .into_iter()
+            .map(|key| format!("unknown config key {key} is ignored"))
+            .collect();
+        let exponent = config.log_length_exponent();
+        match (
+            config.general.pitaya_log_length_exponent,
+            config.general.pitaya_log_length,
+        ) {
+            (None, Some(length)) if length != 1 << exponent => warnings.push(format!(
+                "config explicit log length parameter {length} rounded down to 2^{exponent} = {}",
+                1u64 << exponent
+            )),
+            (None, None) => warnings.push(format!(
+                "no log length parameter found in configuration file, using default of {}",
+                1u64 << exponent
+            )),
+            _ => {}
+        }
+        Ok((config, warnings))
+    }
+
+    /// # Errors
+    /// Returns an error message if there's no section for `board`
+    pub fn board(&self, board: &str) -> Result<&BoardSection, String> {
+        self.boards
+            .get(board)
+            .ok_or_else(|| format!("no config section for board {board}"))
+    }
+
+    /// The name and section of the slave laser of `board`.
+    /// # Errors
+    /// Returns an error message if there's no section for `board` or its slave laser
+    pub fn slave_laser(&self, board: &str) -> Result<(&str, &LaserSection), String> {
+        let name = &self.board(board)?.slave_laser;
+        self.lasers
+            .get_key_value(name)
+            .map(|(name, laser)| (name.as_str(), laser))
+            .ok_or_else(|| format!("no config section for laser {name}"))
+    }
+
+    /// Exponent of the length of the logs, from `pitaya_log_length_exponent` or else
+    /// `pitaya_log_length` rounded down to a power of 2, defaulting to 2^10.
+    #[must_use]
+    pub fn log_length_exponent(&self) -> usize {
+        match (
+            self.general.pitaya_log_length_exponent,
+            self.general.pitaya_log_length,
+        ) {
+            (Some(exponent), _) => exponent as usize,
+            (None, Some(length)) => length.max(1).ilog2() as usize,
+            (None, None) => 10,
+        }
+    }
+
+    /// Range checks that serde can't do, for everything at once.
+    fn validate(&self) -> Vec<String> {
+        let mut errors = Vec::new();
+        let general = &self.general;
+        check(
+            &mut errors,
+            general.logs_port != general.command_port,
+            format!(
+                "general: logs_port and command_port are both {}",
+                general.logs_port
+            ),
+        );
+        check(
+            &mut errors,
+            self.log_length_exponent() <= 24,
+            "general: pitaya_log_length is over 2^24".to_string(),
+        );
+        check(
+            &mut errors,
+            general
+                .state_save_interval_s
+                .is_none_or(|x| x.is_finite() && x >= 0.0),
+            "general: state_save_interval_s must be non-negative".to_string(),
+        );
+
+        let ramp = &self.ramp;
+        let dec = ramp.decimation_factor;
+        check(
+            &mut errors,
+            matches!(dec, 1 | 2 | 4 | 8 | 16..=65536),
+            format!("ramp: decimation_factor {dec} is not one of 1, 2, 4, 8, or 16 to 65536"),
+        );
+        check(
+            &mut errors,
+            (0.01..=0.99).contains(&ramp.symmetry_factor),
+            format!(
+                "ramp: symmetry_factor {} outside [0.01, 0.99]",
+                ramp.symmetry_factor
+            ),
+        );
+        check(
+            &mut errors,
+            ramp.amplitude_volts > 0.0,
+            format!(
+                "ramp: amplitude_volts {} must be positive",
+                ramp.amplitude_volts
+            ),
+        );
+        check(
+            &mut errors,
+            ramp.piezo_scale_factor > 0.0,
+            format!(
+                "ramp: piezo_scale_factor {} must be positive",
+                ramp.piezo_scale_factor
+            ),
+        );
+        check(
+            &mut errors,
+            ramp.piezo_settle_time_ms >= 0.0,
+            "ramp: piezo_settle_time_ms must be non-negative".to_string(),
+        );
+        match ramp.shape.as_deref() {
+            None => {}
+            Some("custom") => check(
+                &mut errors,
+                ramp.custom_waveform.is_some(),
+                "ramp: shape \"custom\" needs custom_waveform".to_string(),
+            ),
+            Some(name) => check(
+                &mut errors,
+                RampShape::from_str(name).is_ok(),
+                format!("ramp: invalid shape \"{name}\""),
+            ),
+        }
+        if let Some(name) = ramp.shape.as_deref() {
+            let own_rise_time =
+                name == "custom" || RampShape::from_str(name).is_ok_and(|x| x.ignores_symmetry());
+            check(
+                &mut errors,
+                !own_rise_time || (ramp.symmetry_factor - 0.5).abs() < 1e-6,
+                format!(
+                    "ramp: shape \"{name}\" sets its own rise time, so needs symmetry_factor = 0.5"
+                ),
+            );
+        }
+        check(
+            &mut errors,
+            ramp.edge_fraction.is_none_or(|x| x > 0.0 && x <= 0.5),
+            "ramp: edge_fraction must be in (0, 0.5]".to_string(),
+        );
+        if let Some(ctrl) = ramp.amplitude_control.as_ref() {
+            check(
+                &mut errors,
+                0.0 < ctrl.min_v && ctrl.min_v < ctrl.max_v,
+                format!(
+                    "ramp: amplitude_control needs 0 < min_v < max_v, not {} and {}",
+                    ctrl.min_v, ctrl.max_v
+                ),
+            );
+            check(
+                &mut errors,
+                ctrl.target_fringes > 0.0 && ctrl.max_step_v > 0.0,
+                "ramp: amplitude_control target_fringes and max_step_v must be positive"
+                    .to_string(),
+            );
+            check(
+                &mut errors,
+                ctrl.gain.is_none_or(|x| (0.0..=1.0).contains(&x)),
+                "ramp: amplitude_control gain must be in [0, 1]".to_string(),
+            );
+        }
+
+        for (name, laser) in std::iter::once(("ref_laser", &self.ref_laser))
+            .chain(self.lasers.iter().map(|(n, l)| (n.as_str(), l)))
+        {
+            check(
+                &mut errors,
+                laser.wavelength_nm > 0.0,
+                format!(
+                    "{name}: wavelength_nm {} must be positive",
+                    laser.wavelength_nm
+                ),
+            );
+            check(
+                &mut errors,
+                (0.0..=1.0).contains(&laser.integral_decay_rate),
+                format!(
+                    "{name}: integral_decay_rate {} outside [0, 1]",
+                    laser.integral_decay_rate
+                ),
+            );
+            check(
+                &mut errors,
+                laser.feedback_max_step_size_v >= 0.0,
+                format!("{name}: feedback_max_step_size_v must be non-negative"),
+            );
+            let fit = &laser.fit;
+            check(
+                &mut errors,
+                fit.max_iterations > 0 && fit.xtol > 0.0 && fit.gtol > 0.0 && fit.ftol > 0.0,
+                format!("{name}: max_iterations and the fit tolerances must be positive"),
+            );
+            fit.roi.check(&format!("{name}: fit region"), &mut errors);
+            if let Some(roi) = fit.falling_roi {
+                roi.check(&format!("{name}: falling fit region"), &mut errors);
+                // both segments come from one acquisition, in which the fall overwrites the
+                // start of the rise
+                let falling = falling_points(ramp.symmetry_factor);
+                check(
+                    &mut errors,
+                    RAMP_POINTS - roi.skip_end.min(RAMP_POINTS) <= falling,
+                    format!(
+                        "{name}: falling fit region runs past the {falling} samples of the falling \
+                         segment"
+                    ),
+                );
+                check(
+                    &mut errors,
+                    fit.roi.skip_start >= falling,
+                    format!(
+                        "{name}: with fit_falling, samples_skip_start must be at least {falling}, \
+                         as the falling segment overwrites the start of the rise"
+                    ),
+                );
+            }
+            if let Some(seed) = laser.seed_control.as_ref() {
+                check(
+                    &mut errors,
+                    seed.timeout_sec > 0.0 && seed.loop_cycle_sec > 0.0,
+                    format!("{name}: seed_control timeout_sec and loop_cycle_sec must be positive"),
+                );
+            }
+        }
+
+        let mut dnas = BTreeMap::new();
+        for (name, board) in &self.boards {
+            errors.extend(self.validate_board(name, board));
+            if let Some(dna) = board.dna.as_ref() {
+                match board.dna() {
+                    Some(x) => {
+                        if let Some(other) = dnas.insert(x, name) {
+                            errors.push(format!("{name}: dna {dna} is also used by {other}"));
+                        }
+                    }
+                    None => errors.push(format!("{name}: invalid dna \"{dna}\"")),
+                }
+            }
+        }
+
+        let mut leds = StatusLeds::new();
+        for (pin_name, led) in &self.leds {
+            match dpin::Pin::from_str(pin_name) {
+                Ok(pin) => check(
+                    &mut errors,
+                    leds.assign(pin, led.indicator, led.pattern).is_ok(),
+                    format!("leds: {pin_name} is not an LED"),
+                ),
+                Err(()) => errors.push(format!("leds: invalid LED {pin_name}")),
+            }
+        }
+        errors
+    }
+
+    fn validate_board(&self, name: &str, board: &BoardSection) -> Vec<String> {
+        let mut errors = Vec::new();
+        check(
+            &mut errors,
+            self.lasers.contains_key(&board.slave_laser),
+            format!("{name}: no section for slave_laser {}", board.slave_laser),
+        );
+        check(
+            &mut errors,
+            !board.is_master || board.ref_output_channel.is_some(),
+            format!("{name}: the master needs a ref_output_channel"),
+        );
+        check(
+            &mut errors,
+            board.ref_input_channel as u32 != board.slave_input_channel as u32,
+            format!("{name}: ref_input_channel and slave_input_channel are the same"),
+        );
+        if board.is_master {
+            check(
+                &mut errors,
+                board.ref_output_channel.map(|x| x as u32)
+                    != Some(board.slave_output_channel as u32),
+                format!("{name}: ref_output_channel and slave_output_channel are the same"),
+            );
+        }
+
+        // The DAC puts out +/-1 V, which the preamp turns into `gain * (raw + hw_offset)`.
+        for ch in [Channel::CH_1, Channel::CH_2] {
+            let out = board.output(ch);
+            let ch = channel_name(ch);
+            if out.preamp_gain <= 0.0 || out.min_v >= out.max_v {
+                check(
+                    &mut errors,
+                    false,
+                    format!(
+                        "{name}: {ch} needs a positive preamp_gain and min_output_v < max_output_v"
+                    ),
+                );
+                continue;
+            }
+            let (raw_min, raw_max) = (
+                out.min_v / out.preamp_gain - out.hw_offset_v,
+                out.max_v / out.preamp_gain - out.hw_offset_v,
+            );
+            check(
+                &mut errors,
+                raw_min >= -1.0 - 1e-6 && raw_max <= 1.0 + 1e-6,
+                format!(
+                    "{name}: {ch} output range [{}, {}] V needs DAC outputs [{raw_min}, {raw_max}] \
+                     V, beyond +/-1 V",
+                    out.min_v, out.max_v
+                ),
+            );
+        }
+        for ch in [Channel::CH_1, Channel::CH_2] {
+            let (_, attenuation) = board.input(ch);
+            check(
+                &mut errors,
+                attenuation > 0.0,
+                format!(
+                    "{name}: {}_input_attenuation must be positive",
+                    channel_name(ch)
+                ),
+            );
+            if let Averaging::Block(n) | Averaging::Sliding(n) = board.averaging(ch) {
+                check(
+                    &mut errors,
+                    n >= 1,
+                    format!("{name}: {}_averaging needs traces >= 1", channel_name(ch)),
+                );
+            }
+        }
+
+        // the ramp swings `amplitude` either side of its offset
+        if let (true, Some(ch)) = (board.is_master, board.ref_output_channel) {
+            let out = board.output(ch);
+            let max_ampl = self
+                .ramp
+                .amplitude_control
+                .as_ref()
+                .map_or(self.ramp.amplitude_volts, |x| {
+                    x.max_v.max(self.ramp.amplitude_volts)
+                });
+            check(
+                &mut errors,
+                2.0 * max_ampl <= out.max_v - out.min_v,
+                format!(
+                    "{name}: a ramp amplitude of {max_ampl} V doesn't fit in the [{}, {}] V \
+                     range of {}",
+                    out.min_v,
+                    out.max_v,
+                    channel_name(ch)
+                ),
+            );
+        }
+        for (key, park_v, ch) in [
+            ("ref_park_v", board.ref_park_v, board.ref_output_channel),
+            (
+                "slave_park_v",
+                board.slave_park_v,
+                Some(board.slave_output_channel),
+            ),
+        ] {
+            if let (Some(v), Some(ch)) = (park_v, ch) {
+                let out = board.output(ch);
+                check(
+                    &mut errors,
+                    (out.min_v..=out.max_v).contains(&v),
+                    format!(
+                        "{name}: {key} {v} V outside the [{}, {}] V range of {}",
+                        out.min_v,
+                        out.max_v,
+                        channel_name(ch)
+                    ),
+                );
+            }
+        }
+        for (key, slow) in [
+            ("ref_slow_output", board.ref_slow_output.as_ref()),
+            ("slave_slow_output", board.slave_slow_output.as_ref()),
+        ] {
+            if let Some(slow) = slow {
+                check(
+                    &mut errors,
+                    slow.pin.is_output(),
+                    format!("{name}: {key} pin {:?} is not an analog output", slow.pin),
+                );
+                check(
+                    &mut errors,
+                    slow.max_step_v >= 0.0,
+                    format!("{name}: {key} max_step_v must be non-negative"),
+                );
+            }
+        }
+        for pin in &board.monitor_inputs {
+            check(
+                &mut errors,
+                !pin.is_output(),
+                format!("{name}: monitor input {pin:?} is not an analog input"),
+            );
+        }
+        errors
+    }
+}
+
+impl FromStr for Config {
+    type Err = ConfigErrors;
+    /// Like `Config::parse`, but dropping the warnings.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Config::parse(s).map(|(config, _)| config)
+    }
+}
+
+/// A small config that parses, for tests to start from: the required keys of the shared
+/// sections, a slave laser `las_1114`, and then `test_board("rp-1")`. The board section comes
+/// last, so keys appended to the text go into it.
+#[cfg(test)]
+pub(crate) fn minimal_config() -> String {
+    let shared = r#"[general]
+master_external_trigger_output_pin = "DIO6_P"
+ready_to_acquire_pin = "DIO7_P"
+logs_port = 8080
+command_port = 8081
+
+[ramp]
+piezo_scale_factor = 3474.9457343334234
+piezo_settle_time_ms = 50.0
+amplitude_volts = 1.0 # either side of the offset
+decimation_factor = 16
+symmetry_factor = 0.8
+
+[multifit]
+samples_skip_start = 6500
+samples_skip_end = 10
+skip_rate = 40
+max_iterations = 256
+xtol = 1.0e-6
+gtol = 1.0e-8
+ftol = 1.0e-8
+max_av_ratio = 10.0
+low_contrast_threshold = 0.01
+
+[ref_laser]
+wavelength_nm = 1550.0
+gain_p = 0.001
+gain_i = 0.003
+gain_d = 0.0
+integral_decay_rate = 0.85
+feedback_max_step_size_v = 0.01
+
+[las_1114]
+wavelength_nm = 1114.0
+gain_p = 0.001
+gain_i = 0.003
+gain_d = 0.0
+integral_decay_rate = 0.85
+feedback_max_step_size_v = 0.01
+"#;
+    format!("{shared}\n{}", test_board("rp-1"))
+}
+
+/// The section of a master board `name` locking `las_1114`, on `CH_1` (reference) and `CH_2`.
+#[cfg(test)]
+pub(crate) fn test_board(name: &str) -> String {
+    format!(
+        r#"[{name}]
+is_master = true
+slave_laser = "las_1114"
+ref_input_channel = "CH_1"
+ref_output_channel = "CH_1"
+slave_input_channel = "CH_2"
+slave_output_channel = "CH_2"
+ch_1_out_hardware_offset_volts = 1.0
+ch_1_min_output_v = 0.0
+ch_1_max_output_v = 5.0
+ch_1_preamp_gain = 2.5
+ch_2_out_hardware_offset_volts = 1.0
+ch_2_min_output_v = 0.0
+ch_2_max_output_v = 5.0
+ch_2_preamp_gain = 2.5
+"#
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn example_config_and_error_report() {
+        let text = std::fs::read_to_string("example/config.toml").unwrap();
+        let (config, warnings) = Config::parse(&text).unwrap();
+        assert!(warnings.is_empty(), "{warnings:?}");
+        assert_eq!(config.slave_laser("jmdsp7-arch").unwrap().0, "las_1114");
+        assert_eq!(config.lasers["las_1114"].fit.roi.skip_rate, 30);
+        assert_eq!(config.ref_laser.fit.roi.skip_rate, 40);
+
+        let misspelled = text.replace("logs_port = 8080", "logs_port = 8080\nlogs_prot = 1");
+        let (_, warnings) = Config::parse(&misspelled).unwrap();
+        assert_eq!(
+            warnings,
+            ["unknown config key general:logs_prot is ignored".to_string()]
+        );
+
+        let broken = misspelled
+            .replace("decimation_factor = 16", "decimation_factor = 12")
+            .replace("ch_2_max_output_v = 5.0", "ch_2_max_output_v = 9.0")
+            .replace("skip_rate = 30", "skip_rate = \"30\"");
+        let errors = Config::parse(&broken).unwrap_err().0;
+        // the bad skip_rate stops las_1114 from parsing, so the board's reference to it fails
+        // too; the other two are range checks
+        assert_eq!(errors.len(), 4, "{errors:?}");
+        assert!(errors.iter().any(|e| e.contains("decimation_factor 12")));
+        assert!(errors.iter().any(|e| e.contains("ch_2 output range")));
+        assert!(errors.iter().any(|e| e.starts_with("[las_1114]")));
+
+        // one sample left is reported, rather than reaching `ScopeRegion::new`
+        let errors = minimal_config()
+            .replace("samples_skip_start = 6500", "samples_skip_start = 16373")
+            .parse::<Config>()
+            .unwrap_err()
+            .0;
+        assert_eq!(errors.len(), 2, "{errors:?}");
+        assert!(errors.iter().all(|e| e.ends_with("leaves too few to fit")));
+    }
+}
@@ -6,48 +6,50 @@
 )]
 
 use gethostname::gethostname;
-use std::str::FromStr;
-use toml;
+use std::path::Path;
+use std::time::Duration;
 
+use librp_sys::calibration::Calibration;
+use librp_sys::core;
+use librp_sys::dpin;
 use librp_sys::dpin::DigitalPin;
 use librp_sys::generator::Generator;
-use librp_sys::oscilloscope::Oscilloscope;
-use librp_sys::{core, dpin};
+use librp_sys::oscilloscope::{ChannelScaling, Oscilloscope, ScopeUnits};
 
 use crate::multifit::FitSetup;
 
+use super::amplitude_control::AmplitudeControl;
+use super::config_model::{Config, Fit, LaserSection, Roi, SlowOutputConfig};
+use super::falling::FallingSegment;
+use super::fault::FaultMonitor;
 use super::laser::Laser;
 use super::lock::Servo;
 use super::ramp::DaqSetup;
+use super::ramp_shape::RampShape;
+use super::scheduler::Scheduler;
+use super::seed::SeedMonitor;
+use super::shutdown::Parking;
+use super::slow_io::{MonitorInput, SlowFeedback};
+use super::state::StateStore;
+use super::status_leds::StatusLeds;
+use super::wavelength_meter::WavelengthMeter;
 use super::{communications::InterfComms, interferometer::Interferometer};
 
-macro_rules! tomlget {
-    ($cfg:ident, $sec:expr, $key:expr, $conv:ident, $as:ty) => {
-        $cfg.get($sec)
-            .ok_or_else(|| format!("failed to get section {}", $sec))?
-            .get($key)
-            .ok_or_else(|| format!("failed to get key {}:{}", $sec, $key))?
-            .$conv()
-            .ok_or_else(|| format!("failed to convert {}:{} to {}", $sec, $key, stringify!($as)))?
-            as $as
-    };
-    ($cfg:ident, $sec:expr, $key:expr, as_str) => {
-        $cfg.get($sec)
-            .ok_or_else(|| format!("failed to get section {}", $sec))?
-            .get($key)
-            .ok_or_else(|| format!("failed to get key {}:{}", $sec, $key))?
-            .as_str()
-            .ok_or_else(|| format!("failed to convert {}:{} to string", $sec, $key))?
-    };
-    ($cfg:ident, $sec:expr, $key:expr, as_bool) => {
-        $cfg.get($sec)
-            .ok_or_else(|| format!("failed to get section {}", $sec))?
-            .get($key)
-            .ok_or_else(|| format!("failed to get key {}:{}", $sec, $key))?
-            .as_bool()
-            .ok_or_else(|| format!("failed to convert {}:{} to boolean", $sec, $key))?
-    };
+/// Name of the config section holding this board's settings. If some section has a `dna` key
+/// matching the board's FPGA DNA (as a hex string, e.g. `dna = "0x0012345678abcdef"`), that
+/// section is used; otherwise we fall back on the section named after the hostname.
+pub fn board_section(cfg: &Config, dna: Option<u64>) -> Result<String, String> {
+    if let Some(dna) = dna {
+        // DNAs are checked to be unique when the config is parsed
+        if let Some((name, _)) = cfg.boards.iter().find(|(_, x)| x.dna() == Some(dna)) {
+            return Ok(name.clone());
+        }
+    }
+    gethostname()
+        .into_string()
+        .map_err(|_| "failed to get hostname".to_string())
 }
+
 pub fn floor_exp(num: u64) -> u8 {
     let mut exp = 63;
     while (1 << exp) & num == 0 {
@@ -56,73 +58,37 @@ pub fn floor_exp(num: u64) -> u8 {
     exp
 }
 
-pub fn generator_from_config(cfg: &toml::Value, gen: &mut Generator) -> Result<(), String> {
-    let hostname = gethostname()
-        .into_string()
-        .map_err(|_| "failed to get hostname")?;
-    let hostname = hostname.as_str();
-    gen.ch_a.set_hw_offset_v(tomlget!(
-        cfg,
-        hostname,
-        "ch_1_out_hardware_offset_volts",
-        as_float,
-        f32
-    ));
-    gen.ch_a
-        .set_gain_post(tomlget!(cfg, hostname, "ch_1_preamp_gain", as_float, f32));
-    gen.ch_a.set_output_range(
-        tomlget!(cfg, hostname, "ch_1_min_output_v", as_float, f32),
-        tomlget!(cfg, hostname, "ch_1_max_output_v", as_float, f32),
-    );
-    gen.ch_a
-        .set_trigger_source(librp_sys::generator::GenTriggerSource::ExternalRisingEdge);
-    // gen.ch_a.enable();
-    gen.ch_b.set_hw_offset_v(tomlget!(
-        cfg,
-        hostname,
-        "ch_2_out_hardware_offset_volts",
-        as_float,
-        f32
-    ));
-    gen.ch_b
-        .set_gain_post(tomlget!(cfg, hostname, "ch_2_preamp_gain", as_float, f32));
-    gen.ch_b.set_output_range(
-        tomlget!(cfg, hostname, "ch_2_min_output_v", as_float, f32),
-        tomlget!(cfg, hostname, "ch_2_max_output_v", as_float, f32),
-    );
-    gen.ch_b
-        .set_trigger_source(librp_sys::generator::GenTriggerSource::ExternalRisingEdge);
-    // gen.ch_b.enable();
+pub fn generator_from_config(cfg: &Config, board: &str, gen: &mut Generator) -> Result<(), String> {
+    let section = cfg.board(board)?;
+    for (ch, out) in [
+        (&mut gen.ch_a, section.output(core::Channel::CH_1)),
+        (&mut gen.ch_b, section.output(core::Channel::CH_2)),
+    ] {
+        ch.set_hw_offset_v(out.hw_offset_v);
+        ch.set_gain_post(out.preamp_gain);
+        ch.set_output_range(out.min_v, out.max_v);
+        ch.set_trigger_source(librp_sys::generator::GenTriggerSource::ExternalRisingEdge);
+        // ch.enable();
+    }
     Ok(())
 }
 
-pub fn dpin_get_ready_pin(cfg: &toml::Value) -> Result<dpin::Pin, String> {
-    dpin::Pin::from_str(tomlget!(cfg, "general", "ready_to_acquire_pin", as_str))
-        .map_err(|_| "failed to convert to pin".to_string())
+pub fn dpin_get_ready_pin(cfg: &Config) -> dpin::Pin {
+    cfg.general.ready_to_acquire_pin
 }
-pub fn dpin_get_trigger_pin(cfg: &toml::Value) -> Result<dpin::Pin, String> {
-    dpin::Pin::from_str(tomlget!(
-        cfg,
-        "general",
-        "master_external_trigger_output_pin",
-        as_str
-    ))
-    .map_err(|_| "failed to convert to pin".to_string())
+pub fn dpin_get_trigger_pin(cfg: &Config) -> dpin::Pin {
+    cfg.general.master_external_trigger_output_pin
 }
 
-pub fn dpin_from_config(cfg: &toml::Value, dpin: &mut DigitalPin) -> Result<(), String> {
-    let hostname = gethostname()
-        .into_string()
-        .map_err(|_| "failed to get hostname")?;
-    let hostname = hostname.as_str();
-    let is_master = tomlget!(cfg, hostname, "is_master", as_bool);
+pub fn dpin_from_config(cfg: &Config, board: &str, dpin: &mut DigitalPin) -> Result<(), String> {
+    let is_master = cfg.board(board)?.is_master;
     dpin.set_all_input().expect("RP API call failure");
     if is_master {
-        dpin.set_direction(dpin_get_trigger_pin(cfg)?, dpin::PinDirection::Out)
+        dpin.set_direction(dpin_get_trigger_pin(cfg), dpin::PinDirection::Out)
             .expect("RP API call failure");
     };
     dpin.set_direction(
-        dpin_get_ready_pin(cfg)?,
+        dpin_get_ready_pin(cfg),
         if is_master {
             dpin::PinDirection::In
         } else {
@@ -141,15 +107,52 @@ pub fn dpin_from_config(cfg: &toml::Value, dpin: &mut DigitalPin) -> Result<(),
     Ok(())
 }
 
-pub fn scope_from_config(cfg: &toml::Value, scope: &mut Oscilloscope) -> Result<(), String> {
-    scope.set_roi(
-        tomlget!(cfg, "multifit", "samples_skip_start", as_integer, usize),
-        tomlget!(cfg, "multifit", "samples_skip_end", as_integer, usize),
-        tomlget!(cfg, "multifit", "skip_rate", as_integer, usize),
-    );
+/// LEDs are assigned in the optional `[leds]` section, either as `LED_0 = "ref_lock"` (lit solid
+/// while active) or as `LED_5 = {indicator = "saturation", pattern = "blink_fast"}`.
+pub fn leds_from_config(cfg: &Config) -> Result<StatusLeds, String> {
+    let mut out = StatusLeds::new();
+    for (pin_name, led) in &cfg.leds {
+        let pin = pin_name
+            .parse::<dpin::Pin>()
+            .map_err(|_| format!("invalid LED {pin_name}"))?;
+        out.assign(pin, led.indicator, led.pattern)
+            .map_err(|()| format!("{pin_name} is not an LED"))?;
+    }
+    Ok(out)
+}
+
+pub fn scope_from_config(
+    cfg: &Config,
+    board: &str,
+    calibration: &Calibration,
+    scope: &mut Oscilloscope,
+) -> Result<(), String> {
+    let section = cfg.board(board)?;
+    scope.set_units(cfg.general.scope_units.unwrap_or(ScopeUnits::Volts));
+    for ch in [core::Channel::CH_1, core::Channel::CH_2] {
+        // jumper position (`ch_N_input_gain = "LV"|"HV"`) and any external attenuation in front
+        // of the input (`ch_N_input_attenuation`)
+        let (gain, attenuation) = section.input(ch);
+        scope.set_scaling(
+            ch,
+            ChannelScaling::calibrated(calibration.front_end(ch), gain, attenuation),
+        );
+        scope.set_averaging(ch, section.averaging(ch));
+    }
+    let (_, slave_laser) = cfg.slave_laser(board)?;
+    for (ch, laser) in [
+        (section.ref_input_channel, &cfg.ref_laser),
+        (section.slave_input_channel, slave_laser),
+    ] {
+        let roi = laser.fit.roi;
+        scope.set_roi_channel(ch, roi.skip_start, roi.skip_end, roi.skip_rate);
+        if let Some(roi) = laser.fit.falling_roi {
+            scope.set_roi_falling_channel(ch, roi.skip_start, roi.skip_end, roi.skip_rate);
+        }
+    }
     // NOTE: ramp::apply() also sets the decimation, waveform; we may be needlessly duplicating logic here
     scope
-        .set_decimation(tomlget!(cfg, "ramp", "decimation_factor", as_integer, u32))
+        .set_decimation(cfg.ramp.decimation_factor)
         .expect("RP API call failure");
     scope.set_trigger_delay(8192).expect("RP API call failure");
     scope
@@ -159,240 +162,389 @@ pub fn scope_from_config(cfg: &toml::Value, scope: &mut Oscilloscope) -> Result<
     Ok(())
 }
 
-pub async fn comms_from_config(cfg: &toml::Value) -> Result<InterfComms, String> {
-    let mut out = InterfComms::new().ok_or("failed to instantiate comms struct")?;
-    out.bind_sockets(
-        tomlget!(cfg, "general", "logs_port", as_integer, u16),
-        tomlget!(cfg, "general", "command_port", as_integer, u16),
-    )
-    .await
-    .map_err(|e| format!("error [{}] in binding sockets", e))?;
-    Ok(out)
+/// Timing of the acquisition cycle's waits; all keys in `[general]` are optional.
+#[must_use]
+pub fn scheduler_from_config(cfg: &Config) -> Scheduler {
+    let general = &cfg.general;
+    let mut out = Scheduler::new();
+    if let Some(ms) = general.handshake_timeout_ms {
+        out.handshake_timeout = Duration::from_millis(ms);
+    }
+    if let Some(ms) = general.trigger_timeout_ms {
+        out.trigger_timeout = Duration::from_millis(ms);
+    }
+    if let Some(us) = general.poll_interval_us {
+        out.poll_interval = Duration::from_micros(us);
+    }
+    if let Some(us) = general.deadline_spin_us {
+        out.spin = Duration::from_micros(us);
+    }
+    out
 }
 
-pub fn ramp_from_config(cfg: &toml::Value) -> Result<DaqSetup, String> {
-    let mut out = DaqSetup::new();
-    out.amplitude(tomlget!(cfg, "ramp", "amplitude_volts", as_float, f32));
-    out.piezo_settle_time_ms(tomlget!(cfg, "ramp", "piezo_settle_time_ms", as_float, f32));
-    out.piezo_scale_factor(tomlget!(cfg, "ramp", "piezo_scale_factor", as_float, f32));
-    let dec = tomlget!(cfg, "ramp", "decimation_factor", as_integer, u32);
-    let dec_factor;
-    if dec == 1 {
-        dec_factor = 1;
-    } else if dec < 4 {
-        dec_factor = 2;
-    } else if dec < 8 {
-        dec_factor = 4;
-    } else if dec < 16 {
-        dec_factor = 8;
-    } else {
-        dec_factor = dec;
+/// How timeouts in the acquisition cycle turn into a fault; both keys in `[general]` are optional.
+#[must_use]
+pub fn fault_from_config(cfg: &Config) -> FaultMonitor {
+    let mut out = FaultMonitor::new();
+    if let Some(n) = cfg.general.timeouts_to_fault {
+        out.timeouts_to_fault = n.max(1);
     }
-    if dec != dec_factor {
-        eprintln!("Decimation factor specified in config file as {}. Valid decimation factors are 1, 2, 4, 8, or any value between 16 and 65536. Proceeding with decimation factor of {}", dec, dec_factor);
+    if let Some(ms) = cfg.general.fault_retry_interval_ms {
+        out.retry_interval = Duration::from_millis(ms);
     }
-    out.set_decimation(dec_factor);
-
-    out.set_symmetry(tomlget!(cfg, "ramp", "symmetry_factor", as_float, f32).clamp(0.01, 0.99));
+    out
+}
 
+/// Parking voltages for the outputs on shutdown are per board (`ref_park_v`, `slave_park_v`),
+/// while the slew rate is in `[general]`. All keys are optional.
+pub fn parking_from_config(cfg: &Config, board: &str) -> Result<Parking, String> {
+    let section = cfg.board(board)?;
+    let mut out = Parking::new();
+    out.ref_park_v = section.ref_park_v;
+    out.slave_park_v = section.slave_park_v;
+    if let Some(rate) = cfg.general.park_slew_rate_v_per_s {
+        out.slew_rate_v_per_s = rate.abs();
+    }
     Ok(out)
 }
 
+/// Reads the optional `[general]` keys `state_file` (relative to `base_dir`, empty to not keep any
+/// state), `state_save_interval_s` (0 to only save on shutdown) and `restore_state`. Named
+/// snapshots go in `base_dir` too.
 #[must_use]
-fn buff_size_exponent(cfg: &toml::Value) -> usize {
-    // error buffers are of length 2^n, so we check if a valid exponent has been provided. If not, then we see if they've provided an explicit length, and round it down to a power of 2. If neither of those works, then we'll just use a default length of 1024 items.
-    if let Some(exponent) = cfg
-        .get("general")
-        .and_then(|x| x.get("pitaya_log_length_exponent"))
-        .and_then(toml::Value::as_integer)
-    {
-        exponent as usize
-    } else if let Some(length) = cfg
-        .get("general")
-        .and_then(|x| x.get("pitaya_log_length"))
-        .and_then(toml::Value::as_integer)
-    {
-        let buffer_size_exponent = (length as f32).log2().floor() as usize;
-        eprintln!(
-            "WARN: config explicit log length parameter {} rounded down to 2^{} = {}.",
-            length,
-            buffer_size_exponent,
-            1 << buffer_size_exponent,
-        );
-        buffer_size_exponent
-    } else {
-        let buffer_size_exponent = 10;
-        eprintln!(
-            "WARN: no log length parameter found in configuration file, using default of {}",
-            2u32.pow(buffer_size_exponent as u32)
-        );
-        buffer_size_exponent
+pub fn state_store_from_config(cfg: &Config, base_dir: &Path) -> StateStore {
+    let general = &cfg.general;
+    let mut out = StateStore::new(base_dir.to_path_buf());
+    out.file = match general.state_file.as_deref() {
+        Some("") => None,
+        Some(file) => Some(base_dir.join(file)),
+        None => Some(base_dir.join("rusterf_state.toml")),
+    };
+    // checked to be finite and non-negative when the config is parsed
+    out.save_interval = Some(general.state_save_interval_s.unwrap_or(60.0))
+        .filter(|x| *x > 0.0)
+        .map(Duration::from_secs_f64);
+    out.restore_on_startup = general.restore_state.unwrap_or(true);
+    out
+}
+
+pub async fn comms_from_config(cfg: &Config) -> Result<InterfComms, String> {
+    let mut out = InterfComms::new().ok_or("failed to instantiate comms struct")?;
+    if let Some(cycles) = cfg.general.logs_publish_freq_cycles {
+        out.set_log_publish_frequency(cycles.max(1));
     }
+    out.bind_sockets(cfg.general.logs_port, cfg.general.command_port)
+        .await
+        .map_err(|e| format!("error [{}] in binding sockets", e))?;
+    Ok(out)
 }
 
-pub fn ref_laser_from_config(cfg: &toml::Value) -> Result<Laser, String> {
-    let hostname = gethostname()
-        .into_string()
-        .map_err(|_| "failed to get hostname")?;
-    let hostname = hostname.as_str();
-    let is_master = tomlget!(cfg, hostname, "is_master", as_bool);
+/// The optional `[ramp]` key `shape` is one of "linear_cosine" (the default), "triangle", "sine",
+/// "raised_cosine_linear" (with optional `edge_fraction`), or "custom", in which case
+/// `custom_waveform` gives the path of a file of comma- or newline-separated values.
+fn ramp_shape_from_config(cfg: &Config) -> Result<RampShape, String> {
+    let ramp = &cfg.ramp;
+    let name = match ramp.shape.as_deref() {
+        Some(x) => x,
+        None => return Ok(RampShape::LinearCosine),
+    };
+    match name {
+        "custom" => {
+            let path = ramp
+                .custom_waveform
+                .as_deref()
+                .ok_or("failed to get key ramp:custom_waveform")?;
+            let text = std::fs::read_to_string(path)
+                .map_err(|e| format!("failed to read custom waveform {path}: {e}"))?;
+            RampShape::custom_from_str(&text)
+                .ok_or_else(|| format!("invalid custom waveform in {path}"))
+        }
+        "raised_cosine_linear" => Ok(RampShape::RaisedCosineLinear {
+            edge_fraction: ramp.edge_fraction.unwrap_or(0.05),
+        }),
+        _ => name
+            .parse()
+            .map_err(|()| format!("invalid ramp shape {name}")),
+    }
+}
 
-    let buffer_size_exponent = buff_size_exponent(cfg);
+pub fn ramp_from_config(cfg: &Config) -> Result<DaqSetup, String> {
+    let ramp = &cfg.ramp;
+    let mut out = DaqSetup::new();
+    out.amplitude(ramp.amplitude_volts);
+    out.piezo_settle_time_ms(ramp.piezo_settle_time_ms);
+    out.piezo_scale_factor(ramp.piezo_scale_factor);
+    // checked to be one of 1, 2, 4, 8 or 16 to 65536 when the config is parsed
+    out.set_decimation(ramp.decimation_factor);
+    out.set_symmetry(ramp.symmetry_factor);
+    out.set_fit_falling(ramp.fit_falling);
+    out.set_shape(ramp_shape_from_config(cfg)?);
+    if let Some(coeffs) = ramp.predistortion.as_ref() {
+        out.set_predistortion(coeffs.clone());
+    }
+    Ok(out)
+}
 
-    let mut out = Laser::new(buffer_size_exponent).ok_or("failed to instantiate laser struct")?;
-    out.set_wavelength(
-        tomlget!(cfg, "ref_laser", "wavelength_nm", as_float, f32),
-        tomlget!(cfg, "ramp", "piezo_scale_factor", as_float, f32),
-        tomlget!(cfg, "ramp", "amplitude_volts", as_float, f32),
-    );
-    match tomlget!(cfg, hostname, "ref_input_channel", as_str) {
-        // match cfg.get(hostname)?.get("ref_input_channel")?.as_str()? {
-        "CH_1" | "CH_A" => out.input_channel = core::Channel::CH_1,
-        "CH_2" | "CH_B" => out.input_channel = core::Channel::CH_2,
-        _ => {
-            return Err("No valid input channel for reference laser found".to_string());
+/// A laser may optionally offload its fast feedback onto a slow analog output, configured in the
+/// host section as e.g. `slave_slow_output = {pin = "AOUT0", gain = 0.01, max_step_v = 0.001}`.
+/// The fast output is held near `center_v`, which defaults to the middle of that channel's range.
+fn slow_feedback_from_config(
+    cfg: &Config,
+    board: &str,
+    slow: Option<&SlowOutputConfig>,
+    fast_channel: Option<core::Channel>,
+) -> Result<Option<SlowFeedback>, String> {
+    let slow = match slow {
+        Some(x) => x,
+        None => return Ok(None),
+    };
+    let center_v = match (slow.center_v, fast_channel) {
+        (Some(v), _) => v,
+        (None, Some(ch)) => {
+            let out = cfg.board(board)?.output(ch);
+            (out.min_v + out.max_v) / 2.0
         }
+        (None, None) => return Err(format!("no center_v for slow output {:?}", slow.pin)),
     };
-    if is_master {
-        match tomlget!(cfg, hostname, "ref_output_channel", as_str) {
-            // match cfg.get(hostname)?.get("ref_output_channel")?.as_str()? {
-            "CH_1" | "CH_A" => out.output_channel = Some(core::Channel::CH_1),
-            "CH_2" | "CH_B" => out.output_channel = Some(core::Channel::CH_2),
-            _ => {
-                return Err("No valid output channel for reference laser found".to_string());
-            }
-        };
+    Ok(Some(SlowFeedback::new(
+        slow.pin,
+        slow.gain,
+        slow.max_step_v,
+        center_v,
+    )))
+}
+
+/// Slow analog inputs listed under `monitor_inputs` in the host section are sampled every cycle
+/// and logged alongside the phase logs.
+pub fn monitors_from_config(cfg: &Config, board: &str) -> Result<Vec<MonitorInput>, String> {
+    let buffer_size_exponent = cfg.log_length_exponent();
+    cfg.board(board)?
+        .monitor_inputs
+        .iter()
+        .map(|pin| {
+            MonitorInput::new(*pin, buffer_size_exponent)
+                .ok_or_else(|| "failed to instantiate monitor input log".to_string())
+        })
+        .collect()
+}
+
+fn laser_from_config(cfg: &Config, laser: &LaserSection) -> Result<Laser, String> {
+    let mut out =
+        Laser::new(cfg.log_length_exponent()).ok_or("failed to instantiate laser struct")?;
+    out.set_wavelength(
+        laser.wavelength_nm,
+        cfg.ramp.piezo_scale_factor,
+        cfg.ramp.amplitude_volts,
+    );
+    Ok(out)
+}
+
+pub fn ref_laser_from_config(cfg: &Config, board: &str) -> Result<Laser, String> {
+    let section = cfg.board(board)?;
+    let mut out = laser_from_config(cfg, &cfg.ref_laser)?;
+    out.input_channel = section.ref_input_channel;
+    out.averaging = section.averaging(out.input_channel);
+    if section.is_master {
+        out.output_channel = Some(
+            section
+                .ref_output_channel
+                .ok_or("No valid output channel for reference laser found")?,
+        );
+        out.slow_feedback = slow_feedback_from_config(
+            cfg,
+            board,
+            section.ref_slow_output.as_ref(),
+            out.output_channel,
+        )?;
     } else {
         out.output_channel = None;
     }
 
     // fill in ``guess'' fit coefficients for the lasers
-    out.fit_coefficients = [0.0, out.fringe_freq(), 0.0, 1000.0];
+    out.fit_coefficients = [0.0, out.fringe_freq(), 0.0, 0.0];
     Ok(out)
 }
-pub fn slave_laser_from_config(cfg: &toml::Value) -> Result<Laser, String> {
-    let hostname = gethostname()
-        .into_string()
-        .map_err(|_| "failed to get hostname")?;
-    let hostname = hostname.as_str();
-    let slave_laser_name = tomlget!(cfg, hostname, "slave_laser", as_str);
-    let buffer_size_exponent = buff_size_exponent(cfg);
-
-    let mut out = Laser::new(buffer_size_exponent).ok_or("failed to instantiate laser struct")?;
-    out.set_wavelength(
-        tomlget!(cfg, slave_laser_name, "wavelength_nm", as_float, f32),
-        tomlget!(cfg, "ramp", "piezo_scale_factor", as_float, f32),
-        tomlget!(cfg, "ramp", "amplitude_volts", as_float, f32),
-    );
-    match tomlget!(cfg, hostname, "slave_input_channel", as_str) {
-        // match cfg.get(hostname)?.get("ref_input_channel")?.as_str()? {
-        "CH_1" | "CH_A" => out.input_channel = core::Channel::CH_1,
-        "CH_2" | "CH_B" => out.input_channel = core::Channel::CH_2,
-        _ => {
-            return Err("No valid input channel for reference laser found".to_string());
-        }
-    };
-    match tomlget!(cfg, hostname, "slave_output_channel", as_str) {
-        // match cfg.get(hostname)?.get("ref_output_channel")?.as_str()? {
-        "CH_1" | "CH_A" => out.output_channel = Some(core::Channel::CH_1),
-        "CH_2" | "CH_B" => out.output_channel = Some(core::Channel::CH_2),
-        _ => {
-            return Err("No valid output channel for reference laser found".to_string());
-        }
-    };
+pub fn slave_laser_from_config(cfg: &Config, board: &str) -> Result<Laser, String> {
+    let section = cfg.board(board)?;
+    let mut out = laser_from_config(cfg, cfg.slave_laser(board)?.1)?;
+    out.input_channel = section.slave_input_channel;
+    out.averaging = section.averaging(out.input_channel);
+    out.output_channel = Some(section.slave_output_channel);
+    out.slow_feedback = slow_feedback_from_config(
+        cfg,
+        board,
+        section.slave_slow_output.as_ref(),
+        out.output_channel,
+    )?;
 
     // fill in ``guess'' fit coefficients for the lasers
-    out.fit_coefficients = [0.0, out.fringe_freq(), 0.0, 1000.0];
+    out.fit_coefficients = [0.0, out.fringe_freq(), 0.0, 0.0];
     Ok(out)
 }
 
-pub fn ref_lock_from_config(cfg: &toml::Value) -> Result<Servo, String> {
-    let hostname = gethostname()
-        .into_string()
-        .map_err(|_| "failed to get hostname")?;
-    let hostname = hostname.as_str();
-    let is_master = tomlget!(cfg, hostname, "is_master", as_bool);
+fn lock_from_config(laser: &LaserSection) -> Servo {
     let mut out = Servo::new();
-    if is_master {
-        out.gain_P = tomlget!(cfg, "ref_laser", "gain_p", as_float, f32);
-        out.gain_I = tomlget!(cfg, "ref_laser", "gain_i", as_float, f32);
-        out.gain_D = tomlget!(cfg, "ref_laser", "gain_d", as_float, f32);
-        out.set_alpha_I(tomlget!(
-            cfg,
-            "ref_laser",
-            "integral_decay_rate",
-            as_float,
-            f32
-        ));
-        out.max_feedback_step_size =
-            tomlget!(cfg, "ref_laser", "feedback_max_step_size_v", as_float, f32);
+    out.gain_P = laser.gain_p;
+    out.gain_I = laser.gain_i;
+    out.gain_D = laser.gain_d;
+    out.set_alpha_I(laser.integral_decay_rate);
+    out.max_feedback_step_size = laser.feedback_max_step_size_v;
+    // the derivative filter coefficient is optional; if it's absent we keep the `Servo` default
+    if let Some(n) = laser.derivative_filter_n {
+        out.deriv_filter_N = n;
     }
-    Ok(out)
+    if let Some(x) = laser.setpoint {
+        out.set_setpoint(x);
+    }
+    out
 }
-pub fn slave_lock_from_config(cfg: &toml::Value) -> Result<Servo, String> {
-    let hostname = gethostname()
-        .into_string()
-        .map_err(|_| "failed to get hostname")?;
-    let hostname = hostname.as_str();
-    let slave_laser_name = tomlget!(cfg, hostname, "slave_laser", as_str);
-    let mut out = Servo::new();
-    out.gain_P = tomlget!(cfg, slave_laser_name, "gain_p", as_float, f32);
-    out.gain_I = tomlget!(cfg, slave_laser_name, "gain_i", as_float, f32);
-    out.gain_D = tomlget!(cfg, slave_laser_name, "gain_d", as_float, f32);
-    out.set_alpha_I(tomlget!(
-        cfg,
-        slave_laser_name,
-        "integral_decay_rate",
-        as_float,
-        f32
-    ));
-    out.max_feedback_step_size = tomlget!(
-        cfg,
-        slave_laser_name,
-        "feedback_max_step_size_v",
-        as_float,
-        f32
+
+pub fn ref_lock_from_config(cfg: &Config, board: &str) -> Result<Servo, String> {
+    Ok(if cfg.board(board)?.is_master {
+        lock_from_config(&cfg.ref_laser)
+    } else {
+        Servo::new()
+    })
+}
+pub fn slave_lock_from_config(cfg: &Config, board: &str) -> Result<Servo, String> {
+    Ok(lock_from_config(cfg.slave_laser(board)?.1))
+}
+
+/// The slave laser's section may hold an inline `seed_control` table; if it does, build a
+/// monitor that tries to restore injection when the slave's signal drops.
+pub fn seed_from_config(cfg: &Config, board: &str) -> Result<Option<SeedMonitor>, String> {
+    let seed_cfg = match cfg.slave_laser(board)?.1.seed_control.as_ref() {
+        Some(x) => x,
+        None => return Ok(None),
+    };
+    let mut out = SeedMonitor::new(
+        seed_cfg.timeout_sec,
+        seed_cfg.loop_cycle_sec,
+        seed_cfg.threshold_volts,
+        seed_cfg.adjustment_size_volts,
     );
-    Ok(out)
+    if let Some(source) = seed_cfg.source {
+        out.source = source;
+    }
+    if let Some(output) = seed_cfg.output {
+        out.output = output;
+    }
+    Ok(Some(out))
+}
+
+/// The master may hold the number of reference fringes per ramp constant by adjusting the ramp
+/// amplitude, configured by an inline table in `[ramp]`, e.g.
+/// `amplitude_control = {target_fringes = 10.0, max_step_v = 0.01, min_v = 0.2, max_v = 2.0}`,
+/// with optional `gain` and `interval_cycles`.
+#[must_use]
+pub fn amplitude_control_from_config(cfg: &Config) -> Option<AmplitudeControl> {
+    let ctrl_cfg = cfg.ramp.amplitude_control.as_ref()?;
+    let mut out = AmplitudeControl::new(
+        ctrl_cfg.target_fringes,
+        ctrl_cfg.max_step_v,
+        ctrl_cfg.min_v,
+        ctrl_cfg.max_v,
+    );
+    if let Some(gain) = ctrl_cfg.gain {
+        out.gain = gain;
+    }
+    if let Some(n) = ctrl_cfg.interval_cycles {
+        out.interval_cycles = n.max(1);
+    }
+    Some(out)
+}
+
+/// The slave laser's section may hold an inline `wavelength_meter` table, e.g.
+/// `wavelength_meter = {cycles = 256, feedback = false, mode_hop_threshold_nm = 0.01}` (all keys
+/// optional), to start measuring the slave wavelength right away; without one, the meter is left
+/// disabled until enabled by command.
+pub fn wavelength_meter_from_config(
+    cfg: &Config,
+    board: &str,
+    meter: &mut WavelengthMeter,
+) -> Result<(), String> {
+    let meter_cfg = match cfg.slave_laser(board)?.1.wavelength_meter.as_ref() {
+        Some(x) => x,
+        None => return Ok(()),
+    };
+    meter.enabled = true;
+    if let Some(n) = meter_cfg.cycles {
+        meter.cycles = n.max(2);
+    }
+    if let Some(x) = meter_cfg.feedback {
+        meter.feedback = x;
+    }
+    if let Some(x) = meter_cfg.mode_hop_threshold_nm {
+        meter.mode_hop_threshold_nm = x.abs();
+    }
+    Ok(())
 }
 
-pub fn multifit_from_config(cfg: &toml::Value) -> Result<FitSetup, String> {
-    let num_points = (16384
-        - tomlget!(cfg, "multifit", "samples_skip_start", as_integer, u32)
-        - tomlget!(cfg, "multifit", "samples_skip_end", as_integer, u32)
-        + tomlget!(cfg, "multifit", "skip_rate", as_integer, u32)
-        - 1)
-        / tomlget!(cfg, "multifit", "skip_rate", as_integer, u32);
+pub fn multifit_from_config(fit: &Fit) -> Result<FitSetup, String> {
+    fit_setup_from_config(fit, fit.roi)
+}
+
+/// Builds the fit for one segment of the ramp, sized to match exactly what the scope will extract
+/// from `roi` of this laser's channel.
+fn fit_setup_from_config(fit: &Fit, roi: Roi) -> Result<FitSetup, String> {
+    let region = roi.region();
     let mut out = FitSetup::init(
-        tomlget!(cfg, "multifit", "skip_rate", as_integer, u32),
-        num_points,
-        tomlget!(cfg, "multifit", "max_iterations", as_integer, u32),
-        tomlget!(cfg, "multifit", "xtol", as_float, f32),
-        tomlget!(cfg, "multifit", "gtol", as_float, f32),
-        tomlget!(cfg, "multifit", "ftol", as_float, f32),
-        tomlget!(cfg, "multifit", "max_av_ratio", as_float, f32),
+        region.skip_rate() as u32,
+        region.num_points() as u32,
+        fit.max_iterations,
+        fit.xtol,
+        fit.gtol,
+        fit.ftol,
+        fit.max_av_ratio,
     )
     .ok_or_else(|| "Failed to instantiate FitSetup struct".to_string())?;
-    out.low_contrast_threshold = tomlget!(cfg, "multifit", "low_contrast_threshold", as_float, f32);
+    out.low_contrast_threshold = fit.low_contrast_threshold;
     Ok(out)
 }
 
-pub fn interferometer_from_config(cfg: &toml::Value) -> Result<Interferometer, String> {
+pub fn interferometer_from_config(cfg: &Config, board: &str) -> Result<Interferometer, String> {
     let mut out = Interferometer::new().ok_or("failed to instantiate interferometer struct")?;
+    let (_, slave_laser) = cfg.slave_laser(board)?;
 
     out.ramp_setup = ramp_from_config(cfg)?;
-    out.ref_laser = ref_laser_from_config(cfg)?;
-    out.slave_laser = slave_laser_from_config(cfg)?;
-    out.ref_lock = ref_lock_from_config(cfg)?;
-    out.slave_lock = slave_lock_from_config(cfg)?;
-    out.fit_setup_ref = multifit_from_config(cfg)?;
-    out.fit_setup_slave = multifit_from_config(cfg)?;
+    out.scheduler = scheduler_from_config(cfg);
+    out.fault = fault_from_config(cfg);
+    out.ref_laser = ref_laser_from_config(cfg, board)?;
+    out.slave_laser = slave_laser_from_config(cfg, board)?;
+    out.ref_lock = ref_lock_from_config(cfg, board)?;
+    out.slave_lock = slave_lock_from_config(cfg, board)?;
+    out.fit_setup_ref = multifit_from_config(&cfg.ref_laser.fit)?;
+    out.fit_setup_slave = multifit_from_config(&slave_laser.fit)?;
+    if let (Some(ref_roi), Some(slave_roi)) =
+        (cfg.ref_laser.fit.falling_roi, slave_laser.fit.falling_roi)
+    {
+        let mut falling = FallingSegment::new(
+            fit_setup_from_config(&cfg.ref_laser.fit, ref_roi)?,
+            fit_setup_from_config(&slave_laser.fit, slave_roi)?,
+            cfg.log_length_exponent(),
+        )
+        .ok_or("failed to instantiate falling segment fits")?;
+        falling.reset_guesses(
+            out.ref_laser.fringe_freq(),
+            out.slave_laser.fringe_freq(),
+            out.ramp_setup.symmetry(),
+        );
+        out.falling = Some(falling);
+    }
+    if out.is_master() {
+        out.amplitude_control = amplitude_control_from_config(cfg);
+    }
+    wavelength_meter_from_config(cfg, board, &mut out.wavelength_meter)?;
+    out.seed_control = seed_from_config(cfg, board)?;
+    out.monitor_inputs = monitors_from_config(cfg, board)?;
+    out.update_sample_times();
     Ok(out)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::config_model::{minimal_config, test_board};
 
     #[test]
     fn floor_exp_test() {
@@ -405,4 +557,40 @@ mod tests {
         assert_eq!(floor_exp(4095), 11);
         assert_eq!(floor_exp(4096), 12);
     }
+
+    #[test]
+    fn board_section_by_dna() {
+        let text = format!(
+            "{}{}dna = \"0x0012345678abcdef\"\n{}dna = \"0x00fedcba98765432\"\n",
+            minimal_config(),
+            test_board("board_a"),
+            test_board("board_b")
+        );
+        let cfg: Config = text.parse().unwrap();
+        assert_eq!(
+            board_section(&cfg, Some(0x00fe_dcba_9876_5432)).unwrap(),
+            "board_b"
+        );
+        assert_eq!(
+            board_section(&cfg, Some(1)).unwrap(),
+            gethostname().into_string().unwrap()
+        );
+    }
+
+    #[test]
+    fn roi_falls_back_on_multifit() {
+        let cfg: Config = minimal_config()
+            .replace("[las_1114]\n", "[las_1114]\nskip_rate = 20\n")
+            .parse()
+            .unwrap();
+        let roi = |x: &LaserSection| {
+            (
+                x.fit.roi.skip_start,
+                x.fit.roi.skip_end,
+                x.fit.roi.skip_rate,
+            )
+        };
+        assert_eq!(roi(&cfg.ref_laser), (6500, 10, 40));
+        assert_eq!(roi(cfg.slave_laser("rp-1").unwrap().1), (6500, 10, 20));
+    }
 }
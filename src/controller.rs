@@ -0,0 +1,979 @@
+#![warn(clippy::pedantic)]
+#![allow(
+    clippy::cast_possible_truncation,
+    clippy::cast_precision_loss,
+    clippy::module_name_repetitions
+)]
+
+use std::fmt::Write;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use chrono::Local;
+
+use librp_sys::analog::AnalogPin;
+use librp_sys::core::{self, APIResult};
+use librp_sys::dpin::{self, DigitalPin};
+use librp_sys::generator::{DCChannel, Generator, PulseChannel};
+use librp_sys::oscilloscope::{self, Oscilloscope};
+use librp_sys::traces::TraceRecorder;
+use librp_sys::Pitaya;
+
+use super::communications::InterfComms;
+use super::falling::FallingSegment;
+use super::interferometer::Interferometer;
+use super::lock::Mode;
+use super::multifit::{self, FitResult};
+use super::reload::ConfigWatch;
+use super::scheduler::{Phase, Timeout};
+use super::seed::{SeedOutput, SeedSource};
+use super::shutdown::Parking;
+use super::state::{self, Snapshot};
+use super::status_leds::{self, StatusFlags, StatusLeds};
+
+macro_rules! data_ch {
+    ($laser:expr, $scope:expr) => {
+        match $laser.input_channel {
+            core::Channel::CH_1 => &$scope.chA_buff_float,
+            core::Channel::CH_2 => &$scope.chB_buff_float,
+        }
+    };
+}
+
+macro_rules! data_ch_falling {
+    ($laser:expr, $scope:expr) => {
+        match $laser.input_channel {
+            core::Channel::CH_1 => &$scope.chA_buff_falling,
+            core::Channel::CH_2 => &$scope.chB_buff_falling,
+        }
+    };
+}
+
+/// The parts of the Red Pitaya the lock drives, borrowed out of a `Pitaya`: the ramp (master
+/// only) and slave outputs are bound to whichever generator channels the config assigns them.
+pub struct Hardware<'a> {
+    pub scope: &'a mut Oscilloscope,
+    pub dpin: &'a mut DigitalPin,
+    pub analog: &'a mut AnalogPin,
+    pub ramp_ch: Option<PulseChannel<'a>>,
+    pub slave_out_ch: DCChannel<'a>,
+    pub status_leds: StatusLeds,
+    pub ready_to_acquire_pin: dpin::Pin,
+    pub trigger_pin: dpin::Pin,
+}
+
+impl<'a> Hardware<'a> {
+    /// Split `pit` into the handles used by the lock, binding the generator channels to the
+    /// outputs set up in `interf`.
+    /// # Errors
+    /// Propagates any Red Pitaya API errors in setting up the generator channels
+    /// # Panics
+    /// Panics if `interf` has neither a reference nor a slave output channel, which
+    /// `interferometer_from_config` doesn't allow
+    pub fn init(
+        pit: &'a mut Pitaya,
+        interf: &Interferometer,
+        status_leds: StatusLeds,
+        ready_to_acquire_pin: dpin::Pin,
+        trigger_pin: dpin::Pin,
+    ) -> APIResult<Self> {
+        let Pitaya {
+            scope,
+            gen,
+            dpin,
+            analog,
+        } = pit;
+        let Generator { ch_a, ch_b } = gen;
+        let (ramp_ch, slave_out_ch) = match interf.ref_laser.output_channel {
+            Some(core::Channel::CH_1) => (
+                Some(PulseChannel::init(ch_a, vec![0.0; 16], 1.0)?),
+                DCChannel::init(ch_b)?,
+            ),
+            Some(core::Channel::CH_2) => (
+                Some(PulseChannel::init(ch_b, vec![0.0; 16], 1.0)?),
+                DCChannel::init(ch_a)?,
+            ),
+            None => (
+                None,
+                match interf
+                    .slave_laser
+                    .output_channel
+                    .expect("interferometer_from_config already set up slave output channel")
+                {
+                    core::Channel::CH_1 => DCChannel::init(ch_a)?,
+                    core::Channel::CH_2 => DCChannel::init(ch_b)?,
+                },
+            ),
+        };
+        Ok(Hardware {
+            scope,
+            dpin,
+            analog,
+            ramp_ch,
+            slave_out_ch,
+            status_leds,
+            ready_to_acquire_pin,
+            trigger_pin,
+        })
+    }
+}
+
+/// What happened in one completed cycle of `LockController::step`.
+#[derive(Debug, Clone)]
+pub struct CycleReport {
+    pub cycle: u64,
+    pub triggered: Instant,
+    // `None` for a laser whose trace was still being averaged this cycle
+    pub ref_fit: Option<FitResult>,
+    pub slave_fit: Option<FitResult>,
+    pub ref_error: f32,
+    pub slave_error: f32,
+    pub ref_adjustment: f32,
+    pub slave_adjustment: f32,
+    pub fitting_time: Duration,
+}
+
+/// Hooks into the `LockController`, e.g. for tests or alternative front-ends. Both methods do
+/// nothing by default.
+pub trait CycleObserver {
+    /// Called at the end of every completed cycle.
+    fn cycle_completed(&mut self, _report: &CycleReport) {}
+    /// Called for every status event, as it's published on the logs socket.
+    fn status_event(&mut self, _kind: &str, _message: &str) {}
+}
+
+#[derive(Debug, Default)]
+struct DebugStats {
+    total_fitting_time_us: u32,
+    total_err_ref: f32,
+    variance_ref: f32,
+    total_err_slave: f32,
+    variance_slave: f32,
+    iterations_ref: i32,
+    iterations_slave: i32,
+    // with block averaging, channels aren't fit every cycle
+    fits_ref: u32,
+    fits_slave: u32,
+}
+
+/// Runs the lock: each call to `step` goes through one acquisition cycle (handshake, trigger,
+/// comms, fits, servos, logs).
+pub struct LockController<'a> {
+    pub interf: Interferometer,
+    pub comms: InterfComms,
+    pub hw: Hardware<'a>,
+    pub parking: Parking,
+    pub config_watch: Option<ConfigWatch>,
+    pub trace_recorder: Option<TraceRecorder>,
+    observers: Vec<Box<dyn CycleObserver + 'a>>,
+    rayon_pool: rayon::ThreadPool,
+    debug_log_freq_log: Option<u8>,
+    stats: DebugStats,
+    last_ref_result: Option<FitResult>,
+    last_slave_result: Option<FitResult>,
+    last_comms_activity: Option<Instant>,
+}
+
+impl<'a> LockController<'a> {
+    /// # Errors
+    /// Returns an error message if the thread pool for the fits can't be built
+    pub fn new(
+        interf: Interferometer,
+        comms: InterfComms,
+        hw: Hardware<'a>,
+    ) -> Result<Self, String> {
+        let rayon_pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(2)
+            .build()
+            .map_err(|e| format!("failed to build thread pool: {e}"))?;
+        Ok(LockController {
+            interf,
+            comms,
+            hw,
+            parking: Parking::new(),
+            config_watch: None,
+            trace_recorder: None,
+            observers: Vec::new(),
+            rayon_pool,
+            debug_log_freq_log: None,
+            stats: DebugStats::default(),
+            last_ref_result: None,
+            last_slave_result: None,
+            last_comms_activity: None,
+        })
+    }
+
+    /// Print timing and error statistics every 2^`freq_log` cycles; `None` turns them off.
+    pub fn set_debug_log_frequency(&mut self, freq_log: Option<u8>) {
+        self.debug_log_freq_log = freq_log;
+    }
+
+    pub fn add_observer(&mut self, observer: Box<dyn CycleObserver + 'a>) {
+        self.observers.push(observer);
+    }
+
+    /// Apply the ramp and slow output settings, and start acquiring.
+    /// # Errors
+    /// Propagates any Red Pitaya API errors
+    pub fn start(&mut self) -> APIResult<()> {
+        let hw = &mut self.hw;
+        if self.interf.is_master() {
+            hw.dpin.set_state(hw.trigger_pin, dpin::PinState::Low)?;
+        }
+        self.interf
+            .ramp_setup
+            .apply(hw.scope, hw.ramp_ch.as_mut(), &mut hw.slave_out_ch)?;
+
+        for slow in [
+            self.interf.ref_laser.slow_feedback.as_mut(),
+            self.interf.slave_laser.slow_feedback.as_mut(),
+        ]
+        .into_iter()
+        .flatten()
+        {
+            let (min_v, max_v) = hw.analog.get_range(slow.pin)?;
+            slow.set_range(min_v, max_v);
+            slow.set_value_v(hw.analog.get_value(slow.pin).unwrap_or(min_v));
+        }
+
+        hw.scope.start_acquisition()?;
+        let _ = hw
+            .scope
+            .set_trigger_source(oscilloscope::TrigSrc::ExtRising);
+        thread::sleep(Duration::from_millis(50));
+
+        println!(
+            "fitting with n = {:?} (ref), {:?} (slave)",
+            self.interf.fit_setup_ref.num_points, self.interf.fit_setup_slave.num_points
+        );
+        if self.interf.is_master() {
+            self.interf.ref_lock.enable();
+        }
+        Ok(())
+    }
+
+    /// Restore the lock state saved by the last run, if there is one and it passes its sanity
+    /// checks; call after `start`, which would otherwise reset the reference servo. Returns a
+    /// message describing what was restored.
+    /// # Errors
+    /// Returns why the saved state couldn't be restored, in which case nothing was changed
+    pub fn restore_state(&mut self) -> Result<Option<String>, String> {
+        let path = match self.interf.state.file.clone() {
+            Some(path) if self.interf.state.restore_on_startup && path.exists() => path,
+            _ => return Ok(None),
+        };
+        let offsets = state::load(&path)?.restore(&mut self.interf)?;
+        self.apply_offsets(offsets);
+        Ok(Some(format!(
+            "restored lock state from {} (ref lock {}, slave lock {})",
+            path.display(),
+            self.interf.ref_lock.mode,
+            self.interf.slave_lock.mode
+        )))
+    }
+
+    /// The current lock state, including the output offsets.
+    #[must_use]
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot::capture(
+            &self.interf,
+            self.hw.ramp_ch.as_ref().map(PulseChannel::offset_v),
+            Some(self.hw.slave_out_ch.offset_v()),
+        )
+    }
+
+    fn apply_offsets(&mut self, (ref_offset_v, slave_offset_v): (Option<f32>, Option<f32>)) {
+        if let (Some(ch), Some(v)) = (self.hw.ramp_ch.as_mut(), ref_offset_v) {
+            let _ = ch.set_offset(v);
+        }
+        if let Some(v) = slave_offset_v {
+            self.hw.slave_out_ch.set_offset(v);
+        }
+    }
+
+    /// Print a status event, publish it on the logs socket, and pass it on to the observers.
+    async fn status_event(&mut self, kind: &str, message: &str) {
+        println!("[{}] {}: {}", Local::now(), kind, message);
+        if let Err(e) = self.comms.publish_status(kind, message).await {
+            eprintln!("[{}] Failed to publish status: error [{}]", Local::now(), e);
+        }
+        for observer in &mut self.observers {
+            observer.status_event(kind, message);
+        }
+    }
+
+    async fn handle_socket_requests(&mut self) {
+        while let Some(request) = self.comms.handle_socket_request(&mut self.interf).await {
+            println!("[{}] Handled socket request <{}>", Local::now(), request);
+            self.last_comms_activity = Some(Instant::now());
+        }
+    }
+
+    /// Save the live parameters to the config file if `CONFIG:SAVE` asked for it, then reload it
+    /// if it has changed or `CONFIG:RELOAD` asked for it, and publish what was (or wasn't) done as
+    /// status events.
+    async fn handle_config_requests(&mut self) {
+        let save = std::mem::take(&mut self.interf.save_requested);
+        let reload = std::mem::take(&mut self.interf.reload_requested);
+        let Some(watch) = self.config_watch.as_mut() else {
+            if save || reload {
+                self.status_event("CONFIG", "no config file").await;
+            }
+            return;
+        };
+        if save {
+            let msg = match watch.save(&self.interf) {
+                Ok(out) if out.changed.is_empty() => "config file already up to date".to_string(),
+                Ok(out) => format!(
+                    "saved [{}] to {}{}",
+                    out.changed.join(", "),
+                    watch.path.display(),
+                    if out.not_saved.is_empty() {
+                        String::new()
+                    } else {
+                        format!(", not saved [{}]", out.not_saved.join(", "))
+                    }
+                ),
+                Err(e) => format!("not saving: {e}"),
+            };
+            self.status_event("CONFIG", &msg).await;
+        }
+        let Some(watch) = self.config_watch.as_mut() else {
+            return;
+        };
+        if !reload && !watch.changed(Instant::now()) {
+            return;
+        }
+        let msg = match watch.reload(&mut self.interf, &mut self.comms, &mut self.parking) {
+            Ok(report) => report.to_string(),
+            Err(e) => format!("not reloading {}: {}", watch.path.display(), e),
+        };
+        self.status_event("CONFIG", &msg).await;
+    }
+
+    /// Report a wait in the acquisition cycle that timed out, and keep servicing the command
+    /// socket, which is otherwise only handled once per cycle. Skipping the rest of the cycle
+    /// leaves every output where it was. Once enough timeouts pile up to count as a fault, wait out
+    /// the retry interval and re-arm the scope before each further attempt.
+    async fn timed_out(&mut self, err: Timeout) -> Timeout {
+        eprintln!("[{}] {}", Local::now(), err);
+        if let Some(msg) = self.interf.fault.timed_out(&err, Instant::now()) {
+            self.status_event("FAULT", &msg).await;
+        }
+        self.handle_socket_requests().await;
+        if self.interf.fault.is_faulted() {
+            async_std::task::sleep(self.interf.fault.retry_interval).await;
+            let _ = self.hw.scope.start_acquisition();
+            let _ = self
+                .hw
+                .scope
+                .set_trigger_source(oscilloscope::TrigSrc::ExtRising);
+        }
+        err
+    }
+
+    fn print_debug_stats(&mut self) {
+        let stats = std::mem::take(&mut self.stats);
+        let denom_ref = stats.fits_ref.max(1) as f32;
+        let denom_slave = stats.fits_slave.max(1) as f32;
+        println!(
+            "[{}] average fitting time {} us",
+            Local::now(),
+            stats.total_fitting_time_us >> 9
+        );
+        println!(
+            "\taverage iterations per fit cycle: [ref: {:.2}, slave: {:.2}]",
+            stats.iterations_ref as f32 / denom_ref,
+            stats.iterations_slave as f32 / denom_slave,
+        );
+        println!(
+            "\taverage phase error (rad): [ref: {:.2}, slave: {:.2}]",
+            stats.total_err_ref / denom_ref,
+            stats.total_err_slave / denom_slave,
+        );
+        println!(
+            "\tRMS phase error (rad): [ref: {:.4}, slave: {:.4}]",
+            (stats.variance_ref / denom_ref).sqrt(),
+            (stats.variance_slave / denom_slave).sqrt(),
+        );
+        if let Some(falling) = self.interf.falling.as_ref() {
+            println!(
+                "\tfalling - rising phase offset (rad): {:?}",
+                falling.offsets()
+            );
+        }
+        for phase in Phase::ALL {
+            println!(
+                "\twait {}: {}",
+                phase,
+                self.interf.scheduler.histogram(phase)
+            );
+        }
+    }
+
+    /// Run one acquisition cycle.
+    /// # Errors
+    /// Returns the `Timeout` if the handshake or the trigger didn't come in time; the cycle is
+    /// abandoned, leaving the outputs untouched, and the next call starts over.
+    #[allow(clippy::too_many_lines)]
+    pub async fn step(&mut self) -> Result<CycleReport, Timeout> {
+        let interf = &mut self.interf;
+        let hw = &mut self.hw;
+        interf.cycle_counter += 1;
+
+        if interf.is_master() {
+            if let Err(e) = interf.scheduler.poll(Phase::Handshake, || {
+                matches!(
+                    hw.dpin.get_state(hw.ready_to_acquire_pin),
+                    Ok(dpin::PinState::Low)
+                )
+            }) {
+                return Err(self.timed_out(e).await);
+            }
+            let _ = hw.dpin.set_state(hw.trigger_pin, dpin::PinState::High);
+        } else {
+            let _ = hw
+                .dpin
+                .set_direction(hw.ready_to_acquire_pin, dpin::PinDirection::In);
+        }
+
+        let triggered = match interf.scheduler.poll(Phase::Trigger, || {
+            matches!(
+                hw.scope.get_trigger_state(),
+                Ok(oscilloscope::TrigState::Triggered)
+            )
+        }) {
+            Ok(t) => t,
+            Err(e) => {
+                if interf.is_master() {
+                    // lower the trigger line again, so the next cycle gives a fresh edge
+                    let _ = hw.dpin.set_state(hw.trigger_pin, dpin::PinState::Low);
+                }
+                return Err(self.timed_out(e).await);
+            }
+        };
+        if let Some(msg) = interf.fault.triggered(triggered) {
+            // the fit guesses and any partial averages are stale after the break
+            interf.ref_laser.fit_coefficients = [0.0, interf.ref_laser.fringe_freq(), 0.0, 0.0];
+            interf.slave_laser.fit_coefficients = [0.0, interf.slave_laser.fringe_freq(), 0.0, 0.0];
+            hw.scope.reset_averaging();
+            self.status_event("FAULT", &msg).await;
+        }
+        let interf = &mut self.interf;
+        let hw = &mut self.hw;
+
+        if !interf.is_master() {
+            let _ = hw
+                .dpin
+                .set_direction(hw.ready_to_acquire_pin, dpin::PinDirection::Out);
+            let _ = hw
+                .dpin
+                .set_state(hw.ready_to_acquire_pin, dpin::PinState::High);
+        }
+
+        if self.comms.should_publish_logs(interf.cycle_counter) {
+            match self.comms.publish_logs(interf).await {
+                Ok(()) => self.last_comms_activity = Some(Instant::now()),
+                Err(x) => {
+                    eprintln!("[{}] Failed to publish logs: error [{}]", Local::now(), x);
+                }
+            }
+        }
+        self.handle_socket_requests().await;
+        self.handle_config_requests().await;
+
+        if let Some(freq_log) = self.debug_log_freq_log {
+            if self.interf.cycle_counter & ((1 << freq_log) - 1) == 0 {
+                self.print_debug_stats();
+            }
+        }
+        let interf = &mut self.interf;
+        let hw = &mut self.hw;
+
+        // if the last fit got a suspicious result, we should reset our ''guess'' parameters
+        // to try to avoid getting stuck fitting to a bad mode. Also just reset the guess
+        // occasionally just in case.
+        let reset_timer = interf.cycle_counter & ((1 << 12) - 1) == 0;
+        if reset_timer
+            || self
+                .last_ref_result
+                .as_ref()
+                .is_some_and(|r| r.low_contrast)
+        {
+            interf.ref_laser.fit_coefficients = [0.0, interf.ref_laser.fringe_freq(), 0.0, 0.0];
+        }
+        if reset_timer
+            || self
+                .last_slave_result
+                .as_ref()
+                .is_some_and(|r| r.low_contrast)
+        {
+            interf.slave_laser.fit_coefficients = [0.0, interf.slave_laser.fringe_freq(), 0.0, 0.0];
+        }
+
+        interf.scheduler.wait_until(
+            Phase::Rise,
+            triggered + Duration::from_nanos(interf.ramp_setup.rise_time_ns() as u64),
+        );
+        let _ = hw.scope.update_scope_data_both();
+        if interf.is_master() {
+            let _ = hw.dpin.set_state(hw.trigger_pin, dpin::PinState::Low);
+        }
+        // with block averaging, a channel only has a complete trace every few cycles; in between,
+        // we skip its fit and hold its servo output
+        let ref_ready = hw.scope.is_ready(interf.ref_laser.input_channel);
+        let slave_ready = hw.scope.is_ready(interf.slave_laser.input_channel);
+
+        let fit_started = Instant::now();
+        // Can also accomplish this with a 'scoped thread'
+        let (ref_result, slave_result) = self.rayon_pool.join(
+            || {
+                ref_ready.then(|| {
+                    interf.fit_setup_ref.fit(
+                        data_ch!(interf.ref_laser, hw.scope).as_slice(),
+                        interf.ref_laser.fit_coefficients,
+                    )
+                })
+            },
+            || {
+                slave_ready.then(|| {
+                    interf.fit_setup_slave.fit(
+                        data_ch!(interf.slave_laser, hw.scope).as_slice(),
+                        interf.slave_laser.fit_coefficients,
+                    )
+                })
+            },
+        );
+        let fitting_time = fit_started.elapsed();
+        self.stats.total_fitting_time_us += fitting_time.as_micros() as u32;
+        let mut events = Vec::new();
+        let mut good_ref_freq = None;
+        if let Some(result) = ref_result.as_ref() {
+            self.stats.iterations_ref += result.n_iterations;
+            interf.ref_laser.fit_coefficients = result.params;
+            if !result.low_contrast && result.gsl_status == 0 {
+                if let Some(msg) = interf.feed_scale_calibration(result.params[1]) {
+                    events.push(("CALIBRATION", msg));
+                }
+                good_ref_freq = Some(result.params[1]);
+            }
+            self.last_ref_result = Some(result.clone());
+        }
+        if let Some(result) = slave_result.as_ref() {
+            self.stats.iterations_slave += result.n_iterations;
+            interf.slave_laser.fit_coefficients = result.params;
+            self.last_slave_result = Some(result.clone());
+        }
+        // a new amplitude is applied along with any other ramp changes at the end of the cycle
+        if let Some(msg) = good_ref_freq.and_then(|x| interf.feed_amplitude_control(x)) {
+            events.push(("AMPLITUDE", msg));
+        }
+        if ref_ready && slave_ready {
+            let good = |r: &Option<FitResult>| {
+                r.as_ref()
+                    .is_some_and(|r| !r.low_contrast && r.gsl_status == 0)
+            };
+            if good(&self.last_ref_result) && good(&self.last_slave_result) {
+                if let Some(msg) = interf.feed_wavelength_meter(
+                    interf.ref_laser.fit_coefficients[1],
+                    interf.slave_laser.fit_coefficients[1],
+                ) {
+                    events.push(("WAVELENGTH", msg));
+                }
+            }
+        }
+
+        // phases measured on the falling segment, referred to the rising segment
+        let mut falling_phases = (None, None);
+        if let Some(falling) = interf.falling.as_mut() {
+            // the same acquisition carries on through the falling segment (see
+            // `DaqSetup::trigger_delay`), so it's read relative to the same hardware trigger
+            interf.scheduler.wait_until(
+                Phase::Falling,
+                triggered + Duration::from_micros(interf.ramp_setup.ramp_period_us()),
+            );
+            let _ = hw.scope.update_scope_data_falling();
+            let FallingSegment {
+                fit_setup_ref,
+                fit_setup_slave,
+                ref_coefficients,
+                slave_coefficients,
+                ..
+            } = falling;
+            let (ref_fall, slave_fall) = self.rayon_pool.join(
+                || {
+                    ref_ready.then(|| {
+                        fit_setup_ref.fit(
+                            data_ch_falling!(interf.ref_laser, hw.scope).as_slice(),
+                            *ref_coefficients,
+                        )
+                    })
+                },
+                || {
+                    slave_ready.then(|| {
+                        fit_setup_slave.fit(
+                            data_ch_falling!(interf.slave_laser, hw.scope).as_slice(),
+                            *slave_coefficients,
+                        )
+                    })
+                },
+            );
+            if let Some(result) = ref_fall.as_ref() {
+                *ref_coefficients = result.params;
+            }
+            if let Some(result) = slave_fall.as_ref() {
+                *slave_coefficients = result.params;
+            }
+            if reset_timer
+                || ref_fall.as_ref().is_some_and(|r| r.low_contrast)
+                || slave_fall.as_ref().is_some_and(|r| r.low_contrast)
+            {
+                falling.reset_guesses(
+                    interf.ref_laser.fringe_freq(),
+                    interf.slave_laser.fringe_freq(),
+                    interf.ramp_setup.symmetry(),
+                );
+            }
+            falling_phases = (
+                ref_fall
+                    .map(|r| falling.refer_ref(interf.ref_laser.fit_coefficients[2], r.params[2])),
+                slave_fall.map(|r| {
+                    falling.refer_slave(interf.slave_laser.fit_coefficients[2], r.params[2])
+                }),
+            );
+        }
+
+        // the slave's phase is meaningless while it's not injected, so skip its servo entirely
+        // rather than let it integrate that phase and kick once injection returns
+        let mut slave_held = false;
+        if let Some(seed) = interf.seed_control.as_mut() {
+            let seed_signal = match seed.source {
+                SeedSource::FringeContrast => interf.slave_laser.fit_coefficients[0],
+                SeedSource::AnalogIn(pin) => hw.analog.get_value(pin).unwrap_or(f32::NAN),
+            };
+            let update = seed.update(Instant::now(), seed_signal);
+            slave_held = seed.is_recovering();
+            match seed.output {
+                SeedOutput::DcChannel => hw.slave_out_ch.increment_offset(update.step_v),
+                SeedOutput::SlowAnalog(pin) => {
+                    if update.step_v != 0.0 {
+                        if let Ok(v) = hw.analog.get_value(pin) {
+                            let _ = hw.analog.set_value(pin, v + update.step_v);
+                        }
+                    }
+                }
+            }
+            if let Some(event) = update.event {
+                events.push(("SEED", event));
+            }
+        }
+
+        let ref_error = multifit::wrapped_angle_difference(
+            interf.ref_laser.fit_coefficients[2],
+            interf.ref_lock.setpoint(),
+        );
+        let slave_error = multifit::wrapped_angle_difference(
+            interf.slave_laser.fit_coefficients[2]
+                - interf.ref_lock.last_error() * interf.ref_laser.wavelength_nm()
+                    / interf.slave_laser.wavelength_nm(),
+            interf.slave_lock.setpoint(),
+        );
+        // a channel that wasn't fit this cycle has no new error; leave it out of the stats
+        if ref_ready {
+            self.stats.fits_ref += 1;
+            self.stats.total_err_ref += ref_error;
+            self.stats.variance_ref += ref_error * ref_error;
+        }
+        if slave_ready {
+            self.stats.fits_slave += 1;
+            self.stats.total_err_slave += slave_error;
+            self.stats.variance_slave += slave_error * slave_error;
+        }
+
+        // The falling segment measures each phase a second time in the same cycle. The servos
+        // take the mean of the two, so that they still update once per cycle (or block) and the
+        // P and D terms keep their gain.
+        let ref_pid_error = falling_phases.0.map_or(ref_error, |phase| {
+            multifit::wrapped_angle_mean(
+                ref_error,
+                multifit::wrapped_angle_difference(phase, interf.ref_lock.setpoint()),
+            )
+        });
+        let slave_pid_error = falling_phases.1.map_or(slave_error, |phase| {
+            multifit::wrapped_angle_mean(
+                slave_error,
+                multifit::wrapped_angle_difference(
+                    phase
+                        - interf.ref_lock.last_error() * interf.ref_laser.wavelength_nm()
+                            / interf.slave_laser.wavelength_nm(),
+                    interf.slave_lock.setpoint(),
+                ),
+            )
+        });
+        let ref_adjustment = if ref_ready {
+            interf.ref_lock.do_pid(ref_pid_error)
+        } else {
+            0.0
+        };
+        let slave_adjustment = if slave_ready && !slave_held {
+            interf.slave_lock.do_pid(slave_pid_error)
+        } else {
+            0.0
+        };
+
+        if let Some(ch) = hw.ramp_ch.as_mut() {
+            let _ = ch.increment_offset(ref_adjustment);
+        }
+        hw.slave_out_ch.increment_offset(slave_adjustment);
+
+        if let (Some(slow), Some(ch)) =
+            (interf.ref_laser.slow_feedback.as_mut(), hw.ramp_ch.as_ref())
+        {
+            let _ = hw.analog.set_value(slow.pin, slow.update(ch.offset_v()));
+        }
+        if let Some(slow) = interf.slave_laser.slow_feedback.as_mut() {
+            let _ = hw
+                .analog
+                .set_value(slow.pin, slow.update(hw.slave_out_ch.offset_v()));
+        }
+        for monitor in &mut interf.monitor_inputs {
+            monitor
+                .log
+                .push(hw.analog.get_value(monitor.pin).unwrap_or(f32::NAN));
+        }
+
+        // and log NaN for it, rather than repeating the last fit's error
+        interf
+            .ref_laser
+            .phase_log
+            .push(if ref_ready { ref_error } else { f32::NAN });
+        interf
+            .ref_laser
+            .feedback_log
+            .push(hw.ramp_ch.as_ref().map_or(0.0, PulseChannel::offset_v));
+        interf
+            .slave_laser
+            .phase_log
+            .push(if slave_ready { slave_error } else { f32::NAN });
+        interf
+            .slave_laser
+            .feedback_log
+            .push(hw.slave_out_ch.offset_v());
+
+        let last_ref = self.last_ref_result.as_ref();
+        let last_slave = self.last_slave_result.as_ref();
+        hw.status_leds.update(
+            hw.dpin,
+            &StatusFlags {
+                ref_locked: matches!(interf.ref_lock.mode, Mode::Enabled)
+                    && last_ref.is_some_and(|r| !r.low_contrast),
+                slave_locked: matches!(interf.slave_lock.mode, Mode::Enabled)
+                    && last_slave.is_some_and(|r| !r.low_contrast),
+                is_master: interf.is_master(),
+                comms_active: self
+                    .last_comms_activity
+                    .is_some_and(|t| t.elapsed().as_millis() < 200),
+                fit_failed: last_ref.is_some_and(|r| r.gsl_status != 0)
+                    || last_slave.is_some_and(|r| r.gsl_status != 0),
+                saturated: hw.ramp_ch.as_ref().is_some_and(|ch| {
+                    status_leds::is_saturated(
+                        ch.offset_v(),
+                        ch.amplitude_v(),
+                        ch.ch.min_output_v(),
+                        ch.ch.max_output_v(),
+                    )
+                }) || status_leds::is_saturated(
+                    hw.slave_out_ch.offset_v(),
+                    0.0,
+                    hw.slave_out_ch.ch.min_output_v(),
+                    hw.slave_out_ch.ch.max_output_v(),
+                ),
+            },
+        );
+
+        if self.comms.should_publish_logs(interf.cycle_counter + 4) {
+            // Ideally we'd always send the most recent waveform, but we handle communications
+            // while the scope is acquiring, i.e. while the most recent waveform is being written in
+            // memory. Instead, we have to copy the waveform ahead of time, but this large of a
+            // memory operation can take a few milliseconds, which slightly distorts the next
+            // waveform acquired. So we copy the waveform a few cycles ahead of our next
+            // communications event, so in effect when we publish a 'most recent waveform', it's
+            // actually a few cycles out of date. When fitting the falling segment, the start of
+            // the buffer has been overwritten by the falling segment at this point.
+            let _ = interf.update_last_waveforms(hw.scope);
+        }
+
+        // apply any ramp changes requested over the command socket between ramps, rather than
+        // rewriting the waveform while it's being output; likewise output offsets from a snapshot
+        // restored over the command socket
+        if let Some((ref_offset_v, slave_offset_v)) = interf.state.take_pending_offsets() {
+            if let (Some(ch), Some(v)) = (hw.ramp_ch.as_mut(), ref_offset_v) {
+                let _ = ch.set_offset(v);
+            }
+            if let Some(v) = slave_offset_v {
+                hw.slave_out_ch.set_offset(v);
+            }
+        }
+        if interf.ramp_setup.take_pending_apply() {
+            if let Err(e) =
+                interf
+                    .ramp_setup
+                    .apply(hw.scope, hw.ramp_ch.as_mut(), &mut hw.slave_out_ch)
+            {
+                eprintln!(
+                    "[{}] Failed to apply ramp settings: error [{:?}]",
+                    Local::now(),
+                    e
+                );
+            }
+        }
+
+        // the whole buffer is in by now, falling side included
+        if let Some(recorder) = self.trace_recorder.as_mut() {
+            if let Err(e) = recorder.record(hw.scope) {
+                eprintln!(
+                    "[{}] Failed to record traces, no longer recording: error [{}]",
+                    Local::now(),
+                    e
+                );
+                self.trace_recorder = None;
+            }
+        }
+
+        let _ = hw.scope.start_acquisition();
+        let _ = hw
+            .scope
+            .set_trigger_source(oscilloscope::TrigSrc::ExtRising);
+
+        for (kind, msg) in events {
+            self.status_event(kind, &msg).await;
+        }
+        if self.interf.state.save_due(Instant::now()) {
+            if let Some(path) = self.interf.state.file.as_ref() {
+                if let Err(e) = state::save(path, &self.snapshot()) {
+                    eprintln!("[{}] {}", Local::now(), e);
+                }
+            }
+        }
+
+        let interf = &mut self.interf;
+        interf.scheduler.wait_until(
+            Phase::Settle,
+            triggered
+                + Duration::from_micros(
+                    interf.ramp_setup.ramp_period_us() + interf.ramp_setup.piezo_settle_time_us(),
+                ),
+        );
+
+        let report = CycleReport {
+            cycle: interf.cycle_counter,
+            triggered,
+            ref_fit: ref_result,
+            slave_fit: slave_result,
+            ref_error,
+            slave_error,
+            ref_adjustment,
+            slave_adjustment,
+            fitting_time,
+        };
+        for observer in &mut self.observers {
+            observer.cycle_completed(&report);
+        }
+        Ok(report)
+    }
+
+    /// Bring the lock down without leaving the hardware in a bad state: disable both servos, slew
+    /// the outputs to their parking voltages, stop the ramp, save the lock state, and unbind the
+    /// sockets. Errors along the way are reported, but don't stop the rest of the shutdown.
+    pub async fn shutdown(&mut self) {
+        // the state worth restoring is the running lock's, not the parked one
+        let snapshot = self.snapshot();
+        self.interf.ref_lock.disable();
+        self.interf.slave_lock.disable();
+
+        if let (Some(ch), Some(park_v)) = (self.hw.ramp_ch.as_mut(), self.parking.ref_park_v) {
+            for v in self.parking.slew_steps(ch.offset_v(), park_v) {
+                if let Err(e) = ch.set_offset(v) {
+                    eprintln!("[{}] Failed to park ramp output: {:?}", Local::now(), e);
+                    break;
+                }
+                async_std::task::sleep(self.parking.step_interval).await;
+            }
+        }
+        if let Some(park_v) = self.parking.slave_park_v {
+            let ch = &mut self.hw.slave_out_ch;
+            for v in self.parking.slew_steps(ch.offset_v(), park_v) {
+                ch.set_offset(v);
+                async_std::task::sleep(self.parking.step_interval).await;
+            }
+        }
+        if let Some(ch) = self.hw.ramp_ch.as_mut() {
+            if let Err(e) = ch.disable() {
+                eprintln!("[{}] Failed to stop ramp: {:?}", Local::now(), e);
+            }
+        }
+
+        let mut message = format!(
+            "outputs parked at {} V (ref), {} V (slave)",
+            self.hw.ramp_ch.as_ref().map_or(0.0, PulseChannel::offset_v),
+            self.hw.slave_out_ch.offset_v()
+        );
+        if let Some(path) = self.interf.state.file.as_ref() {
+            match state::save(path, &snapshot) {
+                Ok(()) => {
+                    let _ = write!(message, "; lock state saved to {}", path.display());
+                }
+                Err(e) => {
+                    let _ = write!(message, "; {e}");
+                }
+            }
+        }
+        self.status_event("SHUTDOWN", &message).await;
+
+        if let Err(e) = self.comms.unbind_sockets().await {
+            eprintln!("[{}] Failed to unbind sockets: {}", Local::now(), e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    struct Recorder(Rc<RefCell<Vec<u64>>>);
+
+    impl CycleObserver for Recorder {
+        fn cycle_completed(&mut self, report: &CycleReport) {
+            self.0.borrow_mut().push(report.cycle);
+        }
+    }
+
+    #[test]
+    fn steps_against_mock() {
+        let mut pit = Pitaya::init().unwrap();
+        let mut interf = Interferometer::new().unwrap();
+        interf.slave_laser.output_channel = Some(core::Channel::CH_2);
+        interf.ramp_setup.set_decimation(1);
+        interf.ramp_setup.piezo_settle_time_ms(0.1);
+        let hw = Hardware::init(
+            &mut pit,
+            &interf,
+            StatusLeds::new(),
+            dpin::Pin::DIO7_P,
+            dpin::Pin::DIO6_P,
+        )
+        .unwrap();
+        let mut controller = LockController::new(interf, InterfComms::new().unwrap(), hw).unwrap();
+        let cycles = Rc::new(RefCell::new(Vec::new()));
+        controller.add_observer(Box::new(Recorder(Rc::clone(&cycles))));
+        controller.start().unwrap();
+        for _ in 0..3 {
+            let report = async_std::task::block_on(controller.step()).unwrap();
+            assert!(report.ref_fit.is_some() && report.slave_fit.is_some());
+        }
+        assert_eq!(*cycles.borrow(), vec![1, 2, 3]);
+        assert_eq!(controller.interf.cycle_counter, 3);
+    }
+}
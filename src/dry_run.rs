@@ -0,0 +1,179 @@
+#![warn(clippy::pedantic)]
+#![allow(clippy::cast_precision_loss)]
+
+use std::f32::consts::PI;
+use std::fmt::Write;
+
+use super::cluster;
+use super::config_model::Config;
+use super::configs;
+use super::laser::Laser;
+use super::multifit::FitSetup;
+use super::ramp_shape::RAMP_POINTS;
+
+fn describe_fit(out: &mut String, what: &str, fit: &FitSetup) {
+    let _ = writeln!(
+        out,
+        "  {what} fit: {} points (every {} samples), at most {} iterations",
+        fit.num_points, fit.skip_rate, fit.max_iterations
+    );
+}
+
+fn describe_laser(out: &mut String, what: &str, laser: &Laser, fit: &FitSetup) {
+    let _ = writeln!(
+        out,
+        "{what} laser: {} nm, input {:?}, output {}",
+        laser.wavelength_nm(),
+        laser.input_channel,
+        laser
+            .output_channel
+            .map_or_else(|| "none".to_string(), |x| format!("{x:?}")),
+    );
+    let _ = writeln!(
+        out,
+        "  fringe frequency {:.6} rad/sample, {:.2} fringes per acquisition",
+        laser.fringe_freq(),
+        laser.fringe_freq() * RAMP_POINTS as f32 / (2.0 * PI),
+    );
+    describe_fit(out, "rising", fit);
+    let _ = writeln!(
+        out,
+        "  averaging {:?}, phase log {} entries",
+        laser.averaging,
+        laser.phase_log.len()
+    );
+    if let Some(slow) = laser.slow_feedback.as_ref() {
+        let _ = writeln!(
+            out,
+            "  slow feedback on {:?}, centered on {} V",
+            slow.pin, slow.center_v
+        );
+    }
+}
+
+/// Everything a board would set up from the config file `text`, without touching any hardware:
+/// the parsed config is run through the same `configs` functions as at startup, for the section
+/// of board `host` (by default the one for this machine's hostname), and the quantities derived
+/// from it are described.
+/// # Errors
+/// Returns a report of every problem found in the config, or the first failure in setting up
+/// from it
+pub fn check_config(text: &str, host: Option<&str>) -> Result<String, String> {
+    let (cfg, warnings) = Config::parse(text).map_err(|e| e.to_string())?;
+    let board = match host {
+        Some(x) => x.to_string(),
+        None => configs::board_section(&cfg, None)?,
+    };
+    let section = cfg.board(&board)?;
+    let interf = configs::interferometer_from_config(&cfg, &board)?;
+    configs::leds_from_config(&cfg)?;
+    let parking = configs::parking_from_config(&cfg, &board)?;
+
+    let mut out = String::new();
+    for warning in warnings {
+        let _ = writeln!(out, "WARN: {warning}");
+    }
+    let _ = writeln!(
+        out,
+        "Config section [{board}], {}",
+        if interf.is_master() {
+            "MASTER"
+        } else {
+            "slave"
+        }
+    );
+    // a board's own copy of the config may only have its own section
+    if cfg.boards.len() > 1 {
+        let problems = cluster::check(&cfg);
+        if problems.is_empty() {
+            let _ = writeln!(out, "Cluster: {} boards, consistent", cfg.boards.len());
+        }
+        for problem in problems {
+            let _ = writeln!(out, "Cluster: {problem}");
+        }
+    }
+
+    let ramp = &interf.ramp_setup;
+    let _ = writeln!(
+        out,
+        "Ramp: {} shape, {} V amplitude, decimation {}, symmetry {}",
+        ramp.shape(),
+        ramp.amplitude_volts,
+        ramp.decimation(),
+        ramp.symmetry()
+    );
+    let _ = writeln!(
+        out,
+        "  period {} us, rise time {} us, settle time {} us, cycle period {:.3} ms",
+        ramp.ramp_period_us(),
+        ramp.rise_time_ns() / 1000,
+        ramp.piezo_settle_time_us(),
+        ramp.cycle_period_s() * 1.0e3
+    );
+
+    describe_laser(
+        &mut out,
+        "Reference",
+        &interf.ref_laser,
+        &interf.fit_setup_ref,
+    );
+    if let Some(falling) = interf.falling.as_ref() {
+        describe_fit(&mut out, "falling", &falling.fit_setup_ref);
+    }
+    describe_laser(
+        &mut out,
+        &format!("Slave ({})", section.slave_laser),
+        &interf.slave_laser,
+        &interf.fit_setup_slave,
+    );
+    if let Some(falling) = interf.falling.as_ref() {
+        describe_fit(&mut out, "falling", &falling.fit_setup_slave);
+    }
+
+    let _ = writeln!(
+        out,
+        "Pins: trigger output {:?}, ready to acquire {:?} ({})",
+        configs::dpin_get_trigger_pin(&cfg),
+        configs::dpin_get_ready_pin(&cfg),
+        if interf.is_master() {
+            "input"
+        } else {
+            "output"
+        }
+    );
+    for (pin, led) in &cfg.leds {
+        let _ = writeln!(out, "  {pin}: {:?} ({:?})", led.indicator, led.pattern);
+    }
+    for monitor in &interf.monitor_inputs {
+        let _ = writeln!(
+            out,
+            "  monitoring {:?}, log {} entries",
+            monitor.pin,
+            monitor.log.len()
+        );
+    }
+    let _ = writeln!(
+        out,
+        "Parking: ref {:?} V, slave {:?} V, at {} V/s",
+        parking.ref_park_v, parking.slave_park_v, parking.slew_rate_v_per_s
+    );
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config_model::minimal_config;
+
+    #[test]
+    fn master_board_report() {
+        let text = minimal_config().replace("[las_1114]\n", "[las_1114]\nskip_rate = 30\n");
+        let report = check_config(&text, Some("rp-1")).unwrap();
+        assert!(report.contains("MASTER"), "{report}");
+        // 16384 samples, less 6500 + 10 skipped, every 30th for the slave
+        assert!(report.contains("330 points (every 30 samples)"), "{report}");
+        assert!(check_config(&text, Some("no-such-board"))
+            .unwrap_err()
+            .contains("no-such-board"));
+    }
+}
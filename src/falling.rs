@@ -0,0 +1,129 @@
+#![warn(clippy::pedantic)]
+#![allow(clippy::module_name_repetitions)]
+
+use std::str::Split;
+
+use crate::command::{arg_in, CommandError, CommandResult};
+use crate::multifit::{wrapped_angle_difference, FitSetup};
+use crate::ring_buffer::DyadicRingBuffer;
+
+/// Fitting of the falling segment of the ramp, as a second phase measurement per cycle.
+///
+/// The phase fit on the falling segment differs from the one on the rising segment by a roughly
+/// constant offset (the two regions of interest don't start at exactly the same ramp voltage),
+/// plus whatever hysteresis the piezo shows. We track that offset as a slow running average and
+/// subtract it, so the falling phase can be fed to the same servo as the rising one; the offset
+/// itself is logged, since its drift is a direct measurement of the piezo's hysteresis.
+#[derive(Debug)]
+pub struct FallingSegment {
+    pub fit_setup_ref: FitSetup,
+    pub fit_setup_slave: FitSetup,
+    pub ref_coefficients: [f32; 4],
+    pub slave_coefficients: [f32; 4],
+    pub offset_alpha: f32,
+    offset_ref: f32,
+    offset_slave: f32,
+    pub hysteresis_log: DyadicRingBuffer<f32>,
+}
+
+impl FallingSegment {
+    #[must_use]
+    pub fn new(fit_setup_ref: FitSetup, fit_setup_slave: FitSetup, n: usize) -> Option<Self> {
+        Some(FallingSegment {
+            fit_setup_ref,
+            fit_setup_slave,
+            ref_coefficients: [0.0; 4],
+            slave_coefficients: [0.0; 4],
+            offset_alpha: 0.01,
+            offset_ref: f32::NAN,
+            offset_slave: f32::NAN,
+            hysteresis_log: DyadicRingBuffer::new(n)?,
+        })
+    }
+
+    /// Reset the fit guesses, given the fringe frequencies on the rising segment and the ramp
+    /// symmetry. The falling segment is shorter, so its fringes are denser by `s / (1 - s)`.
+    pub fn reset_guesses(&mut self, ref_freq: f32, slave_freq: f32, symmetry: f32) {
+        let speedup = symmetry / (1.0 - symmetry);
+        self.ref_coefficients = [0.0, ref_freq * speedup, 0.0, 0.0];
+        self.slave_coefficients = [0.0, slave_freq * speedup, 0.0, 0.0];
+    }
+
+    /// Given the rising and falling phases of the reference laser, returns the falling phase
+    /// referred to the rising segment, and updates the tracked offset.
+    pub fn refer_ref(&mut self, rising: f32, falling: f32) -> f32 {
+        let out = track_offset(&mut self.offset_ref, self.offset_alpha, rising, falling);
+        self.hysteresis_log.push(self.offset_ref);
+        out
+    }
+
+    /// As `refer_ref`, for the slave laser.
+    pub fn refer_slave(&mut self, rising: f32, falling: f32) -> f32 {
+        track_offset(&mut self.offset_slave, self.offset_alpha, rising, falling)
+    }
+
+    /// Current (falling - rising) phase offsets of the reference and slave lasers, in radians.
+    #[must_use]
+    pub fn offsets(&self) -> (f32, f32) {
+        (self.offset_ref, self.offset_slave)
+    }
+
+    pub fn reset_offsets(&mut self) {
+        self.offset_ref = f32::NAN;
+        self.offset_slave = f32::NAN;
+    }
+
+    /// Takes a split over a string command, parses the command, executes the command, and returns
+    /// a string
+    /// # Errors
+    /// In case of an invalid command (or inability to parse a command), returns a `CommandError`
+    pub fn process_command(&mut self, cmd: Split<'_, char>) -> CommandResult {
+        let resp = match cmd.collect::<Vec<&str>>()[..] {
+            ["HYSTERESIS", "GET"] => format!("{:?}", self.offsets()),
+            ["HYSTERESIS", "RESET"] => {
+                self.reset_offsets();
+                String::new()
+            }
+            ["OFFSET_ALPHA", "SET", x] => {
+                self.offset_alpha = arg_in(x, 0.0..=1.0)?;
+                String::new()
+            }
+            ["OFFSET_ALPHA", "GET"] => self.offset_alpha.to_string(),
+            _ => Err(CommandError::Unknown)?,
+        };
+        Ok(resp)
+    }
+}
+
+fn track_offset(offset: &mut f32, alpha: f32, rising: f32, falling: f32) -> f32 {
+    let diff = wrapped_angle_difference(falling, rising);
+    if offset.is_nan() {
+        *offset = diff;
+    } else if !diff.is_nan() {
+        *offset = wrapped_angle_difference(
+            *offset + alpha * wrapped_angle_difference(diff, *offset),
+            0.0,
+        );
+    }
+    wrapped_angle_difference(falling, *offset)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn offset_tracking() {
+        let mut offset = f32::NAN;
+        // first measurement sets the offset outright
+        assert!((track_offset(&mut offset, 0.1, 0.2, 1.2) - 0.2).abs() < 1e-5);
+        assert!((offset - 1.0).abs() < 1e-5);
+        // a single jump in the difference only moves the offset a little
+        track_offset(&mut offset, 0.1, 0.0, 2.0);
+        assert!((offset - 1.1).abs() < 1e-5);
+        // and the offset wraps properly
+        let mut offset = 3.1;
+        track_offset(&mut offset, 0.5, 0.0, -3.1);
+        assert!(offset.abs() > 3.1);
+    }
+}
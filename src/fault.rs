@@ -0,0 +1,156 @@
+#![warn(clippy::pedantic)]
+#![allow(clippy::module_name_repetitions)]
+
+use std::str::Split;
+use std::time::{Duration, Instant};
+
+use super::command::{arg, CommandError, CommandResult};
+use super::scheduler::{Phase, Timeout};
+
+/// Keeps track of whether the acquisition cycle is running, or stuck waiting on a trigger or
+/// handshake that isn't coming (cable unplugged, another board crashed...).
+///
+/// A single timeout is reported but otherwise just restarts the cycle; after `timeouts_to_fault`
+/// consecutive timeouts the board is considered faulted. While faulted, the cycle is retried every
+/// `retry_interval`, with no fits or servo updates in between, so the actuators hold their last
+/// values until the first successful trigger clears the fault.
+#[derive(Debug)]
+pub struct FaultMonitor {
+    pub timeouts_to_fault: u32,
+    pub retry_interval: Duration,
+    consecutive_timeouts: u32,
+    fault: Option<Fault>,
+    pub n_faults: u64,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Fault {
+    pub phase: Phase,
+    pub since: Instant,
+    pub retries: u32,
+}
+
+impl FaultMonitor {
+    #[must_use]
+    pub fn new() -> Self {
+        FaultMonitor {
+            timeouts_to_fault: 3,
+            retry_interval: Duration::from_secs(1),
+            consecutive_timeouts: 0,
+            fault: None,
+            n_faults: 0,
+        }
+    }
+
+    #[must_use]
+    pub fn fault(&self) -> Option<Fault> {
+        self.fault
+    }
+
+    #[must_use]
+    pub fn is_faulted(&self) -> bool {
+        self.fault.is_some()
+    }
+
+    /// Record a timed out wait. Returns a message if this puts us into the fault state.
+    pub fn timed_out(&mut self, timeout: &Timeout, now: Instant) -> Option<String> {
+        self.consecutive_timeouts = self.consecutive_timeouts.saturating_add(1);
+        if let Some(fault) = self.fault.as_mut() {
+            fault.retries += 1;
+            fault.phase = timeout.phase;
+            return None;
+        }
+        if self.consecutive_timeouts < self.timeouts_to_fault.max(1) {
+            return None;
+        }
+        self.fault = Some(Fault {
+            phase: timeout.phase,
+            since: now,
+            retries: 0,
+        });
+        self.n_faults += 1;
+        Some(format!(
+            "FAULT: {} consecutive timeouts, last {}; holding outputs and retrying every {} ms",
+            self.consecutive_timeouts,
+            timeout,
+            self.retry_interval.as_millis()
+        ))
+    }
+
+    /// Record a completed trigger. Returns a message if this clears a fault.
+    pub fn triggered(&mut self, now: Instant) -> Option<String> {
+        self.consecutive_timeouts = 0;
+        let fault = self.fault.take()?;
+        Some(format!(
+            "RECOVERED: fault waiting on {} cleared after {} s and {} retries",
+            fault.phase,
+            now.saturating_duration_since(fault.since).as_secs_f32(),
+            fault.retries
+        ))
+    }
+
+    /// Takes a split over a string command, parses the command, executes the command, and returns
+    /// a string
+    /// # Errors
+    /// In case of an invalid command (or inability to parse a command), returns a `CommandError`
+    pub fn process_command(&mut self, cmd: Split<'_, char>) -> CommandResult {
+        let resp = match cmd.collect::<Vec<&str>>()[..] {
+            ["GET"] => match self.fault {
+                Some(fault) => format!(
+                    "FAULTED,{},{},{}",
+                    fault.phase,
+                    fault.since.elapsed().as_secs_f32(),
+                    fault.retries
+                ),
+                None => "OK".to_string(),
+            },
+            ["COUNT", "GET"] => self.n_faults.to_string(),
+            ["THRESHOLD", "SET", x] => {
+                self.timeouts_to_fault = arg::<u32>(x)?.max(1);
+                String::new()
+            }
+            ["THRESHOLD", "GET"] => self.timeouts_to_fault.to_string(),
+            ["RETRY_INTERVAL", "SET", x] => {
+                self.retry_interval = Duration::from_millis(arg::<u64>(x)?);
+                String::new()
+            }
+            ["RETRY_INTERVAL", "GET"] => self.retry_interval.as_millis().to_string(),
+            _ => Err(CommandError::Unknown)?,
+        };
+        Ok(resp)
+    }
+}
+
+impl Default for FaultMonitor {
+    fn default() -> Self {
+        FaultMonitor::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fault_and_recovery() {
+        let mut monitor = FaultMonitor::new();
+        monitor.timeouts_to_fault = 2;
+        let timeout = Timeout {
+            phase: Phase::Trigger,
+            waited: Duration::from_secs(1),
+        };
+        let now = Instant::now();
+        assert!(monitor.timed_out(&timeout, now).is_none());
+        // a trigger in between starts the count over
+        assert!(monitor.triggered(now).is_none());
+        assert!(monitor.timed_out(&timeout, now).is_none());
+        assert!(monitor.timed_out(&timeout, now).is_some());
+        assert!(monitor.is_faulted());
+        // further timeouts count as retries, not new faults
+        assert!(monitor.timed_out(&timeout, now).is_none());
+        assert_eq!(monitor.fault().unwrap().retries, 1);
+        assert!(monitor.triggered(now).is_some());
+        assert!(!monitor.is_faulted());
+        assert_eq!(monitor.n_faults, 1);
+    }
+}
@@ -1,14 +1,26 @@
 #![warn(clippy::pedantic)]
-#![allow(clippy::result_unit_err)]
 
+use std::path::PathBuf;
 use std::str::Split;
+use std::time::Duration;
 
 use librp_sys::core::{APIResult, Channel};
-use librp_sys::oscilloscope::Oscilloscope;
+use librp_sys::oscilloscope::{Oscilloscope, ScopeUnits};
 
+use super::amplitude_control::{self, AmplitudeControl};
+use super::command::{arg, CommandError, CommandResult};
+use super::falling::FallingSegment;
+use super::fault::FaultMonitor;
 use super::laser::Laser;
 use super::lock::Servo;
 use super::ramp::DaqSetup;
+use super::ramp_shape::RampShape;
+use super::scale_calibration::ScaleCalibration;
+use super::scheduler::Scheduler;
+use super::seed::SeedMonitor;
+use super::slow_io::MonitorInput;
+use super::state::{self, Snapshot, StateStore};
+use super::wavelength_meter::WavelengthMeter;
 use crate::multifit;
 
 #[derive(Debug)]
@@ -21,9 +33,27 @@ pub struct Interferometer {
     pub fit_setup_slave: multifit::FitSetup,
 
     pub ramp_setup: DaqSetup,
+    pub scheduler: Scheduler,
+    pub fault: FaultMonitor,
+    pub seed_control: Option<SeedMonitor>,
+    pub falling: Option<FallingSegment>,
+    pub scale_calibration: Option<ScaleCalibration>,
+    pub amplitude_control: Option<AmplitudeControl>,
+    pub wavelength_meter: WavelengthMeter,
+    pub state: StateStore,
+    pub monitor_inputs: Vec<MonitorInput>,
     pub cycle_counter: u64,
+    // set by `SYSTEM:SHUTDOWN`; the main loop checks it between cycles
+    pub shutdown_requested: bool,
+    // set by `CONFIG:RELOAD`; picked up by the controller along with changes to the config file
+    pub reload_requested: bool,
+    // set by `CONFIG:SAVE`; the controller writes the live parameters back to the config file
+    pub save_requested: bool,
     pub last_waveform_ref: Vec<u32>,
     pub last_waveform_slave: Vec<u32>,
+    // the same waveforms in volts, when the scope's units are volts; empty otherwise
+    pub last_waveform_volts_ref: Vec<f32>,
+    pub last_waveform_volts_slave: Vec<f32>,
 }
 
 impl Interferometer {
@@ -38,9 +68,23 @@ impl Interferometer {
             fit_setup_slave: multifit::FitSetup::init(1, 16384, 16, 1e-6, 1e-6, 1e-6, 3.0)?,
 
             ramp_setup: DaqSetup::new(),
+            scheduler: Scheduler::new(),
+            fault: FaultMonitor::new(),
+            seed_control: None,
+            falling: None,
+            scale_calibration: None,
+            amplitude_control: None,
+            wavelength_meter: WavelengthMeter::new(256),
+            state: StateStore::new(PathBuf::from(".")),
+            monitor_inputs: Vec::new(),
             cycle_counter: 0,
+            shutdown_requested: false,
+            reload_requested: false,
+            save_requested: false,
             last_waveform_ref: Vec::with_capacity(16384),
             last_waveform_slave: Vec::with_capacity(16384),
+            last_waveform_volts_ref: Vec::new(),
+            last_waveform_volts_slave: Vec::new(),
         })
     }
     #[inline]
@@ -49,7 +93,17 @@ impl Interferometer {
         self.ref_laser.output_channel.is_some()
     }
 
-    fn update_fringe_params(&mut self) {
+    /// Recompute the fringe frequencies after a change to the ramp amplitude or scale factor, and
+    /// clear the servos' integrals, which were built up against the old ones.
+    pub fn update_fringe_params(&mut self) {
+        self.refresh_fringe_freqs();
+        self.ref_lock.reset_integral();
+        self.slave_lock.reset_integral();
+    }
+
+    /// Recompute the lasers' expected fringe frequencies (i.e. the fit guesses) from the ramp
+    /// settings, without disturbing the servos.
+    fn refresh_fringe_freqs(&mut self) {
         self.ref_laser.set_wavelength(
             self.ref_laser.wavelength_nm(),
             self.ramp_setup.piezo_scale_factor,
@@ -60,56 +114,288 @@ impl Interferometer {
             self.ramp_setup.piezo_scale_factor,
             self.ramp_setup.amplitude_volts,
         );
+        if let Some(falling) = self.falling.as_mut() {
+            falling.reset_guesses(
+                self.ref_laser.fringe_freq(),
+                self.slave_laser.fringe_freq(),
+                self.ramp_setup.symmetry(),
+            );
+        }
+    }
 
-        self.ref_lock.reset_integral();
-        self.slave_lock.reset_integral();
+    /// Point the servos at the current acquisition cycle period, so that their gains keep the
+    /// same meaning in physical units when the ramp timing changes. With block averaging, a
+    /// servo only updates once per block, so its sample time is that many cycles. Fitting the
+    /// falling segment doesn't change it, as both phases go into one update.
+    #[allow(clippy::cast_precision_loss)]
+    pub fn update_sample_times(&mut self) {
+        let dt = self.ramp_setup.cycle_period_s();
+        self.ref_lock.sample_time_sec =
+            Some(dt * self.ref_laser.averaging.traces_per_update() as f32);
+        self.slave_lock.sample_time_sec =
+            Some(dt * self.slave_laser.averaging.traces_per_update() as f32);
+    }
+
+    /// Feed the reference laser's latest fitted fringe frequency to a running scale factor
+    /// calibration, if there is one. When the calibration finishes, this applies the new scale
+    /// factor to the ramp and to both lasers, and returns a message describing the result.
+    pub fn feed_scale_calibration(&mut self, fringe_freq: f32) -> Option<String> {
+        let result = self.scale_calibration.as_mut()?.push(
+            fringe_freq,
+            self.ref_laser.wavelength_nm(),
+            self.ramp_setup.amplitude_volts,
+        )?;
+        self.scale_calibration = None;
+        let old = self.ramp_setup.piezo_scale_factor;
+        self.ramp_setup.piezo_scale_factor = result.scale_factor;
+        self.update_fringe_params();
+        Some(format!(
+            "piezo scale factor {} +/- {} nm/V from {} cycles (was {}); set ramp:piezo_scale_factor in the config file to keep it",
+            result.scale_factor, result.uncertainty, result.n, old
+        ))
+    }
+
+    /// Feed the reference laser's latest fitted fringe frequency to the ramp amplitude control, if
+    /// there is one. When it decides on a new amplitude, this updates `ramp_setup` and requests
+    /// that it be applied to the output between ramps, and returns a message describing the step.
+    /// Call once both lasers' fits for the cycle are in, since it updates their fit guesses.
+    pub fn feed_amplitude_control(&mut self, fringe_freq: f32) -> Option<String> {
+        let old_ampl = self.ramp_setup.amplitude_volts;
+        let step = self
+            .amplitude_control
+            .as_mut()?
+            .push(fringe_freq, old_ampl)?;
+        self.ramp_setup.amplitude(step.amplitude_v).request_apply();
+        // The piezo scale factor is left alone, as it may have been calibrated and gets saved. The
+        // fringe frequencies just scale with the amplitude, so start the next fits from what we
+        // measured at the old one. The falling segment's guesses follow their own fits.
+        let scale_factor = self.ramp_setup.piezo_scale_factor;
+        for laser in [&mut self.ref_laser, &mut self.slave_laser] {
+            laser.set_wavelength(laser.wavelength_nm(), scale_factor, step.amplitude_v);
+        }
+        let ratio = step.amplitude_v / old_ampl;
+        self.ref_laser.fit_coefficients[1] = step.measured_freq * ratio;
+        self.slave_laser.fit_coefficients[1] *= ratio;
+        Some(format!(
+            "ramp amplitude {} -> {} V ({} reference fringes per ramp)",
+            old_ampl,
+            step.amplitude_v,
+            amplitude_control::freq_to_fringes(step.measured_freq)
+        ))
+    }
+
+    /// Feed one cycle's fitted fringe frequencies to the wavelength meter. When it completes an
+    /// estimate of the slave wavelength, returns a message describing it; with feedback enabled,
+    /// the estimate also replaces the slave laser's nominal wavelength.
+    pub fn feed_wavelength_meter(&mut self, ref_freq: f32, slave_freq: f32) -> Option<String> {
+        let est =
+            self.wavelength_meter
+                .push(ref_freq, slave_freq, self.ref_laser.wavelength_nm())?;
+        if self.wavelength_meter.feedback {
+            self.slave_laser.set_wavelength(
+                est.wavelength_nm,
+                self.ramp_setup.piezo_scale_factor,
+                self.ramp_setup.amplitude_volts,
+            );
+            self.refresh_fringe_freqs();
+        }
+        Some(format!(
+            "slave wavelength {} +/- {} nm over {} cycles{}",
+            est.wavelength_nm,
+            est.uncertainty_nm,
+            est.n,
+            if est.mode_hop { " (mode hop)" } else { "" }
+        ))
     }
 
-    /// Copy the data from the Red Pitaya's internal oscilloscope buffer into the buffers of `self`.
+    /// Copy the data from the Red Pitaya's internal oscilloscope buffer into the buffers of `self`,
+    /// as raw ADC words and, if the scope's units are volts, in volts as well.
     /// # Errors
     /// Propagates any Red Pitaya API errors
     pub fn update_last_waveforms(&mut self, osc: &mut Oscilloscope) -> APIResult<()> {
-        match self.ref_laser.input_channel {
-            Channel::CH_1 => {
-                osc.write_raw_waveform(&mut self.last_waveform_ref, &mut self.last_waveform_slave)?;
-            }
-            Channel::CH_2 => {
-                osc.write_raw_waveform(&mut self.last_waveform_slave, &mut self.last_waveform_ref)?;
-            }
+        let (raw_a, raw_b, volts_a, volts_b) = match self.ref_laser.input_channel {
+            Channel::CH_1 => (
+                &mut self.last_waveform_ref,
+                &mut self.last_waveform_slave,
+                &mut self.last_waveform_volts_ref,
+                &mut self.last_waveform_volts_slave,
+            ),
+            Channel::CH_2 => (
+                &mut self.last_waveform_slave,
+                &mut self.last_waveform_ref,
+                &mut self.last_waveform_volts_slave,
+                &mut self.last_waveform_volts_ref,
+            ),
         };
+        osc.write_raw_waveform(raw_a, raw_b)?;
+        if osc.units() == ScopeUnits::Volts {
+            osc.write_waveform(volts_a, volts_b)?;
+        } else {
+            volts_a.clear();
+            volts_b.clear();
+        }
+        Ok(())
+    }
+
+    /// The acquisition assumes the rise lasts `symmetry` of the period, which shapes that set
+    /// their own rise time only match at 0.5.
+    fn set_ramp_shape(&mut self, shape: RampShape) -> Result<(), CommandError> {
+        if shape.ignores_symmetry() && (self.ramp_setup.symmetry() - 0.5).abs() > 1e-6 {
+            return Err(CommandError::NotPermitted(format!(
+                "{shape} needs a ramp symmetry of 0.5, not {}",
+                self.ramp_setup.symmetry()
+            )));
+        }
+        self.ramp_setup.set_shape(shape);
         Ok(())
     }
 
-    fn process_ramp_command(&mut self, cmd: Split<'_, char>) -> Result<String, ()> {
+    fn process_ramp_command(&mut self, cmd: Split<'_, char>) -> CommandResult {
         let resp = match cmd.collect::<Vec<&str>>()[..] {
             ["AMPL", "SET", x] => {
-                self.ramp_setup.amplitude(x.parse::<f32>().or(Err(()))?);
+                self.ramp_setup.amplitude(arg::<f32>(x)?);
                 self.update_fringe_params();
                 String::new()
             }
             ["AMPL", "GET"] => self.ramp_setup.amplitude_volts.to_string(),
             ["SCALE_FACTOR", "SET", x] => {
-                self.ramp_setup.piezo_scale_factor = x.parse::<f32>().or(Err(()))?;
+                self.ramp_setup.piezo_scale_factor = arg::<f32>(x)?;
                 self.update_fringe_params();
                 String::new()
             }
             ["SCALE_FACTOR", "GET"] => self.ramp_setup.piezo_scale_factor.to_string(),
+            ["SCALE_FACTOR", "CALIBRATE", "GET"] => match self.scale_calibration.as_ref() {
+                Some(cal) => format!("{:?}", cal.progress()),
+                None => "IDLE".to_string(),
+            },
+            ["SCALE_FACTOR", "CALIBRATE", "CANCEL"] => {
+                self.scale_calibration = None;
+                String::new()
+            }
+            ["SCALE_FACTOR", "CALIBRATE", n] => {
+                self.scale_calibration = Some(ScaleCalibration::new(arg::<usize>(n)?));
+                String::new()
+            }
             ["SETTLE_TIME", "SET", x] => {
-                self.ramp_setup
-                    .piezo_settle_time_ms(x.parse::<f32>().or(Err(()))?);
+                self.ramp_setup.piezo_settle_time_ms(arg::<f32>(x)?);
+                self.update_sample_times();
                 String::new()
             }
             ["SETTLE_TIME", "GET"] => self.ramp_setup.piezo_settle_time_ms.to_string(),
-            _ => Err(())?,
+            ["SHAPE", "SET", "CUSTOM", x] => {
+                self.set_ramp_shape(
+                    RampShape::custom_from_str(x)
+                        .ok_or_else(|| CommandError::BadArgument(format!("'{x}'")))?,
+                )?;
+                String::new()
+            }
+            ["SHAPE", "SET", "RAISED_COSINE_LINEAR", x] => {
+                self.set_ramp_shape(RampShape::RaisedCosineLinear {
+                    edge_fraction: arg::<f32>(x)?,
+                })?;
+                String::new()
+            }
+            ["SHAPE", "SET", x] => {
+                self.set_ramp_shape(arg::<RampShape>(x)?)?;
+                String::new()
+            }
+            ["SHAPE", "GET"] => self.ramp_setup.shape().to_string(),
+            ["PREDISTORTION", "SET", x] => {
+                self.ramp_setup.set_predistortion(
+                    x.split(',')
+                        .map(str::parse::<f32>)
+                        .collect::<Result<Vec<f32>, _>>()
+                        .map_err(|_| CommandError::BadArgument(format!("'{x}'")))?,
+                );
+                String::new()
+            }
+            ["PREDISTORTION", "CLEAR"] => {
+                self.ramp_setup.set_predistortion(Vec::new());
+                String::new()
+            }
+            ["PREDISTORTION", "GET"] => format!("{:?}", self.ramp_setup.predistortion()),
+            _ => Err(CommandError::Unknown)?,
+        };
+        Ok(resp)
+    }
+
+    /// The file for a named snapshot, or the default state file for `None`.
+    fn state_path(&self, name: Option<&str>) -> Result<PathBuf, CommandError> {
+        match name {
+            Some(name) => self
+                .state
+                .named_path(name)
+                .ok_or_else(|| CommandError::BadArgument(format!("'{name}' (not a state name)"))),
+            None => {
+                self.state.file.clone().ok_or_else(|| {
+                    CommandError::NotPermitted("no state file configured".to_string())
+                })
+            }
+        }
+    }
+
+    /// Snapshots taken over the command socket use the output offsets logged at the end of the
+    /// last cycle, which is where the outputs still are while commands are being handled.
+    fn save_state(&self, name: Option<&str>) -> CommandResult {
+        let snapshot = Snapshot::capture(
+            self,
+            if self.is_master() {
+                self.ref_laser.feedback_log.last_n(1).next()
+            } else {
+                None
+            },
+            self.slave_laser.feedback_log.last_n(1).next(),
+        );
+        state::save(&self.state_path(name)?, &snapshot).map_err(CommandError::Failed)?;
+        Ok(String::new())
+    }
+
+    /// Restore a snapshot; the output offsets are left for the controller to apply between cycles.
+    fn load_state(&mut self, name: Option<&str>) -> CommandResult {
+        let path = self.state_path(name)?;
+        match state::load(&path).and_then(|snapshot| snapshot.restore(self)) {
+            Ok(offsets) => {
+                self.state.set_pending_offsets(offsets);
+                Ok(String::new())
+            }
+            Err(e) => Err(CommandError::Failed(format!("not restoring state: {e}"))),
+        }
+    }
+
+    fn process_state_command(&mut self, cmd: Split<'_, char>) -> CommandResult {
+        let resp = match cmd.collect::<Vec<&str>>()[..] {
+            ["SAVE"] => self.save_state(None)?,
+            ["SAVE", name] => self.save_state(Some(name))?,
+            ["LOAD"] => self.load_state(None)?,
+            ["LOAD", name] => self.load_state(Some(name))?,
+            ["LIST"] => self.state.list().join(","),
+            ["INTERVAL", "SET", x] => {
+                let secs = arg::<f32>(x)?;
+                self.state.save_interval = if secs > 0.0 {
+                    Some(
+                        Duration::try_from_secs_f32(secs)
+                            .map_err(|e| CommandError::OutOfRange(format!("{secs} s: {e}")))?,
+                    )
+                } else {
+                    None
+                };
+                String::new()
+            }
+            ["INTERVAL", "GET"] => self
+                .state
+                .save_interval
+                .map_or(0.0, |x| x.as_secs_f32())
+                .to_string(),
+            _ => Err(CommandError::Unknown)?,
         };
         Ok(resp)
     }
 
-    fn process_laser_command(&mut self, cmd: Split<'_, char>) -> Result<String, ()> {
+    fn process_laser_command(&mut self, cmd: Split<'_, char>) -> CommandResult {
         let resp = match cmd.collect::<Vec<&str>>()[..] {
             ["REF", "WAVELENGTH", "SET", x] => {
                 self.ref_laser.set_wavelength(
-                    x.parse::<f32>().or(Err(()))?,
+                    arg::<f32>(x)?,
                     self.ramp_setup.piezo_scale_factor,
                     self.ramp_setup.amplitude_volts,
                 );
@@ -118,14 +404,14 @@ impl Interferometer {
             ["REF", "WAVELENGTH", "GET"] => self.ref_laser.wavelength_nm().to_string(),
             ["SLAVE", "WAVELENGTH", "SET", x] => {
                 self.slave_laser.set_wavelength(
-                    x.parse::<f32>().or(Err(()))?,
+                    arg::<f32>(x)?,
                     self.ramp_setup.piezo_scale_factor,
                     self.ramp_setup.amplitude_volts,
                 );
                 String::new()
             }
             ["SLAVE", "WAVELENGTH", "GET"] => self.slave_laser.wavelength_nm().to_string(),
-            _ => Err(())?,
+            _ => Err(CommandError::Unknown)?,
         };
         Ok(resp)
     }
@@ -133,17 +419,58 @@ impl Interferometer {
     /// Handle an incoming command by routing it to the appropriate sufunction. Returns a String
     /// holding the response to the sender of the command.
     /// # Errors
-    /// Returns `Err(())` in case of a failure to parse a valid command in `cmd`
-    pub fn process_command(&mut self, mut cmd: Split<'_, char>) -> Result<String, ()> {
+    /// Returns a `CommandError` if `cmd` isn't a valid command, or can't be carried out
+    pub fn process_command(&mut self, mut cmd: Split<'_, char>) -> CommandResult {
         match cmd.next() {
             Some("RAMP") => self.process_ramp_command(cmd),
             Some("LASER") => self.process_laser_command(cmd),
             Some("LOCK") => match cmd.next() {
                 Some("REF") => self.ref_lock.process_command(cmd),
                 Some("SLAVE") => self.slave_lock.process_command(cmd),
-                Some(_) | None => Err(()),
+                Some(_) | None => Err(CommandError::Unknown),
             },
-            Some(_) | None => Err(()),
+            Some("SEED") => self
+                .seed_control
+                .as_mut()
+                .ok_or_else(|| not_configured("seed control"))?
+                .process_command(cmd),
+            Some("SYSTEM") => match cmd.collect::<Vec<&str>>()[..] {
+                ["SHUTDOWN"] => {
+                    self.shutdown_requested = true;
+                    Ok(String::new())
+                }
+                _ => Err(CommandError::Unknown),
+            },
+            Some("CONFIG") => match cmd.collect::<Vec<&str>>()[..] {
+                ["RELOAD"] => {
+                    self.reload_requested = true;
+                    Ok(String::new())
+                }
+                ["SAVE"] => {
+                    self.save_requested = true;
+                    Ok(String::new())
+                }
+                _ => Err(CommandError::Unknown),
+            },
+            Some("STATE") => self.process_state_command(cmd),
+            Some("FAULT") => self.fault.process_command(cmd),
+            Some("TIMING") => self.scheduler.process_command(cmd),
+            Some("WAVEMETER") => self.wavelength_meter.process_command(cmd),
+            Some("FALLING") => self
+                .falling
+                .as_mut()
+                .ok_or_else(|| not_configured("falling segment fitting"))?
+                .process_command(cmd),
+            Some("AMPL_CONTROL") => self
+                .amplitude_control
+                .as_mut()
+                .ok_or_else(|| not_configured("amplitude control"))?
+                .process_command(cmd),
+            Some(_) | None => Err(CommandError::Unknown),
         }
     }
 }
+
+fn not_configured(feature: &str) -> CommandError {
+    CommandError::NotPermitted(format!("{feature} isn't configured on this board"))
+}
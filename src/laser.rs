@@ -3,8 +3,10 @@
 use std::f32::consts::PI;
 
 use super::ring_buffer::DyadicRingBuffer;
+use super::slow_io::SlowFeedback;
 
 use librp_sys::core;
+use librp_sys::oscilloscope::Averaging;
 
 // TODO: skip logs in debug representation?
 #[derive(Debug)]
@@ -16,6 +18,9 @@ pub struct Laser {
     fringe_freq: f32,
     pub phase_log: DyadicRingBuffer<f32>,
     pub feedback_log: DyadicRingBuffer<f32>,
+    pub slow_feedback: Option<SlowFeedback>,
+    // mirrors the averaging the scope applies to `input_channel`
+    pub averaging: Averaging,
 }
 
 impl Laser {
@@ -29,6 +34,8 @@ impl Laser {
             fit_coefficients: [0.0, 0.0, 0.0, 0.0],
             phase_log: DyadicRingBuffer::new(n)?,
             feedback_log: DyadicRingBuffer::new(n)?,
+            slow_feedback: None,
+            averaging: Averaging::None,
         })
     }
 
@@ -1,8 +1,28 @@
+pub mod amplitude_control;
+pub mod cli;
+pub mod cluster;
+pub mod command;
 pub mod communications;
+pub mod config_export;
+pub mod config_model;
 pub mod configs;
+pub mod controller;
+pub mod dry_run;
+pub mod falling;
+pub mod fault;
 pub mod interferometer;
 pub mod laser;
 pub mod lock;
 pub mod multifit;
 pub mod ramp;
+pub mod ramp_shape;
+pub mod reload;
 pub mod ring_buffer;
+pub mod scale_calibration;
+pub mod scheduler;
+pub mod seed;
+pub mod shutdown;
+pub mod slow_io;
+pub mod state;
+pub mod status_leds;
+pub mod wavelength_meter;
@@ -4,6 +4,8 @@
 use std::fmt;
 use std::str::Split;
 
+use super::command::{arg, arg_in, CommandError, CommandResult};
+
 #[derive(Debug, Default)]
 pub enum Mode {
     #[default]
@@ -27,10 +29,18 @@ pub struct Servo {
     integral: f32,
     last_error: f32,
 
-    // approx. time between samples. In effect, the actual integral gain is
-    // <gain_I * sample_time_sec> and actual derivative gain is
-    // <gain_D / sample_time_sec>. There's no functional difference, but this
-    // scheme is a bit more consistent with standard PID tuning methods like Ziegler-Nichols.
+    // The derivative term acts on the measurement (error + setpoint) rather than the error, so
+    // that setpoint changes don't kick the output, and is low-pass filtered with time constant
+    // `Tf = (gain_D / gain_P) / deriv_filter_N`. A non-positive `N` disables the filter.
+    pub deriv_filter_N: f32,
+    derivative: f32,
+    last_measurement: f32,
+
+    // approx. time between updates, set from the acquisition cycle. The integral sums
+    // <error * sample_time_sec> and the derivative is <gain_D * d(measurement) / sample_time_sec>,
+    // so gain_I is in V/(rad s) and gain_D in V s/rad, and the gains don't change meaning when the
+    // ramp timing does. gain_P multiplies each update's error as is. Unset, it counts as 1, making
+    // all three gains per update.
     pub sample_time_sec: Option<f32>,
 
     setpoint: f32,
@@ -44,6 +54,7 @@ impl Servo {
     #[must_use]
     pub fn new() -> Self {
         Servo {
+            deriv_filter_N: 10.0,
             ..Default::default()
         }
     }
@@ -62,22 +73,69 @@ impl Servo {
     }
 
     fn pid_core(&mut self, err: f32) -> f32 {
-        match self.mode {
+        let measurement = err + self.setpoint;
+        let out = match self.mode {
             Mode::Enabled => {
+                let dt = self.sample_time_sec.unwrap_or(1.0);
                 self.integral *= self.alpha_I;
-                self.integral += err * self.sample_time_sec.unwrap_or(1.0);
-                let deriv_term =
-                    self.gain_D * (err - self.last_error) / self.sample_time_sec.unwrap_or(1.0);
+                self.integral += err * dt;
+                let tf = self.deriv_filter_time_sec();
+                // backward-Euler discretization of gain_D * s / (1 + s * Tf)
+                self.derivative = (tf * self.derivative
+                    + self.gain_D * (measurement - self.last_measurement))
+                    / (tf + dt);
                 let integral_term = self.gain_I * self.integral;
-                err * self.gain_P + deriv_term + integral_term
+                err * self.gain_P + self.derivative + integral_term
             }
             Mode::Disabled => 0.,
+        };
+        self.last_measurement = measurement;
+        out
+    }
+
+    /// Time constant of the low-pass filter on the derivative term, in seconds (or cycles, if
+    /// `sample_time_sec` is unset).
+    #[must_use]
+    pub fn deriv_filter_time_sec(&self) -> f32 {
+        if self.deriv_filter_N > 0.0 && self.gain_P != 0.0 {
+            (self.gain_D / (self.gain_P * self.deriv_filter_N)).abs()
+        } else {
+            0.0
+        }
+    }
+
+    /// Report the gains with their units, followed by the derivative filter time constant and the
+    /// sample time. The servo's output is a step in the output voltage at each update, so every
+    /// gain is per update: `Kp` in V/rad, `Ki` (on the error integrated over time) in V/(rad s),
+    /// and `Kd` (on the rate of change of the measurement) in V s/rad.
+    #[must_use]
+    pub fn effective_gains(&self) -> String {
+        match self.sample_time_sec {
+            Some(dt) => format!(
+                "Kp={} V/rad, Ki={} V/(rad s), Kd={} V s/rad (output steps per update), Tf={} s, \
+                 T={} s",
+                self.gain_P,
+                self.gain_I,
+                self.gain_D,
+                self.deriv_filter_time_sec(),
+                dt,
+            ),
+            None => format!(
+                "Kp={} V/rad, Ki={} V/rad, Kd={} V/rad (output steps per update), Tf={} updates, \
+                 T=unset",
+                self.gain_P,
+                self.gain_I,
+                self.gain_D,
+                self.deriv_filter_time_sec(),
+            ),
         }
     }
 
     #[inline]
     pub fn enable(&mut self) {
         self.integral = 0.0;
+        self.derivative = 0.0;
+        self.last_measurement = self.last_error + self.setpoint;
         self.mode = Mode::Enabled;
     }
 
@@ -106,6 +164,37 @@ impl Servo {
         self.alpha_I
     }
 
+    /// Change the gains without a kick in the output: the integral is rescaled so that the integral
+    /// term stays where it was. If either the old or the new integral gain is zero there's nothing
+    /// to carry over, and the integral starts again from zero.
+    #[allow(clippy::float_cmp)]
+    pub fn set_gains_bumpless(&mut self, gain_P: f32, gain_I: f32, gain_D: f32) {
+        if gain_I != self.gain_I {
+            self.integral = if gain_I == 0.0 || self.gain_I == 0.0 {
+                0.0
+            } else {
+                self.integral * self.gain_I / gain_I
+            };
+        }
+        self.gain_P = gain_P;
+        self.gain_I = gain_I;
+        self.gain_D = gain_D;
+    }
+
+    #[must_use]
+    #[inline]
+    pub fn integral(&self) -> f32 {
+        self.integral
+    }
+
+    /// Restore a saved integral, e.g. after a restart; call after `enable`, which clears it.
+    #[inline]
+    pub fn set_integral(&mut self, integral: f32) {
+        if integral.is_finite() {
+            self.integral = integral;
+        }
+    }
+
     #[inline]
     pub fn reset_integral(&mut self) {
         self.integral = 0.0;
@@ -133,33 +222,32 @@ impl Servo {
     /// Takes a split over a string command, parses the command, executes the command, and returns
     /// a string
     /// # Errors
-    /// In case of an invalid command (or inability to parse a command), returns an
-    #[allow(clippy::result_unit_err)]
-    pub fn process_command(&mut self, cmd: Split<'_, char>) -> Result<String, ()> {
+    /// In case of an invalid command (or inability to parse a command), returns a `CommandError`
+    pub fn process_command(&mut self, cmd: Split<'_, char>) -> CommandResult {
         let resp = match cmd.collect::<Vec<&str>>()[..] {
             ["GAIN_P", "SET", x] => {
-                self.gain_P = x.parse::<f32>().or(Err(()))?;
+                self.gain_P = arg::<f32>(x)?;
                 String::new()
             }
             ["GAIN_P", "GET"] => self.gain_P.to_string(),
             ["GAIN_I", "SET", x] => {
-                self.gain_I = x.parse::<f32>().or(Err(()))?;
+                self.gain_I = arg::<f32>(x)?;
                 self.reset_integral();
                 String::new()
             }
             ["GAIN_I", "GET"] => self.gain_I.to_string(),
             ["GAIN_D", "SET", x] => {
-                self.gain_D = x.parse::<f32>().or(Err(()))?;
+                self.gain_D = arg::<f32>(x)?;
                 String::new()
             }
             ["GAIN_D", "GET"] => self.gain_D.to_string(),
             ["ALPHA_I", "SET", x] => {
-                self.set_alpha_I(x.parse::<f32>().or(Err(()))?);
+                self.set_alpha_I(arg_in(x, 0.0..=1.0)?);
                 String::new()
             }
             ["ALPHA_I", "GET"] => self.alpha_I().to_string(),
             ["SETPOINT", "SET", x] => {
-                self.set_setpoint(x.parse::<f32>().or(Err(()))?);
+                self.set_setpoint(arg::<f32>(x)?);
                 String::new()
             }
             ["SETPOINT", "GET"] => self.setpoint().to_string(),
@@ -173,12 +261,66 @@ impl Servo {
             }
             ["MODE", "GET"] => self.mode.to_string(),
             ["MAX_STEP_SIZE", "SET", x] => {
-                self.max_feedback_step_size = x.parse::<f32>().or(Err(()))?;
+                self.max_feedback_step_size = arg::<f32>(x)?;
                 String::new()
             }
             ["MAX_STEP_SIZE", "GET"] => self.max_feedback_step_size.to_string(),
-            _ => Err(())?,
+            ["DERIV_FILTER_N", "SET", x] => {
+                self.deriv_filter_N = arg::<f32>(x)?;
+                String::new()
+            }
+            ["DERIV_FILTER_N", "GET"] => self.deriv_filter_N.to_string(),
+            ["SAMPLE_TIME", "GET"] => self
+                .sample_time_sec
+                .map_or_else(|| "unset".to_string(), |x| x.to_string()),
+            ["EFFECTIVE_GAINS", "GET"] => self.effective_gains(),
+            _ => Err(CommandError::Unknown)?,
         };
         Ok(resp)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_servo() -> Servo {
+        let mut servo = Servo::new();
+        servo.gain_P = 1.0;
+        servo.gain_D = 0.5;
+        servo.sample_time_sec = Some(0.1);
+        servo.max_feedback_step_size = 100.0;
+        servo.enable();
+        servo
+    }
+
+    #[test]
+    fn no_setpoint_kick() {
+        let mut servo = test_servo();
+        servo.gain_P = 0.0;
+        servo.do_pid(0.0);
+        servo.set_setpoint(1.0);
+        // the measurement hasn't changed, so the derivative term shouldn't respond to the new
+        // setpoint
+        let out = servo.do_pid(-1.0);
+        assert!(out.abs() < 1e-6);
+    }
+
+    #[test]
+    fn filtered_derivative_step() {
+        let mut servo = test_servo();
+        servo.do_pid(0.0);
+        let unfiltered = {
+            let mut s = test_servo();
+            s.deriv_filter_N = 0.0;
+            s.do_pid(0.0);
+            s.do_pid(1.0) - 1.0
+        };
+        let first = servo.do_pid(1.0) - 1.0;
+        let second = servo.do_pid(1.0) - 1.0;
+        assert!((unfiltered - 5.0).abs() < 1e-5);
+        // Tf = 0.5 / (1.0 * 10) = 0.05 s, so the first response is 0.5 / (0.05 + 0.1)
+        assert!((first - 0.5 / 0.15).abs() < 1e-5);
+        assert!(second > 0.0 && second < first);
+    }
+}
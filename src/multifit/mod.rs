@@ -26,6 +26,12 @@ pub fn wrapped_angle_difference(a: f32, b: f32) -> f32 {
     (a.sin() * b.cos() - a.cos() * b.sin()).atan2(a.cos() * b.cos() + a.sin() * b.sin())
 }
 
+/// The angle halfway between `a` and `b`, the short way round.
+#[must_use]
+pub fn wrapped_angle_mean(a: f32, b: f32) -> f32 {
+    a + wrapped_angle_difference(b, a) / 2.0
+}
+
 #[must_use]
 pub fn sinusoid(x: f32, p: [f32; 4]) -> f32 {
     p[0] * (p[1] * x - p[2]).cos() + p[3]
@@ -53,7 +59,7 @@ struct FitResultRaw {
     params: [f32; 4],
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct FitResult {
     pub gsl_status: i32,
     pub n_iterations: i32,
@@ -108,7 +114,8 @@ impl FitSetup {
             gtol,
             ftol,
             max_av_ratio,
-            low_contrast_threshold: 100.0,
+            // in the scope's units (volts or raw counts), so it has to come from the config
+            low_contrast_threshold: 0.0,
         };
         match unsafe { init_multifit_setup(ptr::addr_of_mut!(setup)) } {
             0 => Some(setup),
@@ -5,12 +5,20 @@
     clippy::cast_lossless
 )]
 #![allow(clippy::module_name_repetitions)]
-use std::f32::consts::PI;
 
 use librp_sys::core::{APIResult, ADC_SAMPLE_RATE};
 use librp_sys::generator::{DCChannel, PulseChannel};
 use librp_sys::oscilloscope::Oscilloscope;
 
+use super::ramp_shape::{self, RampShape};
+
+/// Number of samples the scope takes over the falling segment of the ramp: the rise fills the
+/// 16384-sample buffer, and the fall lasts `(1 - symmetry) / symmetry` as long.
+#[must_use]
+pub fn falling_points(symmetry: f32) -> usize {
+    (16384.0 * (1.0 - symmetry) / symmetry).round() as usize
+}
+
 #[derive(Debug)]
 pub struct DaqSetup {
     decimation: u32,
@@ -22,6 +30,10 @@ pub struct DaqSetup {
     pub piezo_settle_time_ms: f32,
     ramp_period_us: u64,
     piezo_settle_time_us: u64,
+    fit_falling: bool,
+    shape: RampShape,
+    predistortion: Vec<f32>,
+    pending_apply: bool,
 }
 
 impl DaqSetup {
@@ -37,6 +49,10 @@ impl DaqSetup {
             piezo_settle_time_ms: 2.0,
             ramp_period_us: 1_000_000,
             piezo_settle_time_us: 2000,
+            fit_falling: false,
+            shape: RampShape::LinearCosine,
+            predistortion: Vec::new(),
+            pending_apply: false,
         }
     }
 
@@ -50,19 +66,18 @@ impl DaqSetup {
         ref_ch: Option<&mut PulseChannel>,
         slave_ch: &mut DCChannel,
     ) -> APIResult<()> {
-        // Create the voltage ramp waveform:
-        let steps_up = (16384.0 * self.symmetry) as u16;
-        let mut waveform = Vec::<f32>::with_capacity(16384);
-        for i in 0..steps_up {
-            waveform.push(1.0 * (i as f32) / (steps_up as f32) - 0.5);
-        }
-        for i in steps_up..16384 {
-            waveform.push(
-                0.5 * f32::cos(PI * (i as f32 - steps_up as f32) / (16384 - steps_up) as f32),
-            );
+        // Create the voltage ramp waveform. The default shape returns along a cosine, to be gentle
+        // on the piezo; if we're fitting the falling segment, though, it has to be linear too.
+        let mut waveform = match self.shape {
+            RampShape::LinearCosine if self.fit_falling => RampShape::Triangle,
+            _ => self.shape.clone(),
         }
+        .generate(self.symmetry);
+        ramp_shape::predistort(&mut waveform, &self.predistortion);
+        self.pending_apply = false;
 
         osc.set_decimation(self.decimation)?;
+        osc.set_trigger_delay(self.trigger_delay())?;
         if let Some(ref_ch) = ref_ch {
             ref_ch.set_period(self.ramp_period_s)?;
             ref_ch.set_amplitude(self.amplitude_volts)?;
@@ -109,6 +124,14 @@ impl DaqSetup {
     pub fn piezo_settle_time_us(&self) -> u64 {
         self.piezo_settle_time_us
     }
+    /// Approximate time between successive acquisitions: one full ramp, plus the settling time
+    /// we wait afterwards. This is the sample time seen by the servos.
+    #[inline]
+    #[must_use]
+    #[allow(clippy::cast_precision_loss)]
+    pub fn cycle_period_s(&self) -> f32 {
+        (self.ramp_period_us + self.piezo_settle_time_us) as f32 * 1.0e-6
+    }
 
     pub fn set_symmetry(&mut self, symm: f32) -> &mut Self {
         self.symmetry = symm;
@@ -134,6 +157,68 @@ impl DaqSetup {
         self
     }
 
+    /// Change the shape of the ramp. Since this needs the output channels, it doesn't take effect
+    /// until the owner of the channels notices `take_pending_apply` and calls `apply`.
+    pub fn set_shape(&mut self, shape: RampShape) -> &mut Self {
+        self.shape = shape;
+        self.pending_apply = true;
+        self
+    }
+    #[inline]
+    #[must_use]
+    pub fn shape(&self) -> &RampShape {
+        &self.shape
+    }
+
+    /// Coefficients of the pre-distortion polynomial applied to the ramp (see
+    /// `ramp_shape::predistort`). Like `set_shape`, takes effect on the next `apply`.
+    pub fn set_predistortion(&mut self, coeffs: Vec<f32>) -> &mut Self {
+        self.predistortion = coeffs;
+        self.pending_apply = true;
+        self
+    }
+    #[inline]
+    #[must_use]
+    pub fn predistortion(&self) -> &[f32] {
+        &self.predistortion
+    }
+
+    /// Ask for the settings to be reapplied to the outputs, e.g. after changing the amplitude.
+    pub fn request_apply(&mut self) -> &mut Self {
+        self.pending_apply = true;
+        self
+    }
+
+    /// Returns true (once) if the ramp settings have changed such that `apply` needs calling.
+    pub fn take_pending_apply(&mut self) -> bool {
+        std::mem::take(&mut self.pending_apply)
+    }
+
+    /// Scope trigger delay: the whole buffer is written after the trigger, so that it holds the
+    /// rising segment. When fitting the falling segment, the acquisition carries on through the
+    /// fall, which overwrites the first `falling_points` samples of the rise; both segments are
+    /// then taken from the one hardware-triggered acquisition, at fixed positions along the ramp.
+    #[must_use]
+    pub fn trigger_delay(&self) -> i32 {
+        let falling = if self.fit_falling {
+            falling_points(self.symmetry)
+        } else {
+            0
+        };
+        8192 + i32::try_from(falling).unwrap_or(i32::MAX - 8192)
+    }
+
+    /// Also acquire and fit the falling segment of the ramp. Takes effect on the next `apply`.
+    pub fn set_fit_falling(&mut self, fit_falling: bool) -> &mut Self {
+        self.fit_falling = fit_falling;
+        self
+    }
+    #[inline]
+    #[must_use]
+    pub fn fit_falling(&self) -> bool {
+        self.fit_falling
+    }
+
     pub fn amplitude(&mut self, volts: f32) -> &mut Self {
         self.amplitude_volts = volts;
         self
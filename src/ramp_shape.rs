@@ -0,0 +1,232 @@
+#![warn(clippy::pedantic)]
+#![allow(
+    clippy::cast_possible_truncation,
+    clippy::cast_sign_loss,
+    clippy::cast_precision_loss,
+    clippy::module_name_repetitions
+)]
+use std::f32::consts::PI;
+use std::fmt;
+
+/// Number of points in the AWG's arbitrary waveform buffer.
+pub const RAMP_POINTS: usize = 16384;
+
+/// Shape of the interferometer's piezo ramp. Every shape is generated as `RAMP_POINTS` points
+/// running from -0.5 to 0.5, scaled by the ramp amplitude on output. The rising portion of the
+/// ramp lasts `symmetry` of the period, and is what gets acquired and fit.
+#[derive(Debug, Clone, PartialEq)]
+pub enum RampShape {
+    /// Linear rise, with a cosine return that's gentle on the piezo
+    LinearCosine,
+    /// Linear rise and linear return
+    Triangle,
+    /// Symmetric sinusoid, ignoring `symmetry` (which therefore has to be 0.5 for the acquisition
+    /// to line up); for piezos that don't like sharp corners. Note that the fringes are then chirped,
+    /// so the fit model only holds near the middle of the rise
+    Sine,
+    /// Linear rise and return, with the corners rounded off by raised-cosine edges spanning
+    /// `edge_fraction` of each segment
+    RaisedCosineLinear { edge_fraction: f32 },
+    /// User-supplied waveform covering the whole period (so ignoring `symmetry`, which has to be
+    /// 0.5), resampled to `RAMP_POINTS` and normalized to [-0.5, 0.5]
+    Custom(Vec<f32>),
+}
+
+impl std::str::FromStr for RampShape {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "linear_cosine" | "LINEAR_COSINE" => Ok(RampShape::LinearCosine),
+            "triangle" | "TRIANGLE" => Ok(RampShape::Triangle),
+            "sine" | "SINE" => Ok(RampShape::Sine),
+            "raised_cosine_linear" | "RAISED_COSINE_LINEAR" => Ok(RampShape::RaisedCosineLinear {
+                edge_fraction: 0.05,
+            }),
+            _ => Err(()),
+        }
+    }
+}
+
+impl fmt::Display for RampShape {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RampShape::LinearCosine => write!(f, "LINEAR_COSINE"),
+            RampShape::Triangle => write!(f, "TRIANGLE"),
+            RampShape::Sine => write!(f, "SINE"),
+            RampShape::RaisedCosineLinear { edge_fraction } => {
+                write!(f, "RAISED_COSINE_LINEAR ({edge_fraction})")
+            }
+            RampShape::Custom(points) => write!(f, "CUSTOM ({} points)", points.len()),
+        }
+    }
+}
+
+impl RampShape {
+    /// Build a custom shape from user-supplied points, e.g. from a file or a command. Returns
+    /// `None` if there are fewer than two points, or if they're all equal.
+    #[must_use]
+    pub fn custom(points: &[f32]) -> Option<Self> {
+        if points.len() < 2 || points.iter().any(|x| !x.is_finite()) {
+            return None;
+        }
+        let min = points.iter().copied().fold(f32::INFINITY, f32::min);
+        let max = points.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+        if max <= min {
+            return None;
+        }
+        Some(RampShape::Custom(
+            resample(points, RAMP_POINTS)
+                .into_iter()
+                .map(|x| (x - min) / (max - min) - 0.5)
+                .collect(),
+        ))
+    }
+
+    /// Parse a custom shape from comma-, whitespace- or newline-separated values, e.g. the
+    /// contents of a CSV file.
+    #[must_use]
+    pub fn custom_from_str(text: &str) -> Option<Self> {
+        let points = text
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|x| !x.is_empty())
+            .map(str::parse::<f32>)
+            .collect::<Result<Vec<f32>, _>>()
+            .ok()?;
+        RampShape::custom(&points)
+    }
+
+    /// Whether the shape fixes its own rise time, rather than following `symmetry`. The
+    /// acquisition still assumes the rise lasts `symmetry` of the period, so these shapes need a
+    /// symmetry of 0.5.
+    #[must_use]
+    pub fn ignores_symmetry(&self) -> bool {
+        matches!(self, RampShape::Sine | RampShape::Custom(_))
+    }
+
+    /// Generate the waveform, with the rising portion lasting `symmetry` of the period.
+    #[must_use]
+    pub fn generate(&self, symmetry: f32) -> Vec<f32> {
+        let steps_up = ((RAMP_POINTS as f32 * symmetry) as usize).clamp(1, RAMP_POINTS - 1);
+        let steps_down = RAMP_POINTS - steps_up;
+        let mut waveform = Vec::<f32>::with_capacity(RAMP_POINTS);
+        match self {
+            RampShape::LinearCosine => {
+                waveform.extend((0..steps_up).map(|i| i as f32 / steps_up as f32 - 0.5));
+                waveform.extend(
+                    (0..steps_down).map(|i| 0.5 * f32::cos(PI * i as f32 / steps_down as f32)),
+                );
+            }
+            RampShape::Triangle => {
+                waveform.extend((0..steps_up).map(|i| i as f32 / steps_up as f32 - 0.5));
+                waveform.extend((0..steps_down).map(|i| 0.5 - i as f32 / steps_down as f32));
+            }
+            RampShape::Sine => {
+                waveform.extend(
+                    (0..RAMP_POINTS)
+                        .map(|i| -0.5 * f32::cos(2.0 * PI * i as f32 / RAMP_POINTS as f32)),
+                );
+            }
+            RampShape::RaisedCosineLinear { edge_fraction } => {
+                let edge = edge_fraction.clamp(0.0, 0.5);
+                waveform.extend(
+                    (0..steps_up).map(|i| rounded_linear(i as f32 / steps_up as f32, edge) - 0.5),
+                );
+                waveform.extend(
+                    (0..steps_down)
+                        .map(|i| 0.5 - rounded_linear(i as f32 / steps_down as f32, edge)),
+                );
+            }
+            RampShape::Custom(points) => waveform.extend_from_slice(points),
+        }
+        waveform
+    }
+}
+
+/// Apply a pre-distortion polynomial `sum_k coeffs[k] * x^k` to each point of `waveform`, e.g. to
+/// invert a measured piezo response so that the displacement is linear in time. The result is
+/// renormalized to [-0.5, 0.5], so the output still spans exactly the ramp amplitude (or clamped,
+/// if the polynomial flattens it out). An empty slice leaves the waveform untouched.
+pub fn predistort(waveform: &mut [f32], coeffs: &[f32]) {
+    if coeffs.is_empty() {
+        return;
+    }
+    for x in waveform.iter_mut() {
+        *x = coeffs.iter().rev().fold(0.0, |acc, c| acc * *x + c);
+    }
+    let min = waveform.iter().copied().fold(f32::INFINITY, f32::min);
+    let max = waveform.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+    for x in waveform.iter_mut() {
+        *x = if max > min {
+            (*x - min) / (max - min) - 0.5
+        } else {
+            x.clamp(-0.5, 0.5)
+        };
+    }
+}
+
+/// Monotonic map from [0, 1] onto [0, 1] that's linear in the middle, with zero slope at either
+/// end: over the first and last `edge` of the interval the slope follows a raised cosine.
+fn rounded_linear(t: f32, edge: f32) -> f32 {
+    if edge <= 0.0 {
+        return t;
+    }
+    // each edge covers half the distance a linear segment would, so the slope of the linear
+    // middle has to be 1 / (1 - edge) for the whole thing to span [0, 1]
+    let k = 1.0 / (1.0 - edge);
+    let edge_rise = |u: f32| 0.5 * k * (u - edge / PI * (PI * u / edge).sin());
+    if t < edge {
+        edge_rise(t)
+    } else if t > 1.0 - edge {
+        1.0 - edge_rise(1.0 - t)
+    } else {
+        0.5 * k * edge + k * (t - edge)
+    }
+}
+
+fn resample(points: &[f32], n: usize) -> Vec<f32> {
+    let scale = (points.len() - 1) as f32 / (n - 1) as f32;
+    (0..n)
+        .map(|i| {
+            let x = i as f32 * scale;
+            let lo = (x.floor() as usize).min(points.len() - 2);
+            let frac = x - lo as f32;
+            points[lo] * (1.0 - frac) + points[lo + 1] * frac
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shapes_in_range() {
+        for shape in [
+            RampShape::LinearCosine,
+            RampShape::Triangle,
+            RampShape::Sine,
+            RampShape::RaisedCosineLinear { edge_fraction: 0.1 },
+            RampShape::custom(&[0.0, 3.0, 1.0]).unwrap(),
+        ] {
+            let wf = shape.generate(0.8);
+            assert_eq!(wf.len(), RAMP_POINTS);
+            assert!(
+                wf.iter().all(|x| (-0.5 - 1e-4..=0.5 + 1e-4).contains(x)),
+                "{shape}"
+            );
+        }
+        let rounded = RampShape::RaisedCosineLinear { edge_fraction: 0.1 }.generate(0.5);
+        assert!(rounded.windows(2).take(8191).all(|w| w[1] >= w[0]));
+        assert!((rounded[8191] - 0.5).abs() < 1e-3);
+    }
+
+    #[test]
+    fn predistortion() {
+        let mut wf = vec![-0.5, 0.0, 0.5];
+        // -0.375, 0.0, 0.625 before renormalizing
+        predistort(&mut wf, &[0.0, 1.0, 0.5]);
+        assert!((wf[0] - -0.5).abs() < 1e-6);
+        assert!((wf[1] - -0.125).abs() < 1e-6);
+        assert!((wf[2] - 0.5).abs() < 1e-6);
+    }
+}
@@ -0,0 +1,736 @@
+#![warn(clippy::pedantic)]
+#![allow(clippy::module_name_repetitions)]
+
+use std::fmt::{self, Debug};
+use std::fs;
+use std::path::PathBuf;
+use std::time::{Duration, Instant, SystemTime};
+
+use librp_sys::core::Channel;
+
+use super::communications::InterfComms;
+use super::config_export::{self, Exported};
+use super::config_model::{Config, LaserSection};
+use super::configs;
+use super::interferometer::Interferometer;
+use super::lock::Servo;
+use super::multifit::FitSetup;
+use super::shutdown::Parking;
+use super::wavelength_meter::WavelengthMeter;
+
+/// What a config reload did, by how disruptive each change is: applied right away, applied to
+/// the ramp at the end of the current cycle, or left alone until the next restart.
+#[derive(Debug, Default)]
+pub struct ReloadReport {
+    pub applied: Vec<String>,
+    pub scheduled: Vec<String>,
+    pub needs_restart: Vec<String>,
+    pub warnings: Vec<String>,
+}
+
+impl fmt::Display for ReloadReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let parts = [
+            ("applied", &self.applied),
+            ("at the next ramp", &self.scheduled),
+            ("needs a restart", &self.needs_restart),
+            ("warnings", &self.warnings),
+        ]
+        .into_iter()
+        .filter(|(_, changes)| !changes.is_empty())
+        .map(|(what, changes)| format!("{what} [{}]", changes.join(", ")))
+        .collect::<Vec<String>>();
+        if parts.is_empty() {
+            write!(f, "config reloaded, no changes")
+        } else {
+            write!(f, "config reloaded: {}", parts.join("; "))
+        }
+    }
+}
+
+/// Note `what` in `changes` if it differs between `old` and `new`, and say whether it did.
+fn diff<T: PartialEq + Debug>(changes: &mut Vec<String>, what: &str, old: &T, new: &T) -> bool {
+    if old == new {
+        return false;
+    }
+    changes.push(format!("{what} {old:?} -> {new:?}"));
+    true
+}
+
+/// Settings only read when setting up the hardware, the sockets, or the fits' workspaces; these
+/// are compared against the config the lock was started with, so they keep being reported until
+/// the next restart.
+#[allow(clippy::too_many_lines)]
+fn restart_changes(started: &Config, new: &Config, board: &str) -> Result<Vec<String>, String> {
+    let mut out = Vec::new();
+    let (old_g, new_g) = (&started.general, &new.general);
+    diff(&mut out, "logs_port", &old_g.logs_port, &new_g.logs_port);
+    diff(
+        &mut out,
+        "command_port",
+        &old_g.command_port,
+        &new_g.command_port,
+    );
+    diff(
+        &mut out,
+        "master_external_trigger_output_pin",
+        &old_g.master_external_trigger_output_pin,
+        &new_g.master_external_trigger_output_pin,
+    );
+    diff(
+        &mut out,
+        "ready_to_acquire_pin",
+        &old_g.ready_to_acquire_pin,
+        &new_g.ready_to_acquire_pin,
+    );
+    diff(
+        &mut out,
+        "scope_units",
+        &old_g.scope_units,
+        &new_g.scope_units,
+    );
+    diff(
+        &mut out,
+        "log length exponent",
+        &started.log_length_exponent(),
+        &new.log_length_exponent(),
+    );
+    diff(
+        &mut out,
+        "debug_list_freq_cycles",
+        &old_g.debug_list_freq_cycles,
+        &new_g.debug_list_freq_cycles,
+    );
+    diff(&mut out, "leds", &started.leds, &new.leds);
+
+    let (old_r, new_r) = (&started.ramp, &new.ramp);
+    diff(
+        &mut out,
+        "decimation_factor",
+        &old_r.decimation_factor,
+        &new_r.decimation_factor,
+    );
+    diff(
+        &mut out,
+        "symmetry_factor",
+        &old_r.symmetry_factor,
+        &new_r.symmetry_factor,
+    );
+    diff(
+        &mut out,
+        "fit_falling",
+        &old_r.fit_falling,
+        &new_r.fit_falling,
+    );
+
+    let (old_b, new_b) = (started.board(board)?, new.board(board)?);
+    diff(&mut out, "is_master", &old_b.is_master, &new_b.is_master);
+    diff(
+        &mut out,
+        "slave_laser",
+        &old_b.slave_laser,
+        &new_b.slave_laser,
+    );
+    diff(
+        &mut out,
+        "channel assignment",
+        &(
+            old_b.ref_input_channel,
+            old_b.ref_output_channel,
+            old_b.slave_input_channel,
+            old_b.slave_output_channel,
+        ),
+        &(
+            new_b.ref_input_channel,
+            new_b.ref_output_channel,
+            new_b.slave_input_channel,
+            new_b.slave_output_channel,
+        ),
+    );
+    for ch in [Channel::CH_1, Channel::CH_2] {
+        diff(
+            &mut out,
+            &format!("{ch:?} output range"),
+            &old_b.output(ch),
+            &new_b.output(ch),
+        );
+        diff(
+            &mut out,
+            &format!("{ch:?} input"),
+            &old_b.input(ch),
+            &new_b.input(ch),
+        );
+    }
+    diff(
+        &mut out,
+        "averaging",
+        &(old_b.ch_1_averaging, old_b.ch_2_averaging),
+        &(new_b.ch_1_averaging, new_b.ch_2_averaging),
+    );
+    diff(
+        &mut out,
+        "slow outputs",
+        &(old_b.ref_slow_output, old_b.slave_slow_output),
+        &(new_b.ref_slow_output, new_b.slave_slow_output),
+    );
+    diff(
+        &mut out,
+        "monitor_inputs",
+        &old_b.monitor_inputs,
+        &new_b.monitor_inputs,
+    );
+
+    let (_, old_slave) = started.slave_laser(board)?;
+    let (_, new_slave) = new.slave_laser(board)?;
+    for (what, old, new) in [
+        ("ref_laser", &started.ref_laser, &new.ref_laser),
+        ("slave laser", old_slave, new_slave),
+    ] {
+        diff(
+            &mut out,
+            &format!("{what} fit region"),
+            &(old.fit.roi, old.fit.falling_roi),
+            &(new.fit.roi, new.fit.falling_roi),
+        );
+    }
+    Ok(out)
+}
+
+/// Change the servo's gains, decay rate, step size, derivative filter and setpoint to those in
+/// `new`.
+fn retune(
+    changes: &mut Vec<String>,
+    what: &str,
+    servo: &mut Servo,
+    old: &LaserSection,
+    new: &LaserSection,
+) {
+    let gains = diff(changes, &format!("{what} gain_p"), &old.gain_p, &new.gain_p)
+        | diff(changes, &format!("{what} gain_i"), &old.gain_i, &new.gain_i)
+        | diff(changes, &format!("{what} gain_d"), &old.gain_d, &new.gain_d);
+    if gains {
+        servo.set_gains_bumpless(new.gain_p, new.gain_i, new.gain_d);
+    }
+    if diff(
+        changes,
+        &format!("{what} integral_decay_rate"),
+        &old.integral_decay_rate,
+        &new.integral_decay_rate,
+    ) {
+        servo.set_alpha_I(new.integral_decay_rate);
+    }
+    if diff(
+        changes,
+        &format!("{what} feedback_max_step_size_v"),
+        &old.feedback_max_step_size_v,
+        &new.feedback_max_step_size_v,
+    ) {
+        servo.max_feedback_step_size = new.feedback_max_step_size_v;
+    }
+    if diff(
+        changes,
+        &format!("{what} derivative_filter_n"),
+        &old.derivative_filter_n,
+        &new.derivative_filter_n,
+    ) {
+        servo.deriv_filter_N = new
+            .derivative_filter_n
+            .unwrap_or_else(|| Servo::new().deriv_filter_N);
+    }
+    if diff(
+        changes,
+        &format!("{what} setpoint"),
+        &old.setpoint,
+        &new.setpoint,
+    ) {
+        servo.set_setpoint(new.setpoint.unwrap_or(0.0));
+    }
+}
+
+/// Update the fit settings that are read on every fit (rather than when allocating the fit's
+/// workspace).
+fn refit(
+    changes: &mut Vec<String>,
+    what: &str,
+    fits: [Option<&mut FitSetup>; 2],
+    old: &LaserSection,
+    new: &LaserSection,
+) {
+    let (old, new) = (&old.fit, &new.fit);
+    let any = diff(
+        changes,
+        &format!("{what} max_iterations"),
+        &old.max_iterations,
+        &new.max_iterations,
+    ) | diff(
+        changes,
+        &format!("{what} tolerances"),
+        &(old.xtol, old.gtol, old.ftol),
+        &(new.xtol, new.gtol, new.ftol),
+    ) | diff(
+        changes,
+        &format!("{what} max_av_ratio"),
+        &old.max_av_ratio,
+        &new.max_av_ratio,
+    ) | diff(
+        changes,
+        &format!("{what} low_contrast_threshold"),
+        &old.low_contrast_threshold,
+        &new.low_contrast_threshold,
+    );
+    if any {
+        for fit in fits.into_iter().flatten() {
+            fit.max_iterations = new.max_iterations;
+            fit.xtol = new.xtol;
+            fit.gtol = new.gtol;
+            fit.ftol = new.ftol;
+            fit.max_av_ratio = new.max_av_ratio;
+            fit.low_contrast_threshold = new.low_contrast_threshold;
+        }
+    }
+}
+
+/// Bring the running lock in line with `new`, given the config it was started with and the one
+/// it's currently running (the last one applied). Servo, fit, timing, fault, seed, wavelength meter,
+/// amplitude control, parking and state settings are applied straight away; servo gains without a
+/// kick in the output. Ramp settings that need the output channels are applied between ramps.
+/// Anything that would need the hardware, sockets or fits set up again is left for a restart.
+/// # Errors
+/// Returns a message if `new` can't be used at all for this board, in which case nothing was
+/// changed
+#[allow(clippy::too_many_lines)]
+pub fn apply(
+    started: &Config,
+    current: &Config,
+    new: &Config,
+    board: &str,
+    interf: &mut Interferometer,
+    comms: &mut InterfComms,
+    parking: &mut Parking,
+) -> Result<ReloadReport, String> {
+    // anything that can fail goes first, so that a bad config leaves the lock untouched
+    let needs_restart = restart_changes(started, new, board)?;
+    let new_ramp = configs::ramp_from_config(new)?;
+    let new_parking = configs::parking_from_config(new, board)?;
+    let new_seed = configs::seed_from_config(new, board)?;
+    let mut new_meter = WavelengthMeter::new(256);
+    configs::wavelength_meter_from_config(new, board, &mut new_meter)?;
+    let (_, old_slave) = current.slave_laser(board)?;
+    let (_, new_slave) = new.slave_laser(board)?;
+
+    let mut report = ReloadReport {
+        needs_restart,
+        ..ReloadReport::default()
+    };
+    let applied = &mut report.applied;
+
+    retune(
+        applied,
+        "ref lock",
+        &mut interf.ref_lock,
+        &current.ref_laser,
+        &new.ref_laser,
+    );
+    retune(
+        applied,
+        "slave lock",
+        &mut interf.slave_lock,
+        old_slave,
+        new_slave,
+    );
+    let (falling_ref, falling_slave) = match interf.falling.as_mut() {
+        Some(falling) => (
+            Some(&mut falling.fit_setup_ref),
+            Some(&mut falling.fit_setup_slave),
+        ),
+        None => (None, None),
+    };
+    refit(
+        applied,
+        "ref fit",
+        [Some(&mut interf.fit_setup_ref), falling_ref],
+        &current.ref_laser,
+        &new.ref_laser,
+    );
+    refit(
+        applied,
+        "slave fit",
+        [Some(&mut interf.fit_setup_slave), falling_slave],
+        old_slave,
+        new_slave,
+    );
+
+    let (old_g, new_g) = (&current.general, &new.general);
+    if diff(
+        applied,
+        "logs_publish_freq_cycles",
+        &old_g.logs_publish_freq_cycles,
+        &new_g.logs_publish_freq_cycles,
+    ) {
+        comms.set_log_publish_frequency(new_g.logs_publish_freq_cycles.unwrap_or(256).max(1));
+    }
+    if diff(
+        applied,
+        "timing",
+        &(
+            old_g.handshake_timeout_ms,
+            old_g.trigger_timeout_ms,
+            old_g.poll_interval_us,
+            old_g.deadline_spin_us,
+        ),
+        &(
+            new_g.handshake_timeout_ms,
+            new_g.trigger_timeout_ms,
+            new_g.poll_interval_us,
+            new_g.deadline_spin_us,
+        ),
+    ) {
+        let scheduler = configs::scheduler_from_config(new);
+        interf.scheduler.handshake_timeout = scheduler.handshake_timeout;
+        interf.scheduler.trigger_timeout = scheduler.trigger_timeout;
+        interf.scheduler.poll_interval = scheduler.poll_interval;
+        interf.scheduler.spin = scheduler.spin;
+    }
+    if diff(
+        applied,
+        "fault settings",
+        &(old_g.timeouts_to_fault, old_g.fault_retry_interval_ms),
+        &(new_g.timeouts_to_fault, new_g.fault_retry_interval_ms),
+    ) {
+        let fault = configs::fault_from_config(new);
+        interf.fault.timeouts_to_fault = fault.timeouts_to_fault;
+        interf.fault.retry_interval = fault.retry_interval;
+    }
+    let (old_b, new_b) = (current.board(board)?, new.board(board)?);
+    if diff(
+        applied,
+        "parking",
+        &(
+            old_b.ref_park_v,
+            old_b.slave_park_v,
+            old_g.park_slew_rate_v_per_s,
+        ),
+        &(
+            new_b.ref_park_v,
+            new_b.slave_park_v,
+            new_g.park_slew_rate_v_per_s,
+        ),
+    ) {
+        *parking = new_parking;
+    }
+    if diff(
+        applied,
+        "state settings",
+        &(
+            &old_g.state_file,
+            old_g.state_save_interval_s,
+            old_g.restore_state,
+        ),
+        &(
+            &new_g.state_file,
+            new_g.state_save_interval_s,
+            new_g.restore_state,
+        ),
+    ) {
+        let state = configs::state_store_from_config(new, &interf.state.dir);
+        interf.state.file = state.file;
+        interf.state.save_interval = state.save_interval;
+        interf.state.restore_on_startup = state.restore_on_startup;
+    }
+
+    if diff(
+        applied,
+        "slave seed_control",
+        &old_slave.seed_control,
+        &new_slave.seed_control,
+    ) {
+        match (interf.seed_control.as_mut(), new_seed) {
+            // keep the monitor's state, in case it's in the middle of recovering injection
+            (Some(seed), Some(new_seed)) => {
+                seed.timeout_sec = new_seed.timeout_sec;
+                seed.loop_cycle_sec = new_seed.loop_cycle_sec;
+                seed.threshold_volts = new_seed.threshold_volts;
+                seed.adjustment_size_volts = new_seed.adjustment_size_volts;
+                seed.source = new_seed.source;
+                seed.output = new_seed.output;
+            }
+            (_, new_seed) => interf.seed_control = new_seed,
+        }
+    }
+    if diff(
+        applied,
+        "slave wavelength_meter",
+        &old_slave.wavelength_meter,
+        &new_slave.wavelength_meter,
+    ) {
+        let meter = &mut interf.wavelength_meter;
+        meter.enabled = new_meter.enabled;
+        meter.cycles = new_meter.cycles;
+        meter.feedback = new_meter.feedback;
+        meter.mode_hop_threshold_nm = new_meter.mode_hop_threshold_nm;
+        meter.reset();
+    }
+    if interf.is_master()
+        && diff(
+            applied,
+            "amplitude_control",
+            &current.ramp.amplitude_control,
+            &new.ramp.amplitude_control,
+        )
+    {
+        match (
+            interf.amplitude_control.as_mut(),
+            configs::amplitude_control_from_config(new),
+        ) {
+            (Some(ctrl), Some(new_ctrl)) => {
+                ctrl.target_freq = new_ctrl.target_freq;
+                ctrl.gain = new_ctrl.gain;
+                ctrl.max_step_v = new_ctrl.max_step_v;
+                ctrl.min_v = new_ctrl.min_v;
+                ctrl.max_v = new_ctrl.max_v;
+                ctrl.interval_cycles = new_ctrl.interval_cycles;
+            }
+            (_, new_ctrl) => interf.amplitude_control = new_ctrl,
+        }
+    }
+
+    // the servos' integrals are only cleared for changes of fringe frequency, as for the
+    // equivalent commands
+    let (old_r, new_r) = (&current.ramp, &new.ramp);
+    let fringes = diff(
+        &mut report.scheduled,
+        "amplitude_volts",
+        &old_r.amplitude_volts,
+        &new_r.amplitude_volts,
+    ) | diff(
+        &mut report.scheduled,
+        "piezo_scale_factor",
+        &old_r.piezo_scale_factor,
+        &new_r.piezo_scale_factor,
+    );
+    let wavelengths = diff(
+        &mut report.applied,
+        "ref_laser wavelength_nm",
+        &current.ref_laser.wavelength_nm,
+        &new.ref_laser.wavelength_nm,
+    ) | diff(
+        &mut report.applied,
+        "slave laser wavelength_nm",
+        &old_slave.wavelength_nm,
+        &new_slave.wavelength_nm,
+    );
+    let settle = diff(
+        &mut report.scheduled,
+        "piezo_settle_time_ms",
+        &old_r.piezo_settle_time_ms,
+        &new_r.piezo_settle_time_ms,
+    );
+    let shape = diff(
+        &mut report.scheduled,
+        "ramp shape",
+        &(&old_r.shape, old_r.edge_fraction, &old_r.custom_waveform),
+        &(&new_r.shape, new_r.edge_fraction, &new_r.custom_waveform),
+    ) | diff(
+        &mut report.scheduled,
+        "predistortion",
+        &old_r.predistortion,
+        &new_r.predistortion,
+    );
+
+    let ramp = &mut interf.ramp_setup;
+    if fringes {
+        ramp.amplitude(new_ramp.amplitude_volts)
+            .piezo_scale_factor(new_ramp.piezo_scale_factor)
+            .request_apply();
+    }
+    if settle {
+        ramp.piezo_settle_time_ms(new_ramp.piezo_settle_time_ms)
+            .request_apply();
+    }
+    if shape {
+        ramp.set_shape(new_ramp.shape().clone())
+            .set_predistortion(new_ramp.predistortion().to_vec());
+    }
+    if wavelengths {
+        interf.ref_laser.set_wavelength(
+            new.ref_laser.wavelength_nm,
+            interf.ramp_setup.piezo_scale_factor,
+            interf.ramp_setup.amplitude_volts,
+        );
+        interf.slave_laser.set_wavelength(
+            new_slave.wavelength_nm,
+            interf.ramp_setup.piezo_scale_factor,
+            interf.ramp_setup.amplitude_volts,
+        );
+    }
+    if fringes {
+        interf.update_fringe_params();
+    }
+    if settle {
+        interf.update_sample_times();
+    }
+    Ok(report)
+}
+
+/// The config file the lock was started from, checked for changes every `poll_interval` (or only
+/// on `CONFIG:RELOAD`, if that's `None`).
+#[derive(Debug)]
+pub struct ConfigWatch {
+    pub path: PathBuf,
+    pub board: String,
+    pub poll_interval: Option<Duration>,
+    started: Config,
+    current: Config,
+    modified: Option<SystemTime>,
+    last_poll: Option<Instant>,
+}
+
+impl ConfigWatch {
+    /// Watch `path`, from which `cfg` was read (before any command-line overrides), for the
+    /// section of `board`.
+    #[must_use]
+    pub fn new(path: PathBuf, board: String, cfg: Config) -> Self {
+        let modified = fs::metadata(&path).and_then(|x| x.modified()).ok();
+        ConfigWatch {
+            path,
+            board,
+            poll_interval: Some(Duration::from_secs(1)),
+            started: cfg.clone(),
+            current: cfg,
+            modified,
+            last_poll: None,
+        }
+    }
+
+    /// Whether the file has been modified since it was last read; only looks once every
+    /// `poll_interval`.
+    pub fn changed(&mut self, now: Instant) -> bool {
+        let Some(interval) = self.poll_interval else {
+            return false;
+        };
+        if self
+            .last_poll
+            .is_some_and(|last| now.duration_since(last) < interval)
+        {
+            return false;
+        }
+        self.last_poll = Some(now);
+        let modified = fs::metadata(&self.path).and_then(|x| x.modified()).ok();
+        modified.is_some() && modified != self.modified
+    }
+
+    /// Read the file again and apply it to the running lock (see `apply`).
+    /// # Errors
+    /// Returns a message if the file can't be read, parsed or used, in which case nothing was
+    /// changed
+    pub fn reload(
+        &mut self,
+        interf: &mut Interferometer,
+        comms: &mut InterfComms,
+        parking: &mut Parking,
+    ) -> Result<ReloadReport, String> {
+        self.modified = fs::metadata(&self.path).and_then(|x| x.modified()).ok();
+        let text = fs::read_to_string(&self.path)
+            .map_err(|e| format!("failed to read {}: {e}", self.path.display()))?;
+        let (new, warnings) = Config::parse(&text).map_err(|e| e.to_string())?;
+        let mut report = apply(
+            &self.started,
+            &self.current,
+            &new,
+            &self.board,
+            interf,
+            comms,
+            parking,
+        )?;
+        report.warnings = warnings;
+        self.current = new;
+        Ok(report)
+    }
+
+    /// Write the live parameters back to the file (see `config_export::save`). If the file was
+    /// up to date with the lock, it's taken as the current config, so that saving doesn't trigger
+    /// a reload; otherwise the next reload still picks up whatever else was edited in it.
+    /// # Errors
+    /// Returns a message if the file can't be read, exported to, backed up or written
+    pub fn save(&mut self, interf: &Interferometer) -> Result<Exported, String> {
+        let modified = fs::metadata(&self.path).and_then(|x| x.modified()).ok();
+        let out = config_export::save(&self.path, interf, &self.board)?;
+        if modified.is_some() && modified == self.modified {
+            if let Ok((cfg, _)) = Config::parse(&out.text) {
+                self.current = cfg;
+                self.modified = fs::metadata(&self.path).and_then(|x| x.modified()).ok();
+            }
+        }
+        Ok(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config_model::minimal_config;
+
+    #[test]
+    fn safe_changes_applied_disruptive_ones_refused() {
+        let board = "rp-1";
+        let text = minimal_config();
+        let cfg: Config = text.parse().unwrap();
+        let mut interf = configs::interferometer_from_config(&cfg, board).unwrap();
+        let mut comms = InterfComms::new().unwrap();
+        let mut parking = configs::parking_from_config(&cfg, board).unwrap();
+        interf.slave_lock.enable();
+        for _ in 0..10 {
+            interf.slave_lock.do_pid(0.1);
+        }
+        let integral_term = interf.slave_lock.gain_I * interf.slave_lock.integral();
+
+        // the board section comes last, so the input gain goes into it
+        let edited = format!("{text}ch_2_input_gain = \"HV\"\n")
+            .replacen("gain_i = 0.003", "gain_i = 0.006", 2)
+            .replace("[general]\n", "[general]\nlogs_publish_freq_cycles = 64\n")
+            .replace("piezo_settle_time_ms = 50.0", "piezo_settle_time_ms = 20.0")
+            .replace("decimation_factor = 16", "decimation_factor = 8");
+        let new: Config = edited.parse().unwrap();
+        let report = apply(
+            &cfg,
+            &cfg,
+            &new,
+            board,
+            &mut interf,
+            &mut comms,
+            &mut parking,
+        )
+        .unwrap();
+
+        assert!(report
+            .applied
+            .iter()
+            .any(|x| x.starts_with("slave lock gain_i")));
+        assert!((interf.slave_lock.gain_I - 0.006).abs() < 1e-9);
+        // bumpless: the integral term carries on where it was
+        let new_term = interf.slave_lock.gain_I * interf.slave_lock.integral();
+        assert!((new_term - integral_term).abs() < 1e-9);
+        assert!(comms.should_publish_logs(64));
+
+        assert_eq!(report.scheduled.len(), 1, "{report}");
+        assert_eq!(interf.ramp_setup.piezo_settle_time_us(), 20_000);
+        assert!(interf.ramp_setup.take_pending_apply());
+
+        assert_eq!(report.needs_restart.len(), 2, "{report}");
+        assert_eq!(interf.ramp_setup.decimation(), 16);
+
+        // nothing left to do, but the restart is still needed
+        let report = apply(
+            &cfg,
+            &new,
+            &new,
+            board,
+            &mut interf,
+            &mut comms,
+            &mut parking,
+        )
+        .unwrap();
+        assert!(report.applied.is_empty() && report.scheduled.is_empty());
+        assert_eq!(report.needs_restart.len(), 2, "{report}");
+    }
+}
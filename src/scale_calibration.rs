@@ -0,0 +1,81 @@
+#![warn(clippy::pedantic)]
+#![allow(clippy::cast_precision_loss, clippy::module_name_repetitions)]
+
+use std::f32::consts::PI;
+
+/// Measures the piezo scale factor from the reference laser's fringes. Since the reference
+/// wavelength is known, the fitted fringe frequency `f` (in radians per sample) on a ramp of
+/// amplitude `A` gives back the scale factor `s = f * 16384 * wavelength / (2 pi A)`, inverting
+/// `Laser::set_wavelength`.
+#[derive(Debug)]
+pub struct ScaleCalibration {
+    target: usize,
+    freqs: Vec<f32>,
+}
+
+/// Outcome of a finished calibration run.
+#[derive(Debug)]
+pub struct ScaleCalibrationResult {
+    pub scale_factor: f32,
+    pub uncertainty: f32,
+    pub n: usize,
+}
+
+impl ScaleCalibration {
+    /// Calibrate using the fits of `n_cycles` cycles.
+    #[must_use]
+    pub fn new(n_cycles: usize) -> Self {
+        ScaleCalibration {
+            target: n_cycles.max(2),
+            freqs: Vec::with_capacity(n_cycles.max(2)),
+        }
+    }
+
+    #[must_use]
+    pub fn progress(&self) -> (usize, usize) {
+        (self.freqs.len(), self.target)
+    }
+
+    /// Add one fitted fringe frequency; once enough have been collected, returns the result.
+    pub fn push(
+        &mut self,
+        fringe_freq: f32,
+        wavelength_nm: f32,
+        amplitude_volts: f32,
+    ) -> Option<ScaleCalibrationResult> {
+        if fringe_freq.is_finite() {
+            self.freqs.push(fringe_freq.abs());
+        }
+        if self.freqs.len() < self.target {
+            return None;
+        }
+        let n = self.freqs.len() as f32;
+        let mean = self.freqs.iter().sum::<f32>() / n;
+        let variance = self.freqs.iter().map(|f| (f - mean).powi(2)).sum::<f32>() / (n - 1.0);
+        let to_scale = 16384.0 * wavelength_nm / (2.0 * PI * amplitude_volts);
+        Some(ScaleCalibrationResult {
+            scale_factor: mean * to_scale,
+            // standard error of the mean
+            uncertainty: (variance / n).sqrt() * to_scale,
+            n: self.freqs.len(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::laser::Laser;
+
+    #[test]
+    fn inverts_set_wavelength() {
+        let mut laser = Laser::new(4).unwrap();
+        laser.set_wavelength(1550.0, 3474.9, 1.2);
+        let mut cal = ScaleCalibration::new(3);
+        assert!(cal.push(laser.fringe_freq(), 1550.0, 1.2).is_none());
+        assert!(cal.push(laser.fringe_freq(), 1550.0, 1.2).is_none());
+        let result = cal.push(laser.fringe_freq(), 1550.0, 1.2).unwrap();
+        assert!((result.scale_factor - 3474.9).abs() < 0.01);
+        assert!(result.uncertainty < 0.01);
+    }
+}
@@ -0,0 +1,403 @@
+#![warn(clippy::pedantic)]
+#![allow(
+    clippy::cast_possible_truncation,
+    clippy::cast_possible_wrap,
+    clippy::cast_sign_loss,
+    clippy::cast_precision_loss,
+    clippy::module_name_repetitions
+)]
+
+use std::fmt;
+use std::str::Split;
+use std::time::{Duration, Instant};
+
+use super::command::{arg, CommandError, CommandResult};
+
+/// The waits that make up an acquisition cycle.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    /// (master) waiting for the slaves to pull the ready-to-acquire pin low
+    Handshake,
+    /// waiting for the scope to trigger on the start of the ramp
+    Trigger,
+    /// waiting for the rising segment of the ramp to finish
+    Rise,
+    /// waiting for the falling segment of the ramp to finish, when it's fit too
+    Falling,
+    /// waiting out the piezo settle time at the end of the cycle
+    Settle,
+    /// how late we came out of each of the deadline waits above
+    Overshoot,
+}
+
+const N_PHASES: usize = 6;
+
+impl Phase {
+    pub const ALL: [Phase; N_PHASES] = [
+        Phase::Handshake,
+        Phase::Trigger,
+        Phase::Rise,
+        Phase::Falling,
+        Phase::Settle,
+        Phase::Overshoot,
+    ];
+}
+
+impl std::str::FromStr for Phase {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "HANDSHAKE" => Ok(Phase::Handshake),
+            "TRIGGER" => Ok(Phase::Trigger),
+            "RISE" => Ok(Phase::Rise),
+            "FALLING" => Ok(Phase::Falling),
+            "SETTLE" => Ok(Phase::Settle),
+            "OVERSHOOT" => Ok(Phase::Overshoot),
+            _ => Err(()),
+        }
+    }
+}
+
+impl fmt::Display for Phase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Phase::Handshake => "HANDSHAKE",
+            Phase::Trigger => "TRIGGER",
+            Phase::Rise => "RISE",
+            Phase::Falling => "FALLING",
+            Phase::Settle => "SETTLE",
+            Phase::Overshoot => "OVERSHOOT",
+        };
+        write!(f, "{name}")
+    }
+}
+
+/// Histogram of durations with power-of-two bins: bin 0 counts durations under 1 us, and bin `k`
+/// counts durations in [2^(k-1), 2^k) us, with the last bin also holding anything longer.
+#[derive(Debug, Clone)]
+pub struct TimingHistogram {
+    bins: [u64; 25],
+    count: u64,
+    total: Duration,
+    max: Duration,
+}
+
+impl TimingHistogram {
+    #[must_use]
+    pub fn new() -> Self {
+        TimingHistogram {
+            bins: [0; 25],
+            count: 0,
+            total: Duration::ZERO,
+            max: Duration::ZERO,
+        }
+    }
+
+    pub fn record(&mut self, duration: Duration) {
+        let us = duration.as_micros();
+        let bin = if us == 0 {
+            0
+        } else {
+            (u128::BITS - us.leading_zeros()) as usize
+        };
+        self.bins[bin.min(self.bins.len() - 1)] += 1;
+        self.count += 1;
+        self.total += duration;
+        self.max = self.max.max(duration);
+    }
+
+    pub fn reset(&mut self) {
+        *self = TimingHistogram::new();
+    }
+
+    #[must_use]
+    pub fn bins(&self) -> &[u64] {
+        &self.bins
+    }
+
+    #[must_use]
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    #[must_use]
+    pub fn mean(&self) -> Duration {
+        match u32::try_from(self.count) {
+            Ok(0) => Duration::ZERO,
+            Ok(n) => self.total / n,
+            Err(_) => Duration::from_secs_f64(self.total.as_secs_f64() / self.count as f64),
+        }
+    }
+
+    #[must_use]
+    pub fn max(&self) -> Duration {
+        self.max
+    }
+}
+
+impl Default for TimingHistogram {
+    fn default() -> Self {
+        TimingHistogram::new()
+    }
+}
+
+impl fmt::Display for TimingHistogram {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "n = {}, mean {} us, max {} us",
+            self.count,
+            self.mean().as_micros(),
+            self.max.as_micros()
+        )
+    }
+}
+
+/// A wait on the hardware that didn't finish in time.
+#[derive(Debug, Clone, Copy)]
+pub struct Timeout {
+    pub phase: Phase,
+    pub waited: Duration,
+}
+
+impl fmt::Display for Timeout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "timed out after {} ms waiting on {}",
+            self.waited.as_millis(),
+            self.phase
+        )
+    }
+}
+
+/// Times the waits of the acquisition cycle without burning a CPU core on them: waits for a
+/// deadline sleep on `clock_nanosleep` until `spin` before it, then busy-wait the rest of the way
+/// for precision; waits on hardware state sleep `poll_interval` between polls, and give up after
+/// `handshake_timeout` or `trigger_timeout`. The time spent in every wait is recorded in a
+/// histogram per `Phase`.
+#[derive(Debug)]
+pub struct Scheduler {
+    pub spin: Duration,
+    pub poll_interval: Duration,
+    pub handshake_timeout: Duration,
+    pub trigger_timeout: Duration,
+    histograms: [TimingHistogram; N_PHASES],
+}
+
+impl Scheduler {
+    #[must_use]
+    pub fn new() -> Self {
+        Scheduler {
+            spin: Duration::from_micros(100),
+            poll_interval: Duration::from_micros(20),
+            handshake_timeout: Duration::from_secs(1),
+            trigger_timeout: Duration::from_secs(1),
+            histograms: Default::default(),
+        }
+    }
+
+    /// How long a poll on the hardware in `phase` may take. The deadline waits can't time out, so
+    /// for those this is `None`.
+    #[must_use]
+    pub fn timeout(&self, phase: Phase) -> Option<Duration> {
+        match phase {
+            Phase::Handshake => Some(self.handshake_timeout),
+            Phase::Trigger => Some(self.trigger_timeout),
+            Phase::Rise | Phase::Falling | Phase::Settle | Phase::Overshoot => None,
+        }
+    }
+
+    fn timeout_mut(&mut self, phase: Phase) -> Option<&mut Duration> {
+        match phase {
+            Phase::Handshake => Some(&mut self.handshake_timeout),
+            Phase::Trigger => Some(&mut self.trigger_timeout),
+            Phase::Rise | Phase::Falling | Phase::Settle | Phase::Overshoot => None,
+        }
+    }
+
+    #[must_use]
+    pub fn histogram(&self, phase: Phase) -> &TimingHistogram {
+        &self.histograms[phase as usize]
+    }
+
+    pub fn reset_histograms(&mut self) {
+        for hist in &mut self.histograms {
+            hist.reset();
+        }
+    }
+
+    /// Wait until `deadline`, recording the wait under `phase` and how late we woke up under
+    /// `Phase::Overshoot`.
+    pub fn wait_until(&mut self, phase: Phase, deadline: Instant) {
+        let start = Instant::now();
+        if let Some(early) = deadline.checked_sub(self.spin) {
+            sleep_until(early);
+        }
+        while Instant::now() < deadline {
+            std::hint::spin_loop();
+        }
+        let end = Instant::now();
+        self.histograms[phase as usize].record(end.saturating_duration_since(start));
+        self.histograms[Phase::Overshoot as usize].record(end.saturating_duration_since(deadline));
+    }
+
+    /// Poll `ready` until it returns true, and return the time at which it did.
+    /// # Errors
+    /// Returns a `Timeout` if `ready` didn't return true within `self.timeout(phase)`
+    pub fn poll<F: FnMut() -> bool>(
+        &mut self,
+        phase: Phase,
+        mut ready: F,
+    ) -> Result<Instant, Timeout> {
+        let start = Instant::now();
+        let deadline = self.timeout(phase).and_then(|t| start.checked_add(t));
+        loop {
+            let now = Instant::now();
+            if ready() {
+                self.histograms[phase as usize].record(now.saturating_duration_since(start));
+                return Ok(now);
+            }
+            match deadline {
+                Some(deadline) if now >= deadline => {
+                    return Err(Timeout {
+                        phase,
+                        waited: now.saturating_duration_since(start),
+                    });
+                }
+                Some(deadline) => sleep_until((now + self.poll_interval).min(deadline)),
+                None => sleep_until(now + self.poll_interval),
+            }
+        }
+    }
+
+    /// Takes a split over a string command, parses the command, executes the command, and returns
+    /// a string
+    /// # Errors
+    /// In case of an invalid command (or inability to parse a command), returns a `CommandError`
+    pub fn process_command(&mut self, cmd: Split<'_, char>) -> CommandResult {
+        let resp = match cmd.collect::<Vec<&str>>()[..] {
+            ["SUMMARY", "GET"] => Phase::ALL
+                .iter()
+                .map(|phase| format!("{phase}: {}", self.histogram(*phase)))
+                .collect::<Vec<String>>()
+                .join("; "),
+            ["HISTOGRAM", "GET", phase] => {
+                format!("{:?}", self.histogram(arg::<Phase>(phase)?).bins())
+            }
+            ["RESET"] => {
+                self.reset_histograms();
+                String::new()
+            }
+            ["SPIN", "SET", x] => {
+                self.spin = Duration::from_micros(arg::<u64>(x)?);
+                String::new()
+            }
+            ["SPIN", "GET"] => self.spin.as_micros().to_string(),
+            ["POLL_INTERVAL", "SET", x] => {
+                self.poll_interval = Duration::from_micros(arg::<u64>(x)?);
+                String::new()
+            }
+            ["POLL_INTERVAL", "GET"] => self.poll_interval.as_micros().to_string(),
+            ["TIMEOUT", "SET", phase, x] => {
+                *self
+                    .timeout_mut(arg::<Phase>(phase)?)
+                    .ok_or_else(|| no_timeout(phase))? = Duration::from_millis(arg::<u64>(x)?);
+                String::new()
+            }
+            ["TIMEOUT", "GET", phase] => self
+                .timeout(arg::<Phase>(phase)?)
+                .ok_or_else(|| no_timeout(phase))?
+                .as_millis()
+                .to_string(),
+            _ => Err(CommandError::Unknown)?,
+        };
+        Ok(resp)
+    }
+}
+
+fn no_timeout(phase: &str) -> CommandError {
+    CommandError::BadArgument(format!("'{phase}' (a phase without a timeout)"))
+}
+
+impl Default for Scheduler {
+    fn default() -> Self {
+        Scheduler::new()
+    }
+}
+
+/// Sleep until `deadline` on the monotonic clock (which is what `Instant` uses on Linux). Using an
+/// absolute deadline means an interrupted sleep can simply be restarted without drifting.
+fn sleep_until(deadline: Instant) {
+    let remaining = deadline.saturating_duration_since(Instant::now());
+    if remaining.is_zero() {
+        return;
+    }
+    let mut ts = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    // SAFETY: `ts` is a valid timespec for the duration of the call
+    if unsafe { libc::clock_gettime(libc::CLOCK_MONOTONIC, std::ptr::addr_of_mut!(ts)) } != 0 {
+        std::thread::sleep(remaining);
+        return;
+    }
+    let nsec = ts.tv_nsec as u64 + u64::from(remaining.subsec_nanos());
+    ts.tv_sec += (remaining.as_secs() + nsec / 1_000_000_000) as libc::time_t;
+    ts.tv_nsec = (nsec % 1_000_000_000) as libc::c_long;
+    loop {
+        // SAFETY: `ts` is a valid timespec, and with TIMER_ABSTIME the remainder isn't written
+        let ret = unsafe {
+            libc::clock_nanosleep(
+                libc::CLOCK_MONOTONIC,
+                libc::TIMER_ABSTIME,
+                std::ptr::addr_of!(ts),
+                std::ptr::null_mut(),
+            )
+        };
+        if ret != libc::EINTR {
+            break;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn waits_and_timeouts() {
+        let mut sched = Scheduler::new();
+        let deadline = Instant::now() + Duration::from_millis(2);
+        sched.wait_until(Phase::Rise, deadline);
+        assert!(Instant::now() >= deadline);
+        assert_eq!(sched.histogram(Phase::Rise).count(), 1);
+        assert_eq!(
+            sched.histogram(Phase::Rise).bins()[..11]
+                .iter()
+                .sum::<u64>(),
+            0
+        );
+
+        sched.trigger_timeout = Duration::from_millis(5);
+        let mut polls = 0;
+        let err = sched
+            .poll(Phase::Trigger, || {
+                polls += 1;
+                false
+            })
+            .unwrap_err();
+        assert!(err.waited >= Duration::from_millis(5));
+        assert!(polls > 1);
+        assert_eq!(sched.histogram(Phase::Trigger).count(), 0);
+
+        let mut polls = 0;
+        assert!(sched
+            .poll(Phase::Trigger, || {
+                polls += 1;
+                polls == 3
+            })
+            .is_ok());
+        assert_eq!(sched.histogram(Phase::Trigger).count(), 1);
+    }
+}
@@ -0,0 +1,271 @@
+#![warn(clippy::pedantic)]
+#![allow(clippy::module_name_repetitions)]
+
+use std::fmt;
+use std::str::Split;
+use std::time::Instant;
+
+use librp_sys::analog;
+
+use super::command::{arg, CommandError, CommandResult};
+
+/// Signal used to judge whether the slave laser is still injection locked to its seed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SeedSource {
+    /// Amplitude of the fitted fringes on the slave laser's input channel
+    FringeContrast,
+    /// Voltage on one of the slow analog inputs, e.g. a monitor photodiode
+    AnalogIn(analog::Pin),
+}
+
+impl std::str::FromStr for SeedSource {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "contrast" | "CONTRAST" => Ok(SeedSource::FringeContrast),
+            _ => match s.parse::<analog::Pin>()? {
+                pin if !pin.is_output() => Ok(SeedSource::AnalogIn(pin)),
+                _ => Err(()),
+            },
+        }
+    }
+}
+
+/// Actuator stepped by the monitor while trying to recover injection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SeedOutput {
+    /// The slave laser's (fast) DC output channel, i.e. the same output the slave servo drives
+    DcChannel,
+    /// One of the slow analog outputs
+    SlowAnalog(analog::Pin),
+}
+
+impl std::str::FromStr for SeedOutput {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "dc" | "DC" => Ok(SeedOutput::DcChannel),
+            _ => match s.parse::<analog::Pin>()? {
+                pin if pin.is_output() => Ok(SeedOutput::SlowAnalog(pin)),
+                _ => Err(()),
+            },
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum SeedState {
+    Disabled,
+    Injected,
+    Recovering { started: Instant, steps: u32 },
+    TimedOut,
+}
+impl fmt::Display for SeedState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SeedState::Disabled => write!(f, "Disabled"),
+            SeedState::Injected => write!(f, "Injected"),
+            SeedState::Recovering { steps, .. } => write!(f, "Recovering ({steps} steps)"),
+            SeedState::TimedOut => write!(f, "TimedOut"),
+        }
+    }
+}
+
+/// Result of a single `SeedMonitor::update`: the voltage step to apply to the configured output,
+/// and a status message if the monitor changed state.
+#[derive(Debug, Default)]
+pub struct SeedUpdate {
+    pub step_v: f32,
+    pub event: Option<String>,
+}
+
+/// Watches the injection signal of a seeded slave laser, and when it drops below
+/// `threshold_volts` steps the control output by `adjustment_size_volts` once every
+/// `loop_cycle_sec` until the signal recovers or `timeout_sec` elapses. Once timed out, the
+/// monitor stops adjusting the output until the signal recovers on its own or it is reset.
+#[derive(Debug)]
+pub struct SeedMonitor {
+    pub timeout_sec: f32,
+    pub loop_cycle_sec: f32,
+    pub threshold_volts: f32,
+    pub adjustment_size_volts: f32,
+    pub source: SeedSource,
+    pub output: SeedOutput,
+    state: SeedState,
+    last_step: Option<Instant>,
+    last_signal: f32,
+}
+
+impl SeedMonitor {
+    #[must_use]
+    pub fn new(
+        timeout_sec: f32,
+        loop_cycle_sec: f32,
+        threshold_volts: f32,
+        adjustment_size_volts: f32,
+    ) -> Self {
+        SeedMonitor {
+            timeout_sec,
+            loop_cycle_sec,
+            threshold_volts,
+            adjustment_size_volts,
+            source: SeedSource::FringeContrast,
+            output: SeedOutput::DcChannel,
+            state: SeedState::Injected,
+            last_step: None,
+            last_signal: f32::NAN,
+        }
+    }
+
+    #[inline]
+    #[must_use]
+    pub fn state(&self) -> &SeedState {
+        &self.state
+    }
+
+    /// True while the monitor is actively stepping the output, i.e. while the slave servo's
+    /// error signal shouldn't be trusted.
+    #[inline]
+    #[must_use]
+    pub fn is_recovering(&self) -> bool {
+        matches!(self.state, SeedState::Recovering { .. })
+    }
+
+    pub fn enable(&mut self) {
+        if let SeedState::Disabled = self.state {
+            self.reset();
+        }
+    }
+
+    pub fn disable(&mut self) {
+        self.state = SeedState::Disabled;
+    }
+
+    pub fn reset(&mut self) {
+        self.state = SeedState::Injected;
+        self.last_step = None;
+    }
+
+    pub fn update(&mut self, now: Instant, signal: f32) -> SeedUpdate {
+        self.last_signal = signal;
+        let injected = signal >= self.threshold_volts;
+        let mut out = SeedUpdate::default();
+        match self.state {
+            SeedState::Disabled => {}
+            SeedState::Injected => {
+                if !injected {
+                    self.state = SeedState::Recovering {
+                        started: now,
+                        steps: 0,
+                    };
+                    self.last_step = None;
+                    out.event = Some(format!(
+                        "injection lost (signal {signal} < threshold {}); attempting recovery",
+                        self.threshold_volts
+                    ));
+                }
+            }
+            SeedState::Recovering { started, steps } => {
+                if injected {
+                    self.state = SeedState::Injected;
+                    out.event = Some(format!("injection recovered after {steps} steps"));
+                } else if now.duration_since(started).as_secs_f32() > self.timeout_sec {
+                    self.state = SeedState::TimedOut;
+                    out.event = Some(format!(
+                        "failed to recover injection within {} s after {steps} steps",
+                        self.timeout_sec
+                    ));
+                } else if self.step_due(now) {
+                    self.last_step = Some(now);
+                    self.state = SeedState::Recovering {
+                        started,
+                        steps: steps + 1,
+                    };
+                    out.step_v = self.adjustment_size_volts;
+                }
+            }
+            SeedState::TimedOut => {
+                if injected {
+                    self.state = SeedState::Injected;
+                    out.event = Some("injection recovered".to_string());
+                }
+            }
+        }
+        out
+    }
+
+    fn step_due(&self, now: Instant) -> bool {
+        match self.last_step {
+            Some(t) => now.duration_since(t).as_secs_f32() >= self.loop_cycle_sec,
+            None => true,
+        }
+    }
+
+    /// Takes a split over a string command, parses the command, executes the command, and returns
+    /// a string
+    /// # Errors
+    /// In case of an invalid command (or inability to parse a command), returns a `CommandError`
+    pub fn process_command(&mut self, cmd: Split<'_, char>) -> CommandResult {
+        let resp = match cmd.collect::<Vec<&str>>()[..] {
+            ["STATUS", "GET"] => format!("{}, signal {}", self.state, self.last_signal),
+            ["MODE", "SET", "ENABLE"] => {
+                self.enable();
+                String::new()
+            }
+            ["MODE", "SET", "DISABLE"] => {
+                self.disable();
+                String::new()
+            }
+            ["RESET"] => {
+                self.reset();
+                String::new()
+            }
+            ["THRESHOLD", "SET", x] => {
+                self.threshold_volts = arg::<f32>(x)?;
+                String::new()
+            }
+            ["THRESHOLD", "GET"] => self.threshold_volts.to_string(),
+            ["STEP_SIZE", "SET", x] => {
+                self.adjustment_size_volts = arg::<f32>(x)?;
+                String::new()
+            }
+            ["STEP_SIZE", "GET"] => self.adjustment_size_volts.to_string(),
+            ["TIMEOUT", "SET", x] => {
+                self.timeout_sec = arg::<f32>(x)?;
+                String::new()
+            }
+            ["TIMEOUT", "GET"] => self.timeout_sec.to_string(),
+            _ => Err(CommandError::Unknown)?,
+        };
+        Ok(resp)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn recovers_and_times_out() {
+        let mut mon = SeedMonitor::new(1.0, 0.25, 0.5, 0.1);
+        let t0 = Instant::now();
+        assert!(mon.update(t0, 1.0).step_v.abs() < 1e-6);
+
+        let lost = mon.update(t0, 0.1);
+        assert!(lost.event.is_some());
+        assert!(mon.is_recovering());
+        assert!((mon.update(t0, 0.1).step_v - 0.1).abs() < 1e-6);
+        // still within the loop cycle, so no new step
+        assert!(mon.update(t0 + Duration::from_millis(100), 0.1).step_v.abs() < 1e-6);
+        assert!((mon.update(t0 + Duration::from_millis(300), 0.1).step_v - 0.1).abs() < 1e-6);
+
+        let timed_out = mon.update(t0 + Duration::from_millis(1100), 0.1);
+        assert!(timed_out.event.is_some());
+        assert!(matches!(mon.state(), SeedState::TimedOut));
+        assert!(mon.update(t0 + Duration::from_millis(1500), 0.1).step_v.abs() < 1e-6);
+
+        assert!(mon.update(t0 + Duration::from_secs(2), 0.9).event.is_some());
+        assert!(matches!(mon.state(), SeedState::Injected));
+    }
+}
@@ -0,0 +1,71 @@
+#![warn(clippy::pedantic)]
+#![allow(
+    clippy::cast_possible_truncation,
+    clippy::cast_sign_loss,
+    clippy::cast_precision_loss,
+    clippy::module_name_repetitions
+)]
+
+use std::time::Duration;
+
+/// Where to leave the outputs when shutting down, and how fast to get them there; piezos and
+/// laser current inputs don't appreciate a sudden jump. An output with no parking voltage is left
+/// where the servo last put it.
+#[derive(Debug)]
+pub struct Parking {
+    pub ref_park_v: Option<f32>,
+    pub slave_park_v: Option<f32>,
+    pub slew_rate_v_per_s: f32,
+    pub step_interval: Duration,
+}
+
+impl Parking {
+    #[must_use]
+    pub fn new() -> Self {
+        Parking {
+            ref_park_v: None,
+            slave_park_v: None,
+            slew_rate_v_per_s: 0.5,
+            step_interval: Duration::from_millis(10),
+        }
+    }
+
+    /// The successive values to set an output to, to slew it from `from` to `to`, ending exactly
+    /// at `to`.
+    #[must_use]
+    pub fn slew_steps(&self, from: f32, to: f32) -> Vec<f32> {
+        let max_step = self.slew_rate_v_per_s * self.step_interval.as_secs_f32();
+        if max_step.is_nan() || max_step <= 0.0 || !from.is_finite() {
+            return vec![to];
+        }
+        let n = ((to - from).abs() / max_step).ceil().max(1.0) as usize;
+        (1..=n)
+            .map(|i| from + (to - from) * i as f32 / n as f32)
+            .collect()
+    }
+}
+
+impl Default for Parking {
+    fn default() -> Self {
+        Parking::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn slew_limited() {
+        let mut parking = Parking::new();
+        parking.slew_rate_v_per_s = 1.0;
+        parking.step_interval = Duration::from_millis(250);
+        assert_eq!(parking.slew_steps(1.0, 0.0), vec![0.75, 0.5, 0.25, 0.0]);
+        // split into equal steps no larger than the limit, ending exactly on the target
+        let steps = parking.slew_steps(0.0, 0.6);
+        assert_eq!(steps.len(), 3);
+        assert!(steps.windows(2).all(|w| (w[1] - w[0]).abs() <= 0.25));
+        assert!((steps[2] - 0.6).abs() < 1e-6);
+        assert_eq!(parking.slew_steps(0.3, 0.3), vec![0.3]);
+    }
+}
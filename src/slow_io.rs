@@ -0,0 +1,98 @@
+#![warn(clippy::pedantic)]
+#![allow(clippy::module_name_repetitions)]
+
+use librp_sys::analog;
+
+use super::ring_buffer::DyadicRingBuffer;
+
+/// Second, slow actuator for a laser, driven from one of the Red Pitaya's slow analog outputs.
+/// The fast servo output has limited range, so each cycle this nudges the slow output in
+/// proportion to how far the fast output has wandered from `center_v`, letting the slow actuator
+/// (e.g. temperature or diode current) take over long-term drifts.
+#[derive(Debug)]
+pub struct SlowFeedback {
+    pub pin: analog::Pin,
+    pub gain: f32, // slow volts per cycle, per volt of fast-output excursion
+    pub max_step_v: f32,
+    pub center_v: f32,
+    min_v: f32,
+    max_v: f32,
+    value_v: f32,
+}
+
+impl SlowFeedback {
+    #[must_use]
+    pub fn new(pin: analog::Pin, gain: f32, max_step_v: f32, center_v: f32) -> Self {
+        SlowFeedback {
+            pin,
+            gain,
+            max_step_v,
+            center_v,
+            min_v: 0.0,
+            max_v: 1.8,
+            value_v: 0.0,
+        }
+    }
+
+    /// Set the allowed output range, as reported by `AnalogPin::get_range`.
+    pub fn set_range(&mut self, min_v: f32, max_v: f32) {
+        self.min_v = min_v;
+        self.max_v = max_v;
+        self.value_v = self.value_v.clamp(min_v, max_v);
+    }
+
+    /// Given the current fast output offset, returns the new slow output voltage to be written.
+    pub fn update(&mut self, fast_offset_v: f32) -> f32 {
+        let step = (self.gain * (fast_offset_v - self.center_v))
+            .clamp(-self.max_step_v, self.max_step_v);
+        if !step.is_nan() {
+            self.value_v = (self.value_v + step).clamp(self.min_v, self.max_v);
+        }
+        self.value_v
+    }
+
+    #[inline]
+    #[must_use]
+    pub fn value_v(&self) -> f32 {
+        self.value_v
+    }
+
+    /// Overwrite the tracked output value, e.g. with a value read back from the hardware.
+    #[inline]
+    pub fn set_value_v(&mut self, volts: f32) {
+        self.value_v = volts.clamp(self.min_v, self.max_v);
+    }
+}
+
+/// A slow analog input sampled once per cycle and published alongside the phase logs.
+#[derive(Debug)]
+pub struct MonitorInput {
+    pub pin: analog::Pin,
+    pub log: DyadicRingBuffer<f32>,
+}
+
+impl MonitorInput {
+    #[must_use]
+    pub fn new(pin: analog::Pin, n: usize) -> Option<Self> {
+        Some(MonitorInput {
+            pin,
+            log: DyadicRingBuffer::new(n)?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn slow_feedback_limits() {
+        let mut slow = SlowFeedback::new(analog::Pin::AOUT0, 0.1, 0.05, 2.5);
+        slow.set_range(0.0, 0.12);
+        assert!((slow.update(2.5) - 0.0).abs() < 1e-6);
+        assert!((slow.update(3.0) - 0.05).abs() < 1e-6);
+        assert!((slow.update(5.0) - 0.1).abs() < 1e-6);
+        assert!((slow.update(5.0) - 0.12).abs() < 1e-6);
+        assert!((slow.update(2.0) - 0.07).abs() < 1e-6);
+    }
+}
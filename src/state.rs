@@ -0,0 +1,537 @@
+#![warn(clippy::pedantic)]
+#![allow(
+    clippy::cast_possible_truncation,
+    clippy::cast_precision_loss,
+    clippy::module_name_repetitions
+)]
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use chrono::Local;
+
+use super::interferometer::Interferometer;
+use super::laser::Laser;
+use super::lock::{Mode, Servo};
+
+/// Where lock state snapshots are kept, and how often the running state is saved, so that a
+/// restarted board picks up its servos, outputs and fit guesses where it left off rather than
+/// needing to be relocked by hand.
+#[derive(Debug)]
+pub struct StateStore {
+    // named snapshots (`STATE:SAVE:<name>`) go here, as `state_<name>.toml`
+    pub dir: PathBuf,
+    // the snapshot saved periodically and on shutdown, and restored at startup
+    pub file: Option<PathBuf>,
+    pub save_interval: Option<Duration>,
+    pub restore_on_startup: bool,
+    last_save: Option<Instant>,
+    pending_offsets: Option<(Option<f32>, Option<f32>)>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ServoState {
+    pub gain_p: f32,
+    pub gain_i: f32,
+    pub gain_d: f32,
+    pub integral_decay_rate: f32,
+    pub integral: f32,
+    pub setpoint: f32,
+    pub enabled: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LaserState {
+    pub wavelength_nm: f32,
+    pub fit_coefficients: [f32; 4],
+}
+
+/// Everything about a running lock that isn't in the config file, or that may have been changed
+/// since it was read: the servos, the fit guesses, the ramp settings that get adjusted on the fly
+/// (scale factor calibration, amplitude control), and the output voltages.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Snapshot {
+    pub ref_lock: ServoState,
+    pub slave_lock: ServoState,
+    pub ref_laser: LaserState,
+    pub slave_laser: LaserState,
+    pub ramp_amplitude_v: f32,
+    pub piezo_scale_factor: f32,
+    pub ref_offset_v: Option<f32>,
+    pub slave_offset_v: Option<f32>,
+}
+
+fn float(table: &toml::Value, section: &str, key: &str) -> Result<f32, String> {
+    match table.get(section).and_then(|x| x.get(key)) {
+        Some(toml::Value::Float(x)) => Ok(*x as f32),
+        Some(toml::Value::Integer(x)) => Ok(*x as f32),
+        Some(_) => Err(format!("invalid value for {section}:{key}")),
+        None => Err(format!("missing {section}:{key}")),
+    }
+}
+
+fn float_table<'a>(pairs: impl IntoIterator<Item = (&'a str, f32)>) -> toml::value::Table {
+    pairs
+        .into_iter()
+        .map(|(key, value)| (key.to_string(), toml::Value::Float(f64::from(value))))
+        .collect()
+}
+
+impl ServoState {
+    #[must_use]
+    pub fn capture(servo: &Servo) -> Self {
+        ServoState {
+            gain_p: servo.gain_P,
+            gain_i: servo.gain_I,
+            gain_d: servo.gain_D,
+            integral_decay_rate: servo.alpha_I(),
+            integral: servo.integral(),
+            setpoint: servo.setpoint(),
+            enabled: matches!(servo.mode, Mode::Enabled),
+        }
+    }
+
+    /// Put `servo` back in this state. The order matters: changing the setpoint or enabling the
+    /// servo clears the integral, so it goes last.
+    pub fn apply(&self, servo: &mut Servo) {
+        servo.gain_P = self.gain_p;
+        servo.gain_I = self.gain_i;
+        servo.gain_D = self.gain_d;
+        servo.set_alpha_I(self.integral_decay_rate);
+        servo.set_setpoint(self.setpoint);
+        if self.enabled {
+            servo.enable();
+        } else {
+            servo.disable();
+        }
+        servo.set_integral(self.integral);
+    }
+
+    fn to_toml(self) -> toml::Value {
+        let mut table = float_table([
+            ("gain_p", self.gain_p),
+            ("gain_i", self.gain_i),
+            ("gain_d", self.gain_d),
+            ("integral_decay_rate", self.integral_decay_rate),
+            ("integral", self.integral),
+            ("setpoint", self.setpoint),
+        ]);
+        table.insert("enabled".to_string(), toml::Value::Boolean(self.enabled));
+        toml::Value::Table(table)
+    }
+
+    fn from_toml(state: &toml::Value, section: &str) -> Result<Self, String> {
+        Ok(ServoState {
+            gain_p: float(state, section, "gain_p")?,
+            gain_i: float(state, section, "gain_i")?,
+            gain_d: float(state, section, "gain_d")?,
+            integral_decay_rate: float(state, section, "integral_decay_rate")?,
+            integral: float(state, section, "integral")?,
+            setpoint: float(state, section, "setpoint")?,
+            enabled: state
+                .get(section)
+                .and_then(|x| x.get("enabled"))
+                .and_then(toml::Value::as_bool)
+                .ok_or_else(|| format!("missing {section}:enabled"))?,
+        })
+    }
+
+    fn check(&self, section: &str) -> Result<(), String> {
+        let values = [
+            self.gain_p,
+            self.gain_i,
+            self.gain_d,
+            self.integral,
+            self.setpoint,
+        ];
+        if !values.iter().all(|x| x.is_finite()) {
+            return Err(format!("non-finite value in {section}"));
+        }
+        if !(0.0..=1.0).contains(&self.integral_decay_rate) {
+            return Err(format!(
+                "{section}:integral_decay_rate {} outside [0, 1]",
+                self.integral_decay_rate
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl LaserState {
+    #[must_use]
+    pub fn capture(laser: &Laser) -> Self {
+        LaserState {
+            wavelength_nm: laser.wavelength_nm(),
+            fit_coefficients: laser.fit_coefficients,
+        }
+    }
+
+    fn to_toml(self) -> toml::Value {
+        let mut table = float_table([("wavelength_nm", self.wavelength_nm)]);
+        table.insert(
+            "fit_coefficients".to_string(),
+            toml::Value::Array(
+                self.fit_coefficients
+                    .iter()
+                    .map(|x| toml::Value::Float(f64::from(*x)))
+                    .collect(),
+            ),
+        );
+        toml::Value::Table(table)
+    }
+
+    fn from_toml(state: &toml::Value, section: &str) -> Result<Self, String> {
+        let coeffs = state
+            .get(section)
+            .and_then(|x| x.get("fit_coefficients"))
+            .and_then(toml::Value::as_array)
+            .ok_or_else(|| format!("missing {section}:fit_coefficients"))?;
+        let coeffs = coeffs
+            .iter()
+            .map(|x| x.as_float().map(|x| x as f32))
+            .collect::<Option<Vec<f32>>>()
+            .and_then(|x| <[f32; 4]>::try_from(x).ok())
+            .ok_or_else(|| format!("{section}:fit_coefficients should be 4 numbers"))?;
+        Ok(LaserState {
+            wavelength_nm: float(state, section, "wavelength_nm")?,
+            fit_coefficients: coeffs,
+        })
+    }
+
+    /// A snapshot is only any use for the laser it was taken of: the wavelength may have been
+    /// refined by the wavelength meter since the config was written, but not by more than a
+    /// percent, and the fitted fringe frequency should be near what the wavelength implies.
+    fn check(
+        &self,
+        section: &str,
+        laser: &Laser,
+        scale_factor: f32,
+        ampl: f32,
+    ) -> Result<(), String> {
+        if !self.wavelength_nm.is_finite()
+            || (self.wavelength_nm - laser.wavelength_nm()).abs() > 0.01 * laser.wavelength_nm()
+        {
+            return Err(format!(
+                "{section}:wavelength_nm {} doesn't match the configured {} nm",
+                self.wavelength_nm,
+                laser.wavelength_nm()
+            ));
+        }
+        let mut expected = Laser::new(1).expect("1 is a valid log size");
+        expected.set_wavelength(self.wavelength_nm, scale_factor, ampl);
+        let freq = self.fit_coefficients[1];
+        if !self.fit_coefficients.iter().all(|x| x.is_finite())
+            || (freq.abs() - expected.fringe_freq()).abs() > 0.5 * expected.fringe_freq()
+        {
+            return Err(format!(
+                "{section}:fit_coefficients {:?} are far from the expected fringe frequency {}",
+                self.fit_coefficients,
+                expected.fringe_freq()
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl Snapshot {
+    /// The output offsets are passed in, since the channels belong to the controller; `None`
+    /// for a channel this board doesn't drive.
+    #[must_use]
+    pub fn capture(
+        interf: &Interferometer,
+        ref_offset_v: Option<f32>,
+        slave_offset_v: Option<f32>,
+    ) -> Self {
+        Snapshot {
+            ref_lock: ServoState::capture(&interf.ref_lock),
+            slave_lock: ServoState::capture(&interf.slave_lock),
+            ref_laser: LaserState::capture(&interf.ref_laser),
+            slave_laser: LaserState::capture(&interf.slave_laser),
+            ramp_amplitude_v: interf.ramp_setup.amplitude_volts,
+            piezo_scale_factor: interf.ramp_setup.piezo_scale_factor,
+            ref_offset_v,
+            slave_offset_v,
+        }
+    }
+
+    #[must_use]
+    pub fn to_toml(&self) -> toml::Value {
+        let mut table = toml::value::Table::new();
+        table.insert(
+            "saved".to_string(),
+            toml::Value::String(Local::now().to_rfc3339()),
+        );
+        table.insert(
+            "ramp".to_string(),
+            toml::Value::Table(float_table([
+                ("amplitude_v", self.ramp_amplitude_v),
+                ("piezo_scale_factor", self.piezo_scale_factor),
+            ])),
+        );
+        table.insert(
+            "outputs".to_string(),
+            toml::Value::Table(float_table(
+                [
+                    ("ref_offset_v", self.ref_offset_v),
+                    ("slave_offset_v", self.slave_offset_v),
+                ]
+                .into_iter()
+                .filter_map(|(key, value)| Some((key, value?))),
+            )),
+        );
+        table.insert("ref_lock".to_string(), self.ref_lock.to_toml());
+        table.insert("slave_lock".to_string(), self.slave_lock.to_toml());
+        table.insert("ref_laser".to_string(), self.ref_laser.to_toml());
+        table.insert("slave_laser".to_string(), self.slave_laser.to_toml());
+        toml::Value::Table(table)
+    }
+
+    /// # Errors
+    /// Returns an error message naming the first missing or malformed entry
+    pub fn from_toml(state: &toml::Value) -> Result<Self, String> {
+        let offset = |key| match state.get("outputs").and_then(|x| x.get(key)) {
+            Some(_) => float(state, "outputs", key).map(Some),
+            None => Ok(None),
+        };
+        Ok(Snapshot {
+            ref_lock: ServoState::from_toml(state, "ref_lock")?,
+            slave_lock: ServoState::from_toml(state, "slave_lock")?,
+            ref_laser: LaserState::from_toml(state, "ref_laser")?,
+            slave_laser: LaserState::from_toml(state, "slave_laser")?,
+            ramp_amplitude_v: float(state, "ramp", "amplitude_v")?,
+            piezo_scale_factor: float(state, "ramp", "piezo_scale_factor")?,
+            ref_offset_v: offset("ref_offset_v")?,
+            slave_offset_v: offset("slave_offset_v")?,
+        })
+    }
+
+    /// Check that this snapshot makes sense for `interf` as configured, before touching anything.
+    /// # Errors
+    /// Returns a message describing the first problem found
+    pub fn check(&self, interf: &Interferometer) -> Result<(), String> {
+        self.ref_lock.check("ref_lock")?;
+        self.slave_lock.check("slave_lock")?;
+        let positive = |x: f32| x.is_finite() && x > 0.0;
+        if !positive(self.ramp_amplitude_v) || !positive(self.piezo_scale_factor) {
+            return Err(format!(
+                "invalid ramp amplitude {} V or scale factor {} nm/V",
+                self.ramp_amplitude_v, self.piezo_scale_factor
+            ));
+        }
+        self.ref_laser.check(
+            "ref_laser",
+            &interf.ref_laser,
+            self.piezo_scale_factor,
+            self.ramp_amplitude_v,
+        )?;
+        self.slave_laser.check(
+            "slave_laser",
+            &interf.slave_laser,
+            self.piezo_scale_factor,
+            self.ramp_amplitude_v,
+        )?;
+        if [self.ref_offset_v, self.slave_offset_v]
+            .into_iter()
+            .flatten()
+            .any(|x| !x.is_finite())
+        {
+            return Err("non-finite output offset".to_string());
+        }
+        Ok(())
+    }
+
+    /// Check the snapshot, then restore it into `interf`. The output offsets can't be restored
+    /// from here; they're returned for whoever owns the channels (and are clamped to the
+    /// configured output ranges when set).
+    /// # Errors
+    /// If the snapshot fails its sanity checks, returns why, and leaves `interf` untouched
+    #[allow(clippy::float_cmp)]
+    pub fn restore(
+        &self,
+        interf: &mut Interferometer,
+    ) -> Result<(Option<f32>, Option<f32>), String> {
+        self.check(interf)?;
+        interf.ramp_setup.piezo_scale_factor = self.piezo_scale_factor;
+        if interf.ramp_setup.amplitude_volts != self.ramp_amplitude_v {
+            interf
+                .ramp_setup
+                .amplitude(self.ramp_amplitude_v)
+                .request_apply();
+        }
+        for (laser, state) in [
+            (&mut interf.ref_laser, &self.ref_laser),
+            (&mut interf.slave_laser, &self.slave_laser),
+        ] {
+            laser.set_wavelength(
+                state.wavelength_nm,
+                self.piezo_scale_factor,
+                self.ramp_amplitude_v,
+            );
+            laser.fit_coefficients = state.fit_coefficients;
+        }
+        self.ref_lock.apply(&mut interf.ref_lock);
+        self.slave_lock.apply(&mut interf.slave_lock);
+        Ok((self.ref_offset_v, self.slave_offset_v))
+    }
+}
+
+impl StateStore {
+    #[must_use]
+    pub fn new(dir: PathBuf) -> Self {
+        StateStore {
+            dir,
+            file: None,
+            save_interval: None,
+            restore_on_startup: false,
+            last_save: None,
+            pending_offsets: None,
+        }
+    }
+
+    /// The file a named snapshot is kept in. Names are limited to letters, digits, `-` and `_`,
+    /// so they can't point outside `dir`.
+    #[must_use]
+    pub fn named_path(&self, name: &str) -> Option<PathBuf> {
+        if name.is_empty()
+            || !name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        {
+            return None;
+        }
+        Some(self.dir.join(format!("state_{name}.toml")))
+    }
+
+    /// Names of the snapshots saved in `dir`, sorted.
+    #[must_use]
+    pub fn list(&self) -> Vec<String> {
+        let mut names = fs::read_dir(&self.dir)
+            .map(|dir| {
+                dir.filter_map(|entry| {
+                    let name = entry.ok()?.file_name().into_string().ok()?;
+                    Some(
+                        name.strip_prefix("state_")?
+                            .strip_suffix(".toml")?
+                            .to_string(),
+                    )
+                })
+                .collect::<Vec<String>>()
+            })
+            .unwrap_or_default();
+        names.sort();
+        names
+    }
+
+    /// Whether the periodic snapshot is due. Starts the clock on the first call, so the first
+    /// snapshot comes one interval after the lock starts running.
+    pub fn save_due(&mut self, now: Instant) -> bool {
+        if self.file.is_none() {
+            return false;
+        }
+        let due = match (self.save_interval, self.last_save) {
+            (None, _) => return false,
+            (Some(interval), Some(last)) => now.saturating_duration_since(last) >= interval,
+            (Some(_), None) => false,
+        };
+        if due || self.last_save.is_none() {
+            self.last_save = Some(now);
+        }
+        due
+    }
+
+    /// Output offsets restored by `STATE:LOAD`, for the controller to apply between cycles.
+    pub fn set_pending_offsets(&mut self, offsets: (Option<f32>, Option<f32>)) {
+        self.pending_offsets = Some(offsets);
+    }
+
+    pub fn take_pending_offsets(&mut self) -> Option<(Option<f32>, Option<f32>)> {
+        self.pending_offsets.take()
+    }
+}
+
+/// The previous version of a file at `path` is kept as `<path>.bak`.
+#[must_use]
+pub fn backup_path(path: &Path) -> PathBuf {
+    let mut out = path.as_os_str().to_owned();
+    out.push(".bak");
+    PathBuf::from(out)
+}
+
+/// Replace the file at `path` with `text`, by way of a temporary file, so that losing power
+/// halfway through doesn't leave a truncated file behind. With `keep_backup`, any existing file
+/// is copied to its `backup_path` first.
+/// # Errors
+/// Returns a message if the backup or the file can't be written
+pub fn write_atomic(path: &Path, text: &str, keep_backup: bool) -> Result<(), String> {
+    if keep_backup && path.exists() {
+        let backup = backup_path(path);
+        fs::copy(path, &backup)
+            .map_err(|e| format!("failed to write backup {}: {e}", backup.display()))?;
+    }
+    let tmp = path.with_extension("toml.tmp");
+    fs::write(&tmp, text)
+        .and_then(|()| fs::rename(&tmp, path))
+        .map_err(|e| format!("failed to write {}: {e}", path.display()))
+}
+
+/// Write a snapshot to `path` (see `write_atomic`).
+/// # Errors
+/// Returns a message if the file can't be written
+pub fn save(path: &Path, snapshot: &Snapshot) -> Result<(), String> {
+    let text = toml::to_string(&snapshot.to_toml()).map_err(|e| e.to_string())?;
+    write_atomic(path, &text, false)
+}
+
+/// # Errors
+/// Returns a message if the file can't be read or doesn't hold a snapshot
+pub fn load(path: &Path) -> Result<Snapshot, String> {
+    let text =
+        fs::read_to_string(path).map_err(|e| format!("failed to read {}: {e}", path.display()))?;
+    let value = toml::from_str(&text).map_err(|e| format!("in {}: {e}", path.display()))?;
+    Snapshot::from_toml(&value).map_err(|e| format!("in {}: {e}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn interferometer() -> Interferometer {
+        let mut interf = Interferometer::new().unwrap();
+        let (scale, ampl) = (
+            interf.ramp_setup.piezo_scale_factor,
+            interf.ramp_setup.amplitude_volts,
+        );
+        interf.ref_laser.set_wavelength(1550.0, scale, ampl);
+        interf.slave_laser.set_wavelength(1114.0, scale, ampl);
+        for laser in [&mut interf.ref_laser, &mut interf.slave_laser] {
+            laser.fit_coefficients = [0.1, laser.fringe_freq(), 0.2, 0.3];
+        }
+        interf
+    }
+
+    #[test]
+    fn round_trip_and_sanity_checks() {
+        let mut interf = interferometer();
+        interf.slave_lock.gain_P = 0.5;
+        interf.slave_lock.set_setpoint(0.25);
+        interf.slave_lock.enable();
+        interf.slave_lock.set_integral(1.5);
+        let snapshot = Snapshot::capture(&interf, None, Some(2.0));
+
+        let text = toml::to_string(&snapshot.to_toml()).unwrap();
+        let parsed = Snapshot::from_toml(&toml::from_str(&text).unwrap()).unwrap();
+        assert_eq!(parsed, snapshot);
+
+        let mut fresh = interferometer();
+        assert_eq!(parsed.restore(&mut fresh).unwrap(), (None, Some(2.0)));
+        assert_eq!(ServoState::capture(&fresh.slave_lock), snapshot.slave_lock);
+
+        // a snapshot of some other laser is refused, and doesn't change anything
+        let mut other = interferometer();
+        other.slave_laser.set_wavelength(780.0, 3000.0, 1.0);
+        let before = ServoState::capture(&other.slave_lock);
+        assert!(parsed.restore(&mut other).is_err());
+        assert_eq!(ServoState::capture(&other.slave_lock), before);
+    }
+}
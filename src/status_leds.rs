@@ -0,0 +1,179 @@
+#![warn(clippy::pedantic)]
+#![allow(clippy::module_name_repetitions)]
+
+use std::time::Instant;
+
+use librp_sys::dpin::{DigitalPin, Pin, PinState};
+
+/// Condition that can be shown on one of the board's LEDs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Indicator {
+    RefLock,
+    SlaveLock,
+    Master,
+    Comms,
+    FitFailure,
+    Saturation,
+}
+
+impl std::str::FromStr for Indicator {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ref_lock" => Ok(Indicator::RefLock),
+            "slave_lock" => Ok(Indicator::SlaveLock),
+            "master" => Ok(Indicator::Master),
+            "comms" => Ok(Indicator::Comms),
+            "fit_failure" => Ok(Indicator::FitFailure),
+            "saturation" => Ok(Indicator::Saturation),
+            _ => Err(()),
+        }
+    }
+}
+
+/// How an LED is driven while its indicator is active; inactive LEDs are always off.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pattern {
+    Solid,
+    BlinkSlow, // 1 Hz
+    BlinkFast, // 4 Hz
+}
+
+impl std::str::FromStr for Pattern {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "solid" => Ok(Pattern::Solid),
+            "blink_slow" => Ok(Pattern::BlinkSlow),
+            "blink_fast" => Ok(Pattern::BlinkFast),
+            _ => Err(()),
+        }
+    }
+}
+
+impl Pattern {
+    fn is_on(self, elapsed_ms: u128) -> bool {
+        match self {
+            Pattern::Solid => true,
+            Pattern::BlinkSlow => elapsed_ms % 1000 < 500,
+            Pattern::BlinkFast => elapsed_ms % 250 < 125,
+        }
+    }
+}
+
+/// Snapshot of the conditions shown by the LEDs, filled in by the main loop once per cycle.
+#[derive(Debug, Default)]
+#[allow(clippy::struct_excessive_bools)]
+pub struct StatusFlags {
+    pub ref_locked: bool,
+    pub slave_locked: bool,
+    pub is_master: bool,
+    pub comms_active: bool,
+    pub fit_failed: bool,
+    pub saturated: bool,
+}
+
+impl StatusFlags {
+    fn get(&self, indicator: Indicator) -> bool {
+        match indicator {
+            Indicator::RefLock => self.ref_locked,
+            Indicator::SlaveLock => self.slave_locked,
+            Indicator::Master => self.is_master,
+            Indicator::Comms => self.comms_active,
+            Indicator::FitFailure => self.fit_failed,
+            Indicator::Saturation => self.saturated,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct LedRule {
+    pin: Pin,
+    indicator: Indicator,
+    pattern: Pattern,
+    last_state: Option<bool>,
+}
+
+/// Maps status conditions onto the `LED_0`..`LED_7` pins. `update` only touches a pin when its
+/// state actually changes, so it is cheap enough to call every cycle.
+#[derive(Debug)]
+pub struct StatusLeds {
+    rules: Vec<LedRule>,
+    start: Instant,
+}
+
+impl StatusLeds {
+    #[must_use]
+    pub fn new() -> Self {
+        StatusLeds {
+            rules: Vec::new(),
+            start: Instant::now(),
+        }
+    }
+
+    /// Assign `indicator` to the LED `pin`. Returns `Err(())` if `pin` isn't one of the LEDs.
+    /// # Errors
+    /// Returns `Err(())` if `pin` is not one of `LED_0`..`LED_7`
+    #[allow(clippy::result_unit_err)]
+    pub fn assign(&mut self, pin: Pin, indicator: Indicator, pattern: Pattern) -> Result<(), ()> {
+        if pin as u32 > Pin::LED_7 as u32 {
+            return Err(());
+        }
+        self.rules.push(LedRule {
+            pin,
+            indicator,
+            pattern,
+            last_state: None,
+        });
+        Ok(())
+    }
+
+    pub fn update(&mut self, dpin: &mut DigitalPin, flags: &StatusFlags) {
+        let elapsed_ms = self.start.elapsed().as_millis();
+        for rule in &mut self.rules {
+            let on = flags.get(rule.indicator) && rule.pattern.is_on(elapsed_ms);
+            if rule.last_state != Some(on) {
+                let state = if on { PinState::High } else { PinState::Low };
+                if dpin.set_state(rule.pin, state).is_ok() {
+                    rule.last_state = Some(on);
+                }
+            }
+        }
+    }
+
+    /// Turn off every assigned LED.
+    pub fn clear(&mut self, dpin: &mut DigitalPin) {
+        for rule in &mut self.rules {
+            let _ = dpin.set_state(rule.pin, PinState::Low);
+            rule.last_state = Some(false);
+        }
+    }
+}
+
+impl Default for StatusLeds {
+    fn default() -> Self {
+        StatusLeds::new()
+    }
+}
+
+/// True if an output with the given offset and amplitude is pinned against either end of its
+/// allowed range.
+#[must_use]
+pub fn is_saturated(offset_v: f32, ampl_v: f32, min_v: f32, max_v: f32) -> bool {
+    let margin = 1.0e-3 * (max_v - min_v).abs();
+    offset_v - ampl_v <= min_v + margin || offset_v + ampl_v >= max_v - margin
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn saturation() {
+        assert!(!is_saturated(2.5, 0.0, 0.0, 5.0));
+        assert!(is_saturated(5.0, 0.0, 0.0, 5.0));
+        assert!(is_saturated(0.0, 0.0, 0.0, 5.0));
+        assert!(is_saturated(4.0, 1.0, 0.0, 5.0));
+        assert!(!is_saturated(3.9, 1.0, 0.0, 5.0));
+    }
+}
@@ -0,0 +1,178 @@
+#![warn(clippy::pedantic)]
+#![allow(clippy::cast_precision_loss, clippy::module_name_repetitions)]
+
+use std::str::Split;
+
+use super::command::{arg, CommandError, CommandResult};
+
+/// Measures the slave laser's wavelength against the reference laser. Both lasers see the same
+/// cavity length sweep, so their fitted fringe frequencies are inversely proportional to their
+/// wavelengths, and `wavelength_slave = wavelength_ref * freq_ref / freq_slave`, independently of
+/// the ramp amplitude and the piezo scale factor. The ratio is averaged over blocks of `cycles`
+/// cycles, each of which gives an estimate.
+#[derive(Debug)]
+pub struct WavelengthMeter {
+    pub enabled: bool,
+    pub cycles: usize,
+    // whether to hand the estimate on to `Laser::set_wavelength`, i.e. to the fit guesses
+    pub feedback: bool,
+    // a jump between consecutive estimates larger than this is reported as a mode hop
+    pub mode_hop_threshold_nm: f32,
+    ratios: Vec<f32>,
+    last: Option<WavelengthEstimate>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct WavelengthEstimate {
+    pub wavelength_nm: f32,
+    // standard error of the mean over the block; doesn't include the reference's own uncertainty
+    pub uncertainty_nm: f32,
+    pub n: usize,
+    pub mode_hop: bool,
+}
+
+impl WavelengthMeter {
+    #[must_use]
+    pub fn new(cycles: usize) -> Self {
+        WavelengthMeter {
+            enabled: false,
+            cycles: cycles.max(2),
+            feedback: false,
+            mode_hop_threshold_nm: 0.0,
+            ratios: Vec::with_capacity(cycles.max(2)),
+            last: None,
+        }
+    }
+
+    #[must_use]
+    pub fn last(&self) -> Option<WavelengthEstimate> {
+        self.last
+    }
+
+    pub fn reset(&mut self) {
+        self.ratios.clear();
+        self.last = None;
+    }
+
+    /// Add the fitted fringe frequencies of one cycle. At the end of each block of `cycles`
+    /// cycles, returns the new estimate of the slave wavelength.
+    pub fn push(
+        &mut self,
+        ref_freq: f32,
+        slave_freq: f32,
+        ref_wavelength_nm: f32,
+    ) -> Option<WavelengthEstimate> {
+        if !self.enabled {
+            return None;
+        }
+        let ratio = ref_freq.abs() / slave_freq.abs();
+        if ratio.is_finite() && ratio > 0.0 {
+            self.ratios.push(ratio);
+        }
+        if self.ratios.len() < self.cycles {
+            return None;
+        }
+        let n = self.ratios.len() as f32;
+        let mean = self.ratios.iter().sum::<f32>() / n;
+        let variance = self.ratios.iter().map(|r| (r - mean).powi(2)).sum::<f32>() / (n - 1.0);
+        let wavelength_nm = ref_wavelength_nm * mean;
+        let mode_hop = self.mode_hop_threshold_nm > 0.0
+            && self.last.is_some_and(|last| {
+                (wavelength_nm - last.wavelength_nm).abs() > self.mode_hop_threshold_nm
+            });
+        let estimate = WavelengthEstimate {
+            wavelength_nm,
+            uncertainty_nm: ref_wavelength_nm * (variance / n).sqrt(),
+            n: self.ratios.len(),
+            mode_hop,
+        };
+        self.ratios.clear();
+        self.last = Some(estimate);
+        Some(estimate)
+    }
+
+    /// Takes a split over a string command, parses the command, executes the command, and returns
+    /// a string
+    /// # Errors
+    /// In case of an invalid command (or inability to parse a command), returns a `CommandError`
+    pub fn process_command(&mut self, cmd: Split<'_, char>) -> CommandResult {
+        let resp = match cmd.collect::<Vec<&str>>()[..] {
+            ["MODE", "SET", "ENABLE"] => {
+                self.enabled = true;
+                String::new()
+            }
+            ["MODE", "SET", "DISABLE"] => {
+                self.enabled = false;
+                self.ratios.clear();
+                String::new()
+            }
+            ["MODE", "GET"] => if self.enabled { "ENABLED" } else { "DISABLED" }.to_string(),
+            ["GET"] => match self.last {
+                Some(x) => format!("{},{},{}", x.wavelength_nm, x.uncertainty_nm, x.n),
+                None => "NONE".to_string(),
+            },
+            ["RESET"] => {
+                self.reset();
+                String::new()
+            }
+            ["CYCLES", "SET", x] => {
+                self.cycles = arg::<usize>(x)?.max(2);
+                String::new()
+            }
+            ["CYCLES", "GET"] => self.cycles.to_string(),
+            ["FEEDBACK", "SET", "ENABLE"] => {
+                self.feedback = true;
+                String::new()
+            }
+            ["FEEDBACK", "SET", "DISABLE"] => {
+                self.feedback = false;
+                String::new()
+            }
+            ["FEEDBACK", "GET"] => if self.feedback { "ENABLED" } else { "DISABLED" }.to_string(),
+            ["MODE_HOP_THRESHOLD", "SET", x] => {
+                self.mode_hop_threshold_nm = arg::<f32>(x)?.abs();
+                String::new()
+            }
+            ["MODE_HOP_THRESHOLD", "GET"] => self.mode_hop_threshold_nm.to_string(),
+            _ => Err(CommandError::Unknown)?,
+        };
+        Ok(resp)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::laser::Laser;
+
+    #[test]
+    fn recovers_wavelength() {
+        let mut reference = Laser::new(4).unwrap();
+        let mut slave = Laser::new(4).unwrap();
+        reference.set_wavelength(1550.0, 3474.9, 1.2);
+        slave.set_wavelength(1114.0, 3474.9, 1.2);
+
+        let mut meter = WavelengthMeter::new(2);
+        meter.mode_hop_threshold_nm = 0.05;
+        assert!(meter
+            .push(reference.fringe_freq(), slave.fringe_freq(), 1550.0)
+            .is_none());
+        meter.enabled = true;
+        assert!(meter
+            .push(reference.fringe_freq(), slave.fringe_freq(), 1550.0)
+            .is_none());
+        let est = meter
+            .push(reference.fringe_freq(), slave.fringe_freq(), 1550.0)
+            .unwrap();
+        assert!((est.wavelength_nm - 1114.0).abs() < 0.01);
+        assert!(!est.mode_hop);
+
+        slave.set_wavelength(1114.2, 3474.9, 1.2);
+        meter.push(reference.fringe_freq(), slave.fringe_freq(), 1550.0);
+        let est = meter
+            .push(reference.fringe_freq(), slave.fringe_freq(), 1550.0)
+            .unwrap();
+        assert!((est.wavelength_nm - 1114.2).abs() < 0.01);
+        assert!(est.mode_hop);
+    }
+}